@@ -0,0 +1,216 @@
+/// Computes an Adler-32 checksum (RFC 1950) incrementally.
+///
+/// # Example
+/// ```
+/// # use stdex::algorithms::Adler32;
+/// let mut adler = Adler32::new();
+/// adler.update(b"Wiki");
+/// adler.update(b"pedia");
+/// assert_eq!(adler.value(), 0x11e60398);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+const ADLER_MODULUS: u32 = 65521;
+
+// the most bytes which can be summed before `b` might overflow a u32
+const ADLER_MAX_RUN: usize = 5552;
+
+impl Adler32 {
+    pub fn new() -> Adler32 {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    /// Resumes a checksum from a value returned by `value()`.
+    pub fn from_value(value: u32) -> Adler32 {
+        Adler32 { a: value & 0xffff, b: value >> 16 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for run in data.chunks(ADLER_MAX_RUN) {
+            for &byte in run {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_MODULUS;
+            self.b %= ADLER_MODULUS;
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Adler32 {
+        Adler32::new()
+    }
+}
+
+/// Computes the Adler-32 checksum of `data`.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.value()
+}
+
+/// Computes a CRC-32 checksum (ISO 3309, as used by gzip, zip and PNG)
+/// incrementally.
+///
+/// # Example
+/// ```
+/// # use stdex::algorithms::Crc32;
+/// let mut crc = Crc32::new();
+/// crc.update(b"The quick brown fox ");
+/// crc.update(b"jumps over the lazy dog");
+/// assert_eq!(crc.value(), 0x414fa339);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+const CRC_POLYNOMIAL: u32 = 0xedb88320;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC_POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { crc: 0xffffffff }
+    }
+
+    /// Resumes a checksum from a value returned by `value()`.
+    pub fn from_value(value: u32) -> Crc32 {
+        Crc32 { crc: !value }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.crc;
+        for &byte in data {
+            crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.crc = crc;
+    }
+
+    pub fn value(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+/// Computes the CRC-32 checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
+
+/// Multiplies two polynomials modulo the CRC-32 polynomial. Bits are in the
+/// reflected order the checksum uses, so the top bit is x^0.
+fn multiply_mod(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    for bit in (0..32).rev() {
+        if a & (1 << bit) != 0 {
+            product ^= b;
+        }
+        b = if b & 1 != 0 { (b >> 1) ^ CRC_POLYNOMIAL } else { b >> 1 };
+    }
+    product
+}
+
+/// Computes the CRC-32 checksum of two pieces of data joined together, from
+/// the checksum of each and the length of the second, in O(log n) time.
+///
+/// This lets pieces of data be checksummed separately, such as on several
+/// threads.
+///
+/// # Example
+/// ```
+/// # use stdex::algorithms::{crc32, crc32_combine};
+/// let first = crc32(b"The quick brown fox ");
+/// let second = crc32(b"jumps over the lazy dog");
+/// assert_eq!(crc32_combine(first, second, 23), 0x414fa339);
+/// ```
+pub fn crc32_combine(first: u32, second: u32, second_len: u64) -> u32 {
+    // appending n bytes multiplies the first checksum by x^(8n); the rest
+    // is the second checksum
+    let mut shift = 1 << 31;
+    let mut square = 1 << (31 - 8);
+    let mut n = second_len;
+    while n != 0 {
+        if n & 1 != 0 {
+            shift = multiply_mod(square, shift);
+        }
+        square = multiply_mod(square, square);
+        n >>= 1;
+    }
+    multiply_mod(shift, first) ^ second
+}
+
+mod tests {
+    #[test]
+    fn test_adler32() {
+        use super::{adler32, Adler32};
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+
+        // long runs of high bytes are where the sums would overflow
+        let data = vec![0xff; 100_000];
+        let mut adler = Adler32::new();
+        for piece in data.chunks(333) {
+            adler.update(piece);
+        }
+        assert_eq!(adler.value(), adler32(&data));
+        assert_eq!(adler.value(), 0x149a302c);
+        assert_eq!(Adler32::from_value(adler.value()).value(), adler.value());
+    }
+
+    #[test]
+    fn test_crc32() {
+        use super::{crc32, Crc32};
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+
+        let mut crc = Crc32::new();
+        crc.update(b"12345");
+        let mut resumed = Crc32::from_value(crc.value());
+        resumed.update(b"6789");
+        assert_eq!(resumed.value(), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_crc32_combine() {
+        use super::{crc32, crc32_combine};
+        let data: Vec<u8> = (0..70_000u32).map(|i| (i * 7 + i / 300) as u8).collect();
+        for &split in [0, 1, 9, 4096, 65_537, 70_000].iter() {
+            let (first, second) = data.split_at(split);
+            let combined = crc32_combine(crc32(first), crc32(second), second.len() as u64);
+            assert_eq!(combined, crc32(&data));
+        }
+    }
+}
@@ -1,229 +1,116 @@
-use std::io::Read;
-use crate::error::{error_if, BoxResult};
-use crate::huffman::{Node, Code, CodeString};
-use crate::io::{BitRead, BitReaderLSB};
-use crate::collections::BitString;
-
-struct Codes {
-    litlen: Vec<Code<u16>>,
-    distance: Vec<Code<u16>>,
+use std::io::{Read, Write};
+use crate::error::BoxResult;
+use crate::io::{DeflateCompressor, DeflateDecompressorError, InflateIntoError, InflateOptions};
+
+/// Compresses `input` with the DEFLATE algorithm, appending the result to
+/// `output`.
+///
+/// This is the one-shot counterpart to `inflate`. For more control, or to
+/// compress a stream, use `stdex::io::DeflateCompressor`.
+///
+/// # Example
+/// ```
+/// # use stdex::algorithms::{deflate, inflate};
+/// let input = b"one fish two fish red fish blue fish";
+/// let mut compressed = Vec::new();
+/// deflate(input, &mut compressed);
+///
+/// let mut output = Vec::new();
+/// inflate(&mut &compressed[..], &mut output).unwrap();
+/// assert_eq!(&output[..], &input[..]);
+/// ```
+pub fn deflate(input: &[u8], output: &mut Vec<u8>) {
+    let mut compressor = DeflateCompressor::new(output);
+    compressor.write_all(input).expect("writing to a Vec cannot fail");
+    compressor.finish().expect("writing to a Vec cannot fail");
 }
 
 /// Decompresses a block of data that was compressed by the DEFLATE algorithm.
 pub fn inflate<R: Read>(reader: &mut R, output: &mut Vec<u8>) -> BoxResult<()> {
-    let mut bitreader = BitReaderLSB::new(reader);
-    loop {
-        let bfinal = bitreader.read_bit()? != 0;
-        match bitreader.read_bits_32(2)? {
-            0 => {
-                read_uncompressed_block(&mut bitreader, output)?;
-            },
-            btype @ 1...2 => {
-                let codes = match btype {
-                    1 => fixed_huffman_codes(),
-                    2 => dynamic_huffman_codes(&mut bitreader)?,
-                    _ => unreachable!(),
-                };
-
-                read_huffman_compressed_block(&codes, &mut bitreader, output)?;
-            },
-            3 => {
-                error_if(true, "bad btype value")?;
-            },
-            _ => unreachable!(),
-        }
-
-        if bfinal { break; }
-    }
-
-    Ok(())
+    inflate_with_options(reader, output, InflateOptions::default())
 }
 
-fn read_uncompressed_block<R: Read>(bitreader: &mut BitReaderLSB<R>,
-output: &mut Vec<u8>) -> Result<(), Box<std::error::Error>> {
-    bitreader.flush_byte();
-    let reader = bitreader.as_read_mut();
-    let len = crate::io::read_u16_le(reader)?;
-    let nlen = crate::io::read_u16_le(reader)?;
-
-    error_if(len != !nlen, "non-matching len/nlen")?;
-
-    let old_len = output.len();
-    let new_len = old_len + len as usize;
-    output.reserve(len as usize);
-    unsafe {
-        output.set_len(new_len);
-    }
-
-    reader.read_exact(&mut output[old_len..new_len])?;    
-
-    Ok(())
+/// Like `inflate`, but fails with `InflateErrorKind::LimitExceeded`
+/// if the data expands past the limits in `options`.
+///
+/// # Example
+/// ```
+/// # use stdex::algorithms::{deflate, inflate_with_options};
+/// # use stdex::io::{DeflateDecompressorError, InflateErrorKind, InflateOptions, Limit};
+/// let mut compressed = Vec::new();
+/// deflate(&[0; 100_000], &mut compressed);
+///
+/// let options = InflateOptions { max_output: Some(65536), ..InflateOptions::default() };
+/// let mut output = Vec::new();
+/// let e = inflate_with_options(&mut &compressed[..], &mut output, options).unwrap_err();
+/// let e = e.downcast_ref::<DeflateDecompressorError>().unwrap();
+/// assert_eq!(e.kind, InflateErrorKind::LimitExceeded(Limit::Output));
+/// ```
+pub fn inflate_with_options<R: Read>(reader: &mut R, output: &mut Vec<u8>,
+options: InflateOptions) -> BoxResult<()> {
+    inflate_impl(reader, output, options, &[])
 }
 
-fn fixed_huffman_codes() -> Codes {
-    let mut litlen = Vec::with_capacity(288);
-
-    let mut bits = 0b00110000;
-    for value in 0..=143 {
-        let code = CodeString::new(8, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
-    }
-
-    let mut bits = 0b110010000;
-    for value in 144..=255 {
-        let code = CodeString::new(9, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
-    }
-
-    let mut bits = 0;
-    for value in 256..=279 {
-        let code = CodeString::new(7, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
-    }
-
-    let mut bits = 0b11000000;
-    for value in 280..=287 {
-        let code = CodeString::new(8, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
-    }
-
-    let mut distance = Vec::new();
-    for value in 0..=31 {
-        let code = CodeString::new(5, value as u32);
-        distance.push(Code { value, code });
-    }
-
-    Codes { litlen, distance }
+/// Like `inflate`, for data which was compressed with a preset dictionary.
+///
+/// # Example
+/// ```
+/// # use stdex::algorithms::inflate_with_dictionary;
+/// # use stdex::io::DeflateCompressor;
+/// # use std::io::Write;
+/// let dictionary = b"GET /index.html HTTP/1.1\r\nHost: ";
+/// let mut compressor = DeflateCompressor::with_dictionary(Vec::new(), dictionary);
+/// compressor.write_all(b"GET /about.html HTTP/1.1\r\nHost: example.com").unwrap();
+/// let compressed = compressor.finish().unwrap();
+///
+/// let mut output = Vec::new();
+/// inflate_with_dictionary(&mut &compressed[..], &mut output, dictionary).unwrap();
+/// assert_eq!(&output[..], &b"GET /about.html HTTP/1.1\r\nHost: example.com"[..]);
+/// ```
+pub fn inflate_with_dictionary<R: Read>(reader: &mut R, output: &mut Vec<u8>,
+dictionary: &[u8]) -> BoxResult<()> {
+    inflate_impl(reader, output, InflateOptions::default(), dictionary)
 }
 
-fn dynamic_huffman_codes<R: Read>(bitreader: &mut BitReaderLSB<R>)
--> Result<Codes, Box<std::error::Error>> {
-    let hlit = bitreader.read_bits_32(5)? as usize + 257;
-    let hdist = bitreader.read_bits_32(5)? as usize + 1;
-    let hclen = bitreader.read_bits_32(4)? as usize + 4;
-
-    const SWIZZLE: [usize;19] =
-        [ 16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15 ];
-
-    let mut code_lengths = [0;19];
-    for i in 0..hclen {
-        code_lengths[SWIZZLE[i]] = bitreader.read_bits_32(3)? as u32;
-    }
-
-    let codes = Code::canonical_from_lengths(0, &code_lengths)?;
-    let tree = Node::from_codes(&codes)?;
-
-    let mut code_lengths = [0;288 + 32];
-    let mut i = 0;
-    let mut previous = 0;
-    while i < hlit + hdist {
-        match tree.read_value(bitreader)? {
-            n @ 0...15 => {
-                code_lengths[i] = n as u32;
-                previous = n;
-                i += 1;
-            },
-            16 => {
-                let repeat_len = bitreader.read_bits_32(2)? + 3;
-                for _ in 0..repeat_len {
-                    code_lengths[i] = previous as u32;
-                    i += 1;
-                }
-            },
-            17 => {
-                let repeat_len = bitreader.read_bits_32(3)? + 3;
-                previous = 0;
-                i += repeat_len as usize;
-            },
-            18 => {
-                let repeat_len = bitreader.read_bits_32(7)? + 11;
-                previous = 0;
-                i += repeat_len as usize;
-            },
-            _ => {
-                error_if(true, "bad code lengths")?;
-                unreachable!();
-            }
-        }
-    }
-
-    let litlen = Code::canonical_from_lengths(0, &code_lengths[..hlit])?;
-    let distance = Code::canonical_from_lengths(0, &code_lengths[hlit..])?;
-
-    Ok(Codes {
-        litlen,
-        distance,
-    })
+/// Decompresses the deflate stream at the start of `input` into `output`,
+/// returning the number of bytes written, like miniz's `tinfl`.
+///
+/// Nothing is allocated: the output is used as the window, and the Huffman
+/// codes are decoded with tables of a fixed size. Anything in `input` after
+/// the end of the stream is ignored.
+///
+/// If the stream doesn't fit, `output` is filled with as much of it as
+/// fits, and `InflateIntoError::OutputTooSmall` is returned.
+///
+/// # Example
+/// ```
+/// # use stdex::algorithms::{deflate, inflate_into};
+/// # use stdex::io::InflateIntoError;
+/// let mut compressed = Vec::new();
+/// deflate(b"one fish two fish red fish blue fish", &mut compressed);
+///
+/// let mut output = [0; 64];
+/// let len = inflate_into(&compressed, &mut output).unwrap();
+/// assert_eq!(&output[..len], &b"one fish two fish red fish blue fish"[..]);
+///
+/// let mut output = [0; 8];
+/// let e = inflate_into(&compressed, &mut output).unwrap_err();
+/// assert_eq!(e, InflateIntoError::OutputTooSmall(8));
+/// assert_eq!(&output, b"one fish");
+/// ```
+pub fn inflate_into(input: &[u8], output: &mut [u8]) -> Result<usize, InflateIntoError> {
+    crate::io::inflate_into(input, output)
 }
 
-fn read_huffman_compressed_block<R: Read>(codes: &Codes,
-bitreader: &mut BitReaderLSB<R>, output: &mut Vec<u8>)
--> Result<(), Box<std::error::Error>> {
-    let litlen_tree = Node::from_codes(&codes.litlen)?;
-    let dist_tree = Node::from_codes(&codes.distance)?;
-
-    loop {
-        match litlen_tree.read_value(bitreader)? {
-            value @ 0...255 => {
-                output.push(value as u8);
-            },
-            256 => break,
-            value @ 257...285 => {
-                read_length_distance_pair(value - 257, &dist_tree, bitreader, output)?;
-            },
-            286...287 => { // error in data
-                error_if(true, "invalid litlen code")?;
-            },
-            _ => unreachable!(), // error in my code
+fn inflate_impl<R: Read>(reader: &mut R, output: &mut Vec<u8>, options: InflateOptions,
+dictionary: &[u8]) -> BoxResult<()> {
+    crate::io::inflate_to_vec(reader, output, options, dictionary).map_err(|e| {
+        // errors in the data come back as themselves, rather than wrapped
+        // up in an io::Error
+        let error = e.get_ref().and_then(|e| e.downcast_ref::<DeflateDecompressorError>()).cloned();
+        match error {
+            Some(error) => Box::new(error) as Box<dyn std::error::Error>,
+            None => Box::new(e),
         }
-    }
-
-    Ok(())
+    })
 }
-
-fn read_length_distance_pair<R: Read>(code : u16, dist_tree: &Node<u16>,
-bitreader: &mut BitReaderLSB<R>, output: &mut Vec<u8>)
--> Result<(), Box<std::error::Error>> {
-    const LENGTH_BASE: [usize;29] = [
-        3,4,5,6,7,8,9,10,11,13,
-        15,17,19,23,27,31,35,43,51,59,
-        67,83,99,115,131,163,195,227,258
-    ];
-
-    const LENGTH_EXTRA: [usize;29] = [
-        0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2,3,3,3,3,4,4,4,4,5,5,5,5,0
-    ];
-
-    const DIST_BASE: [usize;32] = [
-        1,2,3,4,5,7,9,13,17,25,33,49,65,97,129,193,
-        257,385,513,769,1025,1537,2049,3073,4097,6145,8193,12289,16385,24577,0,0
-    ];
-
-    const DIST_EXTRA: [usize;32] = [
-        0,0,0,0,1,1,2,2,3,3,4,4,5,5,6,6,7,7,8,8,9,9,10,10,11,11,12,12,13,13,0,0
-    ];
-
-    let mut len = LENGTH_BASE[code as usize];
-    if LENGTH_EXTRA[code as usize] != 0 {
-        len += bitreader.read_bits_32(LENGTH_EXTRA[code as usize])? as usize;
-    }
-
-    let code = dist_tree.read_value(bitreader)?;
-    let mut dist = DIST_BASE[code as usize];
-    if DIST_EXTRA[code as usize] != 0 {
-        dist += bitreader.read_bits_32(DIST_EXTRA[code as usize])? as usize;
-    }
-
-    let mut from_index = output.len() - dist;
-    for _ in 0..len {
-        let value = output[from_index];
-        output.push(value);
-        from_index += 1;
-    }
-
-    Ok(())
-}
\ No newline at end of file
@@ -0,0 +1,545 @@
+//! Finding LZ77 matches: earlier occurrences of the bytes at a position,
+//! within a sliding window, which an encoder can code as a
+//! `(length, distance)` pair instead of the bytes themselves.
+//!
+//! Positions are absolute offsets into the whole input. Functions taking a
+//! `data` slice also take `base`, the absolute position of `data[0]`, so the
+//! caller is free to discard data more than a window behind, and to append
+//! more as it arrives.
+//!
+//! # Example
+//! ```
+//! # use stdex::algorithms::lz77::{parse, HashChain, ParseOptions, Token};
+//! let data = b"abcabcabcabcx";
+//! let mut finder = HashChain::new(32768, 128, 128);
+//! let mut tokens = Vec::new();
+//! parse(&mut finder, ParseOptions::default(), data, 0, 0, data.len(), &mut tokens);
+//! assert_eq!(tokens, vec![
+//!     Token::Literal(b'a'),
+//!     Token::Literal(b'b'),
+//!     Token::Literal(b'c'),
+//!     Token::Match { length: 9, distance: 3 },
+//!     Token::Literal(b'x'),
+//! ]);
+//! ```
+
+/// The shortest match any finder reports.
+pub const MIN_MATCH: usize = 3;
+
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+/// A literal byte, or a match copying `length` bytes from `distance` bytes
+/// back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+/// Finds earlier occurrences of the bytes at each position.
+///
+/// Every position must be passed to either `find_matches` or `skip`, in
+/// order, from the first position of the input on. Only the positions
+/// passed so far are searched.
+pub trait MatchFinder {
+    /// Finds matches for the bytes at `pos`, no longer than `max_length`,
+    /// and then makes `pos` available to later searches.
+    ///
+    /// Matches are appended to `matches` as `(length, distance)` pairs, each
+    /// longer than the one before, and each at the nearest distance the
+    /// finder found for its length. So for any length from `MIN_MATCH` up to
+    /// the longest, the nearest match found is at the distance of the first
+    /// pair at least that long.
+    fn find_matches(&mut self, data: &[u8], base: usize, pos: usize, max_length: usize,
+        matches: &mut Vec<(usize, usize)>);
+
+    /// Makes `pos` available to later searches without searching for it.
+    fn skip(&mut self, data: &[u8], base: usize, pos: usize);
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = (data[0] as usize) << 10 ^ (data[1] as usize) << 5 ^ data[2] as usize;
+    value & (HASH_SIZE - 1)
+}
+
+/// The hash of the bytes at `index`, if there are enough of them left.
+fn hash_at(data: &[u8], index: usize) -> Option<usize> {
+    if index + MIN_MATCH <= data.len() {
+        Some(hash(&data[index..]))
+    } else {
+        None
+    }
+}
+
+fn match_length(data: &[u8], earlier: usize, later: usize, max_length: usize) -> usize {
+    let mut length = 0;
+    while length < max_length && data[earlier + length] == data[later + length] {
+        length += 1;
+    }
+    length
+}
+
+/// Finds matches by chaining together every earlier position whose first
+/// three bytes hash the same, and comparing against each in turn.
+///
+/// A good balance of speed and thoroughness, tuned by how long the chains
+/// it follows may be.
+pub struct HashChain {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    window_size: usize,
+    max_chain: usize,
+    nice_length: usize,
+}
+
+impl HashChain {
+    /// Matches are found up to `window_size` bytes back, which must be a
+    /// power of two. `max_chain` limits how many candidates are compared for
+    /// each search, and a match of at least `nice_length` ends the search
+    /// early.
+    pub fn new(window_size: usize, max_chain: usize, nice_length: usize) -> HashChain {
+        assert!(window_size.is_power_of_two(), "window size must be a power of two");
+        HashChain {
+            head: vec![0; HASH_SIZE],
+            prev: vec![0; window_size],
+            window_size,
+            max_chain,
+            nice_length,
+        }
+    }
+}
+
+impl MatchFinder for HashChain {
+    fn find_matches(&mut self, data: &[u8], base: usize, pos: usize, max_length: usize,
+    matches: &mut Vec<(usize, usize)>) {
+        let index = pos - base;
+        let h = match hash_at(data, index) {
+            Some(h) => h,
+            None => return,
+        };
+
+        let max_length = std::cmp::min(max_length, data.len() - index);
+        let mut best_length = MIN_MATCH - 1;
+        let mut candidate = self.head[h];
+        let mut chain = self.max_chain;
+        while candidate != 0 && chain > 0 && best_length < max_length {
+            let earlier = candidate - 1;
+            let distance = pos - earlier;
+            if distance > self.window_size || earlier < base {
+                break;
+            }
+
+            let earlier_index = earlier - base;
+            if data[earlier_index + best_length] == data[index + best_length] {
+                let length = match_length(data, earlier_index, index, max_length);
+                if length > best_length {
+                    best_length = length;
+                    matches.push((length, distance));
+                    if length >= self.nice_length {
+                        break;
+                    }
+                }
+            }
+
+            // a stale link points forward, into a newer position which
+            // reused this slot
+            let next = self.prev[earlier & (self.window_size - 1)];
+            if next == 0 || next > earlier {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+
+        self.prev[pos & (self.window_size - 1)] = self.head[h];
+        self.head[h] = pos + 1;
+    }
+
+    fn skip(&mut self, data: &[u8], base: usize, pos: usize) {
+        if let Some(h) = hash_at(data, pos - base) {
+            self.prev[pos & (self.window_size - 1)] = self.head[h];
+            self.head[h] = pos + 1;
+        }
+    }
+}
+
+/// Finds matches by keeping the earlier positions with the same hash in a
+/// binary search tree, ordered by the bytes which follow them.
+///
+/// Each search only visits candidates which share a longer prefix with the
+/// bytes searched for than the ones before, so long matches are found with
+/// far fewer comparisons than a `HashChain` needs. This suits large windows
+/// and thorough parsing.
+pub struct BinaryTree {
+    head: Vec<usize>,
+    // the children of each position's node, smaller then larger, with 0
+    // for none. Holds two windows, so that a position's slot isn't reused
+    // until it's well out of reach
+    children: Vec<usize>,
+    window_size: usize,
+    max_depth: usize,
+    nice_length: usize,
+}
+
+impl BinaryTree {
+    /// Matches are found up to `window_size` bytes back, which must be a
+    /// power of two. `max_depth` limits how many candidates are compared for
+    /// each search. Bytes are only compared up to `nice_length`, and a match
+    /// that long ends the search early.
+    pub fn new(window_size: usize, max_depth: usize, nice_length: usize) -> BinaryTree {
+        assert!(window_size.is_power_of_two(), "window size must be a power of two");
+        BinaryTree {
+            head: vec![0; HASH_SIZE],
+            children: vec![0; 4 * window_size],
+            window_size,
+            max_depth,
+            nice_length,
+        }
+    }
+
+    /// Makes `pos` the root of its tree, splitting the old tree into the
+    /// nodes before and after it. Matches found on the way are reported as
+    /// `find_matches` does, if `matches` is given.
+    fn insert(&mut self, data: &[u8], base: usize, pos: usize, max_length: usize,
+    mut matches: Option<&mut Vec<(usize, usize)>>) {
+        let index = pos - base;
+        let h = match hash_at(data, index) {
+            Some(h) => h,
+            None => return,
+        };
+
+        let mask = 2 * self.window_size - 1;
+        let compare_length = std::cmp::min(self.nice_length, data.len() - index);
+        let mut candidate = self.head[h];
+        self.head[h] = pos + 1;
+
+        // the child slots waiting for the next node found to be smaller and
+        // larger than `pos`, starting with its own, and how many bytes the
+        // nodes bounding each side are known to share with it
+        let mut smaller = 2 * (pos & mask);
+        let mut larger = smaller + 1;
+        let (mut smaller_length, mut larger_length) = (0, 0);
+        let mut best_length = MIN_MATCH - 1;
+        let mut depth = self.max_depth;
+        loop {
+            let reachable = candidate > base && depth != 0
+                && pos - (candidate - 1) <= self.window_size;
+            if !reachable {
+                self.children[smaller] = 0;
+                self.children[larger] = 0;
+                return;
+            }
+
+            let earlier = candidate - 1;
+            let earlier_index = earlier - base;
+            let known = std::cmp::min(smaller_length, larger_length);
+            let length = known + match_length(data, earlier_index + known, index + known,
+                compare_length - known);
+
+            let reported = std::cmp::min(length, max_length);
+            if reported > best_length {
+                best_length = reported;
+                if let Some(ref mut matches) = matches {
+                    matches.push((reported, pos - earlier));
+                }
+            }
+
+            let node = 2 * (earlier & mask);
+            if length == compare_length {
+                // as far as we can tell, `earlier` is the same as `pos`, so
+                // `pos` takes its place in the tree
+                self.children[smaller] = self.children[node];
+                self.children[larger] = self.children[node + 1];
+                return;
+            }
+
+            if data[earlier_index + length] < data[index + length] {
+                // `earlier` and its smaller children go before `pos`, and
+                // its larger children are searched next
+                self.children[smaller] = candidate;
+                smaller = node + 1;
+                smaller_length = length;
+                candidate = self.children[node + 1];
+            } else {
+                self.children[larger] = candidate;
+                larger = node;
+                larger_length = length;
+                candidate = self.children[node];
+            }
+            depth -= 1;
+        }
+    }
+}
+
+impl MatchFinder for BinaryTree {
+    fn find_matches(&mut self, data: &[u8], base: usize, pos: usize, max_length: usize,
+    matches: &mut Vec<(usize, usize)>) {
+        self.insert(data, base, pos, max_length, Some(matches));
+    }
+
+    fn skip(&mut self, data: &[u8], base: usize, pos: usize) {
+        self.insert(data, base, pos, 0, None);
+    }
+}
+
+/// Finds matches by remembering only the last position with each hash.
+///
+/// The fastest finder, and the poorest: it compares against a single
+/// candidate, so finds at most one match.
+pub struct HashTable {
+    head: Vec<usize>,
+    window_size: usize,
+}
+
+impl HashTable {
+    /// Matches are found up to `window_size` bytes back.
+    pub fn new(window_size: usize) -> HashTable {
+        HashTable { head: vec![0; HASH_SIZE], window_size }
+    }
+}
+
+impl MatchFinder for HashTable {
+    fn find_matches(&mut self, data: &[u8], base: usize, pos: usize, max_length: usize,
+    matches: &mut Vec<(usize, usize)>) {
+        let index = pos - base;
+        let h = match hash_at(data, index) {
+            Some(h) => h,
+            None => return,
+        };
+
+        let candidate = self.head[h];
+        self.head[h] = pos + 1;
+        if candidate == 0 || candidate - 1 < base || pos - (candidate - 1) > self.window_size {
+            return;
+        }
+
+        let earlier = candidate - 1;
+        let max_length = std::cmp::min(max_length, data.len() - index);
+        let length = match_length(data, earlier - base, index, max_length);
+        if length >= MIN_MATCH {
+            matches.push((length, pos - earlier));
+        }
+    }
+
+    fn skip(&mut self, data: &[u8], base: usize, pos: usize) {
+        if let Some(h) = hash_at(data, pos - base) {
+            self.head[h] = pos + 1;
+        }
+    }
+}
+
+/// Settings for `parse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Shorter matches are coded as literals instead.
+    pub min_length: usize,
+    /// Longer matches are cut short.
+    pub max_length: usize,
+    /// When a match shorter than this is found, the next position is also
+    /// searched, and if it starts a longer match, a literal is coded instead
+    /// of the first. 0 takes every match as soon as it's found.
+    pub max_lazy: usize,
+}
+
+impl Default for ParseOptions {
+    /// Matches from 3 to 258 bytes long, as in deflate, with lazy matching.
+    fn default() -> ParseOptions {
+        ParseOptions {
+            min_length: MIN_MATCH,
+            max_length: 258,
+            max_lazy: 258,
+        }
+    }
+}
+
+/// The longest match in `matches`, if it's long enough to use.
+fn longest(matches: &[(usize, usize)], min_length: usize) -> Option<(usize, usize)> {
+    matches.last().cloned().filter(|&(length, _)| length >= min_length)
+}
+
+/// Parses the positions `start..end` into literals and matches, appending
+/// them to `tokens`.
+///
+/// `finder` must have been given every position before `start`, and is
+/// given every position in `start..end`. Matches may refer back into
+/// anything in `data` within the window, but never extend past `end`.
+pub fn parse<F: MatchFinder + ?Sized>(finder: &mut F, options: ParseOptions, data: &[u8],
+base: usize, start: usize, end: usize, tokens: &mut Vec<Token>) {
+    let max_length = |pos| std::cmp::min(options.max_length, end - pos);
+    let mut matches = Vec::new();
+    let mut pos = start;
+    // the match at `pos`, if it was already found looking ahead
+    let mut next = None;
+    while pos < end {
+        let found = match next.take() {
+            Some(found) => found,
+            None => {
+                matches.clear();
+                finder.find_matches(data, base, pos, max_length(pos), &mut matches);
+                longest(&matches, options.min_length)
+            },
+        };
+
+        // the next position to give the finder
+        let mut searched = pos + 1;
+        if let Some((length, _)) = found {
+            if length < options.max_lazy && pos + 1 < end {
+                matches.clear();
+                finder.find_matches(data, base, pos + 1, max_length(pos + 1), &mut matches);
+                searched = pos + 2;
+                if let Some(later) = longest(&matches, options.min_length) {
+                    if later.0 > length {
+                        tokens.push(Token::Literal(data[pos - base]));
+                        pos += 1;
+                        next = Some(Some(later));
+                        continue;
+                    }
+                }
+            }
+        }
+
+        match found {
+            Some((length, distance)) => {
+                tokens.push(Token::Match { length, distance });
+                for skipped in searched..pos + length {
+                    finder.skip(data, base, skipped);
+                }
+                pos += length;
+            },
+            None => {
+                tokens.push(Token::Literal(data[pos - base]));
+                pos += 1;
+            },
+        }
+    }
+}
+
+mod tests {
+    /// Input with repeats at every distance and length.
+    #[cfg(test)]
+    fn test_input(len: usize) -> Vec<u8> {
+        use crate::random::MT19937_32;
+        let mut gen = MT19937_32::from_seed(19);
+        let mut data = Vec::new();
+        while data.len() < len {
+            let r = gen.generate() as usize;
+            if data.len() < 8 || r.is_multiple_of(4) {
+                data.push(b'a' + (r >> 8) as u8 % 4);
+            } else {
+                let distance = 1 + (r >> 8) % data.len();
+                let length = 1 + (r >> 24) % 40;
+                for _ in 0..length {
+                    data.push(data[data.len() - distance]);
+                }
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    #[cfg(test)]
+    fn finders(window_size: usize)
+    -> Vec<(&'static str, Box<dyn crate::algorithms::lz77::MatchFinder>)> {
+        use crate::algorithms::lz77::{BinaryTree, HashChain, HashTable};
+        vec![
+            ("hash chain", Box::new(HashChain::new(window_size, 64, 258))),
+            ("binary tree", Box::new(BinaryTree::new(window_size, 64, 258))),
+            ("hash table", Box::new(HashTable::new(window_size))),
+        ]
+    }
+
+    /// Checks that `length` bytes copied from `distance` back, through a
+    /// ring buffer holding a window, give the bytes at `pos`.
+    #[cfg(test)]
+    fn replays(data: &[u8], window_size: usize, pos: usize, length: usize, distance: usize)
+    -> bool {
+        use std::io::Write;
+        use crate::io::RingBuffer;
+
+        let mut window = RingBuffer::new(window_size);
+        window.write_all(&data[pos - distance..pos]).unwrap();
+        if window.self_copy(distance, length).is_err() {
+            return false;
+        }
+        let mut copied = vec![0; length];
+        window.copy_out(&mut copied, length);
+        copied[..] == data[pos..pos + length]
+    }
+
+    #[test]
+    fn test_find_matches() {
+        use crate::algorithms::lz77::MIN_MATCH;
+
+        let data = test_input(6000);
+        for &window_size in [256, 4096].iter() {
+            for (name, mut finder) in finders(window_size) {
+                let mut total = 0;
+                let mut matches = Vec::new();
+                for pos in 0..data.len() {
+                    if pos % 3 == 0 {
+                        finder.skip(&data, 0, pos);
+                        continue;
+                    }
+
+                    let max_length = std::cmp::min(100, data.len() - pos);
+                    matches.clear();
+                    finder.find_matches(&data, 0, pos, max_length, &mut matches);
+                    let mut previous = MIN_MATCH - 1;
+                    for &(length, distance) in matches.iter() {
+                        assert!(length > previous && length <= max_length, "{}", name);
+                        assert!(distance >= 1 && distance <= window_size, "{}", name);
+                        assert!(replays(&data, window_size, pos, length, distance), "{}", name);
+                        previous = length;
+                    }
+                    total += matches.len();
+                }
+                assert!(total > data.len() / 2, "{} found too few matches", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        use std::io::Write;
+        use crate::algorithms::lz77::{parse, ParseOptions, Token};
+        use crate::io::RingBuffer;
+
+        let data = test_input(20_000);
+        let window_size = 1024;
+        let lazy = ParseOptions::default();
+        let greedy = ParseOptions { max_lazy: 0, ..ParseOptions::default() };
+        let short = ParseOptions { min_length: 5, max_length: 16, ..ParseOptions::default() };
+        for &options in [lazy, greedy, short].iter() {
+            for (name, mut finder) in finders(window_size) {
+                // parsed in pieces, with the data before each piece discarded
+                let mut tokens = Vec::new();
+                for start in (0..data.len()).step_by(5000) {
+                    let base = start.saturating_sub(window_size);
+                    let end = std::cmp::min(start + 5000, data.len());
+                    parse(&mut *finder, options, &data[base..], base, start, end, &mut tokens);
+                }
+
+                let mut window = RingBuffer::new(window_size);
+                let mut output = Vec::new();
+                for &token in tokens.iter() {
+                    match token {
+                        Token::Literal(byte) => {
+                            window.write_all(&[byte]).unwrap();
+                            output.push(byte);
+                        },
+                        Token::Match { length, distance } => {
+                            assert!(length >= options.min_length && length <= options.max_length);
+                            window.self_copy(distance, length).unwrap();
+                            let mut copied = vec![0; length];
+                            window.copy_out(&mut copied, length);
+                            output.extend_from_slice(&copied);
+                        },
+                    }
+                }
+                assert_eq!(output, data, "{}", name);
+                assert!(tokens.len() < data.len() / 2, "{} found too few matches", name);
+            }
+        }
+    }
+}
@@ -6,8 +6,13 @@ use crate::random::{
 mod heap;
 pub use self::heap::*;
 
+mod checksum;
+pub use self::checksum::*;
+
+pub mod lz77;
+
 mod deflate;
-pub use self::deflate::inflate;
+pub use self::deflate::{inflate, inflate_with_options, inflate_with_dictionary, inflate_into, deflate};
 
 pub fn shuffle<T, G: Rng64>(data: &mut [T], eng: &mut G) {
     let n = data.len();
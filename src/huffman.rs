@@ -63,6 +63,281 @@ impl<T> Node<T> {
     }
 }
 
+/// A lookup table for decoding Huffman codes from a bit stream which is read
+/// least significant bit first, with each code starting from its most
+/// significant bit, as in DEFLATE.
+///
+/// The primary table is indexed by the next `primary_bits` bits of the
+/// stream. Codes longer than that are found in subtables, indexed by the
+/// bits that follow. Unlike `Node`, incomplete codes are accepted, and bit
+/// patterns which don't start any code decode to nothing.
+///
+/// The tables are held in a fixed-size array, so a table can be rebuilt
+/// with `set_lengths` without allocating. It has room for any code of up to
+/// 288 symbols and 15 bits with a primary table of 8 to 10 bits; codes which
+/// need more entries than that are rejected.
+///
+/// # Example
+/// ```
+/// # use stdex::huffman::{Code, Table};
+/// # use stdex::io::BitReaderLSB;
+/// let codes = Code::canonical_from_lengths(b'a', &[1, 2, 3, 3]).unwrap();
+/// let table = Table::from_codes(&codes, 2).unwrap();
+///
+/// // 'a' is 0, 'b' is 10, 'c' is 110 and 'd' is 111
+/// let mut bitreader = BitReaderLSB::new(&[0b0111_0110, 0b01][..]);
+/// let mut text = String::new();
+/// for _ in 0..5 {
+///     text.push(table.read_value(&mut bitreader).unwrap() as char);
+/// }
+/// assert_eq!(text, "acdab");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Table<T> {
+    entries: [TableEntry<T>; TABLE_ENTRIES],
+    primary_bits: usize,
+    max_length: usize,
+}
+
+/// The entries a `Table` has room for, primary table and subtables together.
+const TABLE_ENTRIES: usize = 2048;
+
+/// The longest code a `Table` can hold.
+const MAX_TABLE_CODE_LENGTH: usize = 15;
+
+#[derive(Debug, Clone, Copy)]
+enum TableEntry<T> {
+    Empty,
+    Value(T, u8),
+    Subtable(u16, u8),
+}
+
+fn reverse_bits(bits: u32, len: usize) -> usize {
+    match len {
+        0 => 0,
+        _ => (bits.reverse_bits() >> (32 - len)) as usize,
+    }
+}
+
+/// The canonical codes for a list of code lengths, as each symbol's value,
+/// code length and code, without collecting them anywhere.
+#[derive(Clone)]
+struct CanonicalCodes<'a, T> {
+    lengths: &'a [u8],
+    value: T,
+    next_code: [u32; MAX_TABLE_CODE_LENGTH + 1],
+}
+
+impl<'a, T: Increment + Copy> Iterator for CanonicalCodes<'a, T> {
+    type Item = (T, usize, u32);
+
+    fn next(&mut self) -> Option<(T, usize, u32)> {
+        while let Some((&len, rest)) = self.lengths.split_first() {
+            let value = self.value;
+            self.value.increment();
+            self.lengths = rest;
+            if len != 0 {
+                let code = self.next_code[len as usize];
+                self.next_code[len as usize] += 1;
+                return Some((value, len as usize, code));
+            }
+        }
+        None
+    }
+}
+
+impl<T: Copy> Table<T> {
+    /// A table with no codes, which decodes nothing.
+    pub fn new() -> Table<T> {
+        Table { entries: [TableEntry::Empty; TABLE_ENTRIES], primary_bits: 0, max_length: 0 }
+    }
+
+    pub fn from_codes(codes: &[Code<T>], primary_bits: usize) -> SimpleResult<Table<T>> {
+        let mut table = Table::new();
+        let codes = codes.iter().map(|code| (code.value, code.code.len(), code.code.bits()));
+        if let Err(message) = table.build(codes, primary_bits) {
+            error_if(true, message)?;
+        }
+        Ok(table)
+    }
+
+    /// Rebuilds the table for the canonical code with the given code
+    /// lengths, the first of which is for `first_value`, as with
+    /// `Code::canonical_from_lengths`. Nothing is allocated.
+    ///
+    /// Returns false, leaving the table empty, if no symbol has a code, the
+    /// lengths are too many for a prefix code of some length, or the table
+    /// doesn't have room for the code.
+    pub fn set_lengths(&mut self, first_value: T, lengths: &[u8], primary_bits: usize) -> bool
+    where T: Increment {
+        let mut counts = [0u32; MAX_TABLE_CODE_LENGTH + 1];
+        for &len in lengths {
+            match counts.get_mut(len as usize) {
+                Some(count) => *count += 1,
+                None => {
+                    self.clear();
+                    return false;
+                },
+            }
+        }
+        counts[0] = 0;
+
+        let mut next_code = [0u32; MAX_TABLE_CODE_LENGTH + 1];
+        let mut code = 0;
+        for len in 1..=MAX_TABLE_CODE_LENGTH {
+            code = (code + counts[len - 1]) << 1;
+            next_code[len] = code;
+            if code + counts[len] > 1 << len {
+                self.clear();
+                return false;
+            }
+        }
+
+        let codes = CanonicalCodes { lengths, value: first_value, next_code };
+        self.build(codes, primary_bits).is_ok()
+    }
+
+    fn clear(&mut self) {
+        self.entries[0] = TableEntry::Empty;
+        self.primary_bits = 0;
+        self.max_length = 0;
+    }
+
+    /// Fills the table with `codes`, given as each value, the length of
+    /// its code and the code itself.
+    fn build<I>(&mut self, codes: I, primary_bits: usize) -> Result<(), &'static str>
+    where I: Iterator<Item = (T, usize, u32)> + Clone {
+        let result = self.fill(codes, primary_bits);
+        if result.is_err() {
+            self.clear();
+        }
+        result
+    }
+
+    fn fill<I>(&mut self, codes: I, primary_bits: usize) -> Result<(), &'static str>
+    where I: Iterator<Item = (T, usize, u32)> + Clone {
+        let max_length = codes.clone().map(|(_, len, _)| len).max().ok_or("empty huffman code list")?;
+        if max_length > MAX_TABLE_CODE_LENGTH {
+            return Err("huffman code too long for a table");
+        }
+        let primary_bits = std::cmp::min(primary_bits, max_length);
+        self.primary_bits = primary_bits;
+        self.max_length = max_length;
+
+        // size each subtable to fit the longest code sharing its prefix,
+        // noting the size in the primary table until they're all known
+        let primary_mask = (1 << primary_bits) - 1;
+        self.entries[..1 << primary_bits].fill(TableEntry::Empty);
+        for (_, len, bits) in codes.clone() {
+            if len > primary_bits {
+                let prefix = reverse_bits(bits, len) & primary_mask;
+                let sub_bits = (len - primary_bits) as u8;
+                match self.entries[prefix] {
+                    TableEntry::Subtable(_, longest) if longest >= sub_bits => {},
+                    _ => self.entries[prefix] = TableEntry::Subtable(0, sub_bits),
+                }
+            }
+        }
+
+        let mut used = 1 << primary_bits;
+        for prefix in 0..1 << primary_bits {
+            if let TableEntry::Subtable(_, sub_bits) = self.entries[prefix] {
+                let size = 1 << sub_bits;
+                if used + size > TABLE_ENTRIES {
+                    return Err("huffman code needs too many table entries");
+                }
+                self.entries[prefix] = TableEntry::Subtable(used as u16, sub_bits);
+                self.entries[used..used + size].fill(TableEntry::Empty);
+                used += size;
+            }
+        }
+
+        // a code fills every entry whose index starts with its bits
+        for (value, len, bits) in codes {
+            let reversed = reverse_bits(bits, len);
+            let (table_start, table_bits, first, step) = if len <= primary_bits {
+                (0, primary_bits, reversed, 1 << len)
+            } else {
+                match self.entries[reversed & primary_mask] {
+                    TableEntry::Subtable(offset, sub_bits) => {
+                        let sub_len = len - primary_bits;
+                        (offset as usize, sub_bits as usize, reversed >> primary_bits, 1 << sub_len)
+                    },
+                    _ => return Err("not a prefix code"),
+                }
+            };
+
+            let mut index = first;
+            while index < 1 << table_bits {
+                match self.entries[table_start + index] {
+                    TableEntry::Empty => {
+                        self.entries[table_start + index] = TableEntry::Value(value, len as u8);
+                    },
+                    _ => return Err("not a prefix code"),
+                }
+                index += step;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The length of the longest code.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Looks up the code at the start of `bits`, the next bits of the stream
+    /// with the first in the least significant position, returning the value
+    /// and the length of its code.
+    ///
+    /// If fewer than `max_length()` bits are known, the rest should be zero,
+    /// and a result is only valid if its length is no more than the number of
+    /// bits known.
+    pub fn lookup(&self, bits: u32) -> Option<(&T, usize)> {
+        let bits = bits as usize;
+        let mut entry = &self.entries[bits & ((1 << self.primary_bits) - 1)];
+        if let TableEntry::Subtable(offset, sub_bits) = *entry {
+            let index = (bits >> self.primary_bits) & ((1 << sub_bits) - 1);
+            entry = &self.entries[offset as usize + index];
+        }
+
+        match entry {
+            TableEntry::Value(value, len) => Some((value, *len as usize)),
+            _ => None,
+        }
+    }
+
+    /// Decodes one value, reading no more bytes from the underlying reader
+    /// than the code needs.
+    ///
+    /// Fails with `ErrorKind::InvalidData` if the stream contains a bit
+    /// pattern which doesn't start any code.
+    pub fn read_value<R: std::io::Read>(&self, bitreader: &mut crate::io::BitReaderLSB<R>)
+    -> std::io::Result<T> {
+        loop {
+            let (bits, available) = bitreader.peek_buffered();
+            match self.lookup(bits) {
+                Some((value, len)) if len <= available => {
+                    bitreader.consume_bits(len);
+                    return Ok(*value);
+                },
+                None if available >= self.max_length => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData, "invalid huffman code"));
+                },
+                _ => bitreader.fill_byte()?,
+            }
+        }
+    }
+}
+
+impl<T: Copy> Default for Table<T> {
+    fn default() -> Table<T> {
+        Table::new()
+    }
+}
+
 impl<T: Increment + Clone> Code<T> {
     pub fn canonical_from_lengths(first_value: T, code_lengths: &[u32])
     -> SimpleResult<Vec<Code<T>>> {
@@ -130,6 +405,108 @@ impl<T: Increment + Clone> Code<T> {
     }
 }
 
+/// Computes Huffman code lengths for symbols with the given frequencies,
+/// such that no code is longer than `max_length` bits.
+///
+/// Symbols with a frequency of zero are given a length of zero. If fewer than
+/// two symbols have a nonzero frequency, the results will not form a complete
+/// prefix code, so callers which need one should ensure at least two symbols
+/// are used.
+///
+/// # Panics
+/// Panics if `max_length` is too short to give every used symbol a code.
+///
+/// # Example
+/// ```
+/// # use stdex::huffman::limited_code_lengths;
+/// let lengths = limited_code_lengths(&[10, 0, 3, 3, 1], 15);
+/// assert_eq!(lengths, vec![1, 0, 2, 3, 3]);
+///
+/// // unlimited, these would be 1, 2, 3, 4 and 4 bits long
+/// let lengths = limited_code_lengths(&[1000, 100, 10, 1, 1], 3);
+/// assert_eq!(lengths, vec![1, 3, 3, 3, 3]);
+/// ```
+pub fn limited_code_lengths(frequencies: &[u32], max_length: u32) -> Vec<u32> {
+    use crate::collections::binary_heap::BinaryHeap;
+
+    let mut lengths = vec![0; frequencies.len()];
+    let used: Vec<usize> = (0..frequencies.len())
+        .filter(|&i| frequencies[i] != 0)
+        .collect();
+
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        },
+        _ => {},
+    }
+
+    assert!((used.len() as u64) <= (1u64 << max_length), "max_length too short");
+
+    // Build the tree bottom up. Leaves are `0..used.len()`, internal nodes
+    // follow, and `parent` links every node but the root to its parent.
+    let mut parent = vec![0; used.len() * 2 - 1];
+    let mut heap = BinaryHeap::min_heap();
+    for (node, &symbol) in used.iter().enumerate() {
+        heap.push((frequencies[symbol] as u64, node));
+    }
+
+    let mut next_node = used.len();
+    while heap.len() > 1 {
+        let (weight_0, node_0) = heap.pop().unwrap();
+        let (weight_1, node_1) = heap.pop().unwrap();
+        parent[node_0] = next_node;
+        parent[node_1] = next_node;
+        heap.push((weight_0 + weight_1, next_node));
+        next_node += 1;
+    }
+
+    let root = next_node - 1;
+    let mut depth = vec![0u32; next_node];
+    for node in (0..root).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+
+    // Count the codes of each length, clamping any which are too long, then
+    // lengthen shorter codes until the lengths form a valid prefix code again.
+    let max_length = max_length as usize;
+    let mut length_counts = vec![0u64; max_length + 1];
+    for &leaf_depth in depth[..used.len()].iter() {
+        let length = std::cmp::min(leaf_depth as usize, max_length);
+        length_counts[length] += 1;
+    }
+
+    let mut kraft_total: u64 = (1..=max_length)
+        .map(|length| length_counts[length] << (max_length - length))
+        .sum();
+
+    while kraft_total > 1 << max_length {
+        length_counts[max_length] -= 1;
+        for length in (1..max_length).rev() {
+            if length_counts[length] != 0 {
+                length_counts[length] -= 1;
+                length_counts[length + 1] += 2;
+                break;
+            }
+        }
+        kraft_total -= 1;
+    }
+
+    // Hand out the lengths, shortest first, to the most frequent symbols.
+    let mut by_frequency = used;
+    by_frequency.sort_by(|&a, &b| frequencies[b].cmp(&frequencies[a]).then(a.cmp(&b)));
+    let mut symbols = by_frequency.into_iter();
+    for (length, &count) in length_counts.iter().enumerate().skip(1) {
+        for _ in 0..count {
+            lengths[symbols.next().unwrap()] = length as u32;
+        }
+    }
+
+    lengths
+}
+
 mod tests {
     #[cfg(test)]
     use super::{Code, CodeString};
@@ -179,4 +556,56 @@ mod tests {
             code_bits += 1;
         }
     }
+
+    #[test]
+    fn test_table() {
+        use super::Table;
+        use crate::io::{BitWrite, BitWriterLSB, BitReaderLSB};
+
+        // an incomplete code, with subtables for everything past two bits
+        let codes = Code::canonical_from_lengths(0u16, &[1, 3, 3, 4, 0, 5, 5]).unwrap();
+        let table = Table::from_codes(&codes, 2).unwrap();
+        assert_eq!(table.max_length(), 5);
+
+        let message = [0, 5, 1, 6, 0, 0, 3, 2, 6, 5, 3];
+        let mut bitwriter = BitWriterLSB::new(Vec::new());
+        for &value in message.iter() {
+            let code = codes.iter().find(|code| code.value == value).unwrap().code;
+            let mut code = code;
+            while code.len() > 0 {
+                bitwriter.write_bit(code.pop_bit_front()).unwrap();
+            }
+        }
+        bitwriter.finish_byte(0).unwrap();
+        let encoded = bitwriter.into_write();
+
+        let mut bitreader = BitReaderLSB::new(&encoded[..]);
+        for &value in message.iter() {
+            assert_eq!(table.read_value(&mut bitreader).unwrap(), value);
+        }
+
+        // the same code straight from its lengths, into a reused table
+        let mut reused = Table::new();
+        assert!(reused.set_lengths(0u16, &[2, 2, 2, 2], 2));
+        assert!(reused.set_lengths(0u16, &[1, 3, 3, 4, 0, 5, 5], 2));
+        let mut bitreader = BitReaderLSB::new(&encoded[..]);
+        for &value in message.iter() {
+            assert_eq!(reused.read_value(&mut bitreader).unwrap(), value);
+        }
+
+        // too many codes of length 2, and none at all, leave nothing to decode
+        assert!(!reused.set_lengths(0u16, &[1, 2, 2, 2], 2));
+        assert!(reused.read_value(&mut BitReaderLSB::new(&encoded[..])).is_err());
+        assert!(!reused.set_lengths(0u16, &[0, 0], 2));
+
+        // the unused pattern 11111
+        let e = table.read_value(&mut BitReaderLSB::new(&[0xff][..])).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+        let overlapping = [
+            Code { value: 0, code: CodeString::new(1, 0) },
+            Code { value: 1, code: CodeString::new(2, 1) },
+        ];
+        assert!(Table::from_codes(&overlapping, 1).is_err());
+    }
 }
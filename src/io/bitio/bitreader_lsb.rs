@@ -9,8 +9,8 @@ use super::Bit;
 /// byte.
 pub struct BitReaderLSB<R: Read> {
     reader: R,
-    buffer: u32,
-    mask: u32,
+    buffer: u64,
+    count: usize,
 }
 
 impl<R: Read> BitReaderLSB<R> {
@@ -18,10 +18,56 @@ impl<R: Read> BitReaderLSB<R> {
         BitReaderLSB {
             reader,
             buffer: 0,
-            mask: 0x1,
+            count: 0,
         }
     }
 
+    /// Returns the bits which have been read from the underlying `Read`
+    /// object but not yet consumed, along with how many there are.
+    ///
+    /// The next bit is the least significant, and bits above the count are
+    /// zero. Together with `fill_byte` and `consume_bits`, this allows
+    /// looking ahead without reading any further than necessary.
+    ///
+    /// # Example
+    /// ```
+    /// # use stdex::io::{BitRead, BitReaderLSB};
+    /// let mut bitreader = BitReaderLSB::new(&[0xab, 0xcd][..]);
+    /// assert_eq!(bitreader.read_bits_32(4).ok(), Some(0xb));
+    /// assert_eq!(bitreader.peek_buffered(), (0xa, 4));
+    ///
+    /// bitreader.fill_byte().unwrap();
+    /// assert_eq!(bitreader.peek_buffered(), (0xcda, 12));
+    /// bitreader.consume_bits(8);
+    /// assert_eq!(bitreader.read_bits_32(4).ok(), Some(0xc));
+    /// ```
+    pub fn peek_buffered(&self) -> (u32, usize) {
+        (self.buffer as u32, self.count)
+    }
+
+    /// Reads one more byte from the underlying `Read` object into the
+    /// buffer of unconsumed bits.
+    ///
+    /// # Panic
+    /// Panics if more than 24 bits are already buffered.
+    pub fn fill_byte(&mut self) -> std::io::Result<()> {
+        assert!(self.count <= 24, "bit buffer is full");
+        let byte = read_u8(&mut self.reader)?;
+        self.buffer |= (byte as u64) << self.count;
+        self.count += 8;
+        Ok(())
+    }
+
+    /// Discards `count` buffered bits.
+    ///
+    /// # Panic
+    /// Panics if fewer than `count` bits are buffered.
+    pub fn consume_bits(&mut self, count: usize) {
+        assert!(count <= self.count, "not enough bits buffered");
+        self.buffer >>= count;
+        self.count -= count;
+    }
+
     /// Returns a reference to the underlying `Read` object.
     /// 
     /// Any partially read bytes will not be accessible through the reference.
@@ -77,20 +123,12 @@ impl<R: Read> crate::io::BitRead for BitReaderLSB<R> {
     /// }
     /// ```
     fn read_bit(&mut self) -> std::io::Result<Bit> {
-        if self.mask == 0x1 {
-            self.buffer = read_u8(&mut self.reader)? as u32;
-        }
-
-        let result = match self.mask & self.buffer {
-            0 => 0,
-            _ => 1,
-        };
-
-        self.mask <<= 1;
-        if self.mask == 0x100 {
-            self.mask = 0x1;
+        if self.count == 0 {
+            self.fill_byte()?;
         }
 
+        let result = (self.buffer & 1) as Bit;
+        self.consume_bits(1);
         Ok(result)
     }
 
@@ -113,45 +151,24 @@ impl<R: Read> crate::io::BitRead for BitReaderLSB<R> {
     /// 
     /// # Panic
     /// Panics if `count > 32`.
-    fn read_bits_32(&mut self, mut count: usize) -> std::io::Result<u32> {
+    fn read_bits_32(&mut self, count: usize) -> std::io::Result<u32> {
         assert!(count <= 32);
         let mut result = 0;
-        let mut mask_shift = 0;
-        while count > 0 && self.mask != 0x1 {
-            if self.mask & self.buffer != 0 {
-                result |= 1 << mask_shift;
-            }
-            
-            self.mask <<= 1;
-            if self.mask == 0x100 {
-                self.mask = 0x1;
-            }
-            count -= 1;
-            mask_shift += 1;
-        }
-
-        while count >= 8 {
-            let buffer = read_u8(&mut self.reader)? as u32;
-            result = result | (buffer << mask_shift);
-            count -= 8;
-            mask_shift += 8;
+        let mut shift = 0;
+        while count - shift > self.count {
+            // take what is buffered first, so the buffer can't overflow
+            result |= (self.buffer as u32) << shift;
+            shift += self.count;
+            self.buffer = 0;
+            self.count = 0;
+            self.fill_byte()?;
         }
 
-        while count > 0 {
-            if self.mask == 0x1 {
-                self.buffer = read_u8(&mut self.reader)? as u32;
-            }
-
-            if self.mask & self.buffer != 0 {
-                result |= 1 << mask_shift;
-            }
-
-            self.mask <<= 1;
-            if self.mask == 0x100 {
-                self.mask = 0x1;
-            }
-            count -= 1;
-            mask_shift += 1;
+        let remaining = count - shift;
+        if remaining > 0 {
+            let mask = (1u64 << remaining) - 1;
+            result |= ((self.buffer & mask) as u32) << shift;
+            self.consume_bits(remaining);
         }
 
         Ok(result)
@@ -170,8 +187,8 @@ impl<R: Read> crate::io::BitRead for BitReaderLSB<R> {
     /// assert_eq!(bitreader.read_bits_32(4).ok(), Some(0xd));
     /// ```
     fn flush_byte(&mut self) {
-        self.buffer = 0;
-        self.mask = 0x1;
+        let partial = self.count % 8;
+        self.consume_bits(partial);
     }
 }
 
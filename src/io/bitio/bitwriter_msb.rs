@@ -58,11 +58,11 @@ impl<W: Write> BitWriterMSB<W> {
     /// # Panics
     /// Panics if any partially written bytes are left in the buffer. Call
     /// `remaining_bits()` to check if there are any.
-    pub fn into_write(mut self) -> W {
+    pub fn into_write(self) -> W {
         use crate::io::BitWrite;
         assert_eq!(self.remaining_bits(), 0, "bits remaining in BitWriter before dropping");
         unsafe {
-            let writer = std::mem::replace(&mut self.writer, std::mem::uninitialized());
+            let writer = std::ptr::read(&self.writer);
             std::mem::forget(self);
             writer
         }
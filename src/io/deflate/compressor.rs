@@ -0,0 +1,980 @@
+use std::io::{self, Write};
+use crate::collections::BitString;
+use crate::huffman::{limited_code_lengths, Code};
+use crate::io::{BitWrite, BitWriterLSB, write_u16_le};
+use super::lz77::{self, HashChain, MatchFinder, ParseOptions, Token};
+use super::optimal::optimal_parse;
+use super::{LENGTH_BASE, LENGTH_EXTRA, DIST_BASE, DIST_EXTRA, SWIZZLE};
+
+/// How much input is gathered before it is compressed as a block.
+const BLOCK_SIZE: usize = 65536;
+const MAX_STORED_LEN: usize = 65535;
+
+const LITLEN_CODES: usize = 286;
+const DIST_CODES: usize = 30;
+const CODE_LENGTH_CODES: usize = 19;
+
+/// The match finder settings for each level, after zlib's: the number of
+/// candidates compared, the match length which ends a search, and the
+/// length below which a better match one byte later is looked for.
+const LEVELS: [(usize, usize, usize); 10] = [
+    (0, 0, 0),
+    (4, 8, 0),
+    (8, 16, 0),
+    (32, 32, 0),
+    (16, 16, 4),
+    (32, 32, 16),
+    (128, 128, 16),
+    (256, 128, 32),
+    (1024, 258, 128),
+    (4096, 258, 258),
+];
+
+/// How a `DeflateCompressor` looks for matches and picks block encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Uses every match found, and the smallest block encoding.
+    Default,
+    /// Ignores matches shorter than 6 bytes, which suits data of small,
+    /// noisy values such as filtered images.
+    Filtered,
+    /// Uses no matches at all, only Huffman coding the bytes.
+    HuffmanOnly,
+    /// Only matches runs of the previous byte, at distance 1.
+    Rle,
+    /// Never uses dynamic Huffman blocks, which saves their headers on
+    /// small inputs.
+    Fixed,
+    /// Spends many times longer to compress as well as possible, for data
+    /// which is compressed once and decompressed often. Matches are chosen
+    /// by what they will cost to encode rather than by length, and blocks
+    /// are split wherever that makes them smaller. Best used at level 9,
+    /// which searches for matches the hardest.
+    Optimal,
+}
+
+/// Settings for a `DeflateCompressor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateOptions {
+    /// From 0 to 9. Level 0 only writes stored blocks, and higher levels
+    /// spend more time looking for matches to compress better. The default
+    /// is 6.
+    pub level: u32,
+    pub strategy: Strategy,
+}
+
+impl Default for DeflateOptions {
+    fn default() -> DeflateOptions {
+        DeflateOptions {
+            level: 6,
+            strategy: Strategy::Default,
+        }
+    }
+}
+
+/// Compresses data written to it with the DEFLATE algorithm (RFC 1951),
+/// writing the compressed stream to an underlying `Write` object.
+///
+/// Input is gathered into blocks of 64 KiB, which are parsed into literals
+/// and LZ77 matches and then written with whichever of the stored, fixed
+/// Huffman, or dynamic Huffman encodings is smallest.
+///
+/// `flush()` compresses everything written so far and ends it with an empty
+/// stored block, so that a decompressor can read all of it before the stream
+/// ends. `full_flush()` and `partial_flush()` are variations on it.
+///
+/// `finish()` must be called to write the final block and get back the
+/// underlying writer. If the compressor is dropped without finishing, the
+/// final block is written then, ignoring any errors.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{DeflateCompressor, DeflateDecompressor};
+/// # use std::io::{Read, Write};
+/// let mut compressor = DeflateCompressor::new(Vec::new());
+/// compressor.write_all(b"so much depends upon a red wheel barrow").unwrap();
+/// let compressed = compressor.finish().unwrap();
+///
+/// let mut decompressor = DeflateDecompressor::new(&compressed[..]).unwrap();
+/// let mut output = String::new();
+/// decompressor.read_to_string(&mut output).unwrap();
+/// assert_eq!(output, "so much depends upon a red wheel barrow");
+/// ```
+pub struct DeflateCompressor<W: Write> {
+    bitwriter: Option<BitWriterLSB<W>>,
+    // up to a window of already compressed input, followed by the input
+    // which hasn't been compressed yet
+    buffer: Vec<u8>,
+    buffer_start: usize,
+    pending: usize,
+    matcher: HashChain,
+    parse_options: ParseOptions,
+    options: DeflateOptions,
+    // whether anything has been written since the last flush
+    unflushed: bool,
+}
+
+impl<W: Write> DeflateCompressor<W> {
+    pub fn new(writer: W) -> DeflateCompressor<W> {
+        DeflateCompressor::with_options(writer, DeflateOptions::default())
+    }
+
+    /// Creates a compressor with the given level and strategy.
+    ///
+    /// # Panics
+    /// If the level is over 9.
+    ///
+    /// # Example
+    /// ```
+    /// # use stdex::io::{DeflateCompressor, DeflateOptions, Strategy};
+    /// # use std::io::Write;
+    /// let input = b"abababababababababababababababab".repeat(100);
+    /// let mut sizes = Vec::new();
+    /// for &level in [1, 9].iter() {
+    ///     let options = DeflateOptions { level, strategy: Strategy::Default };
+    ///     let mut compressor = DeflateCompressor::with_options(Vec::new(), options);
+    ///     compressor.write_all(&input).unwrap();
+    ///     sizes.push(compressor.finish().unwrap().len());
+    /// }
+    /// assert!(sizes[1] <= sizes[0]);
+    ///
+    /// let mut output = Vec::new();
+    /// let options = DeflateOptions { level: 9, strategy: Strategy::Default };
+    /// let mut compressor = DeflateCompressor::with_options(Vec::new(), options);
+    /// compressor.write_all(&input).unwrap();
+    /// let compressed = compressor.finish().unwrap();
+    /// stdex::algorithms::inflate(&mut &compressed[..], &mut output).unwrap();
+    /// assert_eq!(output, input);
+    /// ```
+    pub fn with_options(writer: W, options: DeflateOptions) -> DeflateCompressor<W> {
+        assert!(options.level <= 9, "compression level must be from 0 to 9");
+        let (max_chain, nice_length, max_lazy) = LEVELS[options.level as usize];
+        let min_length = match options.strategy {
+            Strategy::Filtered => 6,
+            _ => lz77::MIN_MATCH,
+        };
+
+        DeflateCompressor {
+            bitwriter: Some(BitWriterLSB::new(writer)),
+            buffer: Vec::new(),
+            buffer_start: 0,
+            pending: 0,
+            matcher: HashChain::new(lz77::WINDOW_SIZE, max_chain, nice_length),
+            parse_options: ParseOptions { min_length, max_length: lz77::MAX_MATCH, max_lazy },
+            options,
+            unflushed: false,
+        }
+    }
+
+    /// The settings the compressor was created with.
+    pub fn options(&self) -> DeflateOptions {
+        self.options
+    }
+
+    /// Returns the underlying writer. Only whole bytes of the compressed
+    /// stream have been written to it, and after a `flush()` that is
+    /// everything written so far.
+    pub fn get_ref(&self) -> &W {
+        self.bitwriter.as_ref().unwrap().as_write()
+    }
+
+    /// Returns the underlying writer. Writing to it will corrupt the
+    /// stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.bitwriter.as_mut().unwrap().as_write_mut()
+    }
+
+    /// Creates a compressor whose matches may refer back into a preset
+    /// dictionary, so data which resembles it compresses better. The same
+    /// dictionary must be given to the decompressor.
+    ///
+    /// Only the last 32 KiB of the dictionary can be referred to.
+    pub fn with_dictionary(writer: W, dictionary: &[u8]) -> DeflateCompressor<W> {
+        let mut compressor = DeflateCompressor::new(writer);
+        compressor.set_dictionary(dictionary);
+        compressor
+    }
+
+    /// Sets a preset dictionary, as `with_dictionary` does, for a compressor
+    /// created some other way.
+    ///
+    /// # Panics
+    /// If anything has been written to the compressor.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        assert!(self.buffer_start == 0 && self.buffer.is_empty(),
+            "the dictionary must be set before anything is written");
+        let start = dictionary.len().saturating_sub(lz77::WINDOW_SIZE);
+        self.buffer.extend_from_slice(&dictionary[start..]);
+        self.pending = self.buffer.len();
+        for pos in 0..self.pending {
+            self.matcher.skip(&self.buffer, 0, pos);
+        }
+    }
+
+    /// Like `flush()`, but also forgets all the input so far, so that the
+    /// data after this point can be decompressed without anything before it.
+    /// This costs some compression.
+    pub fn full_flush(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let discard = self.buffer.len();
+        self.buffer.clear();
+        self.buffer_start += discard;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Compresses everything written so far, ending it with an empty fixed
+    /// Huffman block rather than an empty stored block. This takes only 10
+    /// bits, but leaves the stream part way through a byte, so the last
+    /// byte of the input may only be readable once more is written.
+    pub fn partial_flush(&mut self) -> io::Result<()> {
+        if self.compress_pending()? {
+            let bitwriter = self.bitwriter.as_mut().unwrap();
+            bitwriter.write_bit(0)?;
+            bitwriter.write_bits_32(1, 2)?;
+            bitwriter.write_bits_32(0, 7)?;
+        }
+        self.bitwriter.as_mut().unwrap().as_write_mut().flush()
+    }
+
+    /// Compresses any input which hasn't been compressed yet as a block,
+    /// however short it is. Returns whether there was any.
+    fn compress_pending(&mut self) -> io::Result<bool> {
+        let end = self.buffer.len();
+        if end == self.pending {
+            return Ok(false);
+        }
+        self.compress_block(end, false)?;
+        Ok(true)
+    }
+
+    /// Does a sync flush, and returns the underlying writer without ending
+    /// the deflate stream. Another compressor can carry on the stream from
+    /// there, such as one given the end of this one's input as a dictionary.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        let bitwriter = self.bitwriter.take().unwrap();
+        Ok(bitwriter.into_write())
+    }
+
+    /// Compresses any remaining input as the final block, and returns the
+    /// underlying `Write` object.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_final_block()?;
+        let bitwriter = self.bitwriter.take().unwrap();
+        Ok(bitwriter.into_write())
+    }
+
+    fn write_final_block(&mut self) -> io::Result<()> {
+        let end = self.buffer.len();
+        self.compress_block(end, true)?;
+        self.bitwriter.as_mut().unwrap().finish_byte(0)
+    }
+
+    fn compress_block(&mut self, end: usize, final_block: bool) -> io::Result<()> {
+        let start = self.pending;
+        let base = self.buffer_start;
+        let raw = &self.buffer[start..end];
+        let bitwriter = self.bitwriter.as_mut().unwrap();
+        if self.options.level == 0 {
+            write_stored_blocks(bitwriter, raw, final_block)?;
+        } else if self.options.strategy == Strategy::Optimal {
+            let blocks = optimal_parse(&mut self.matcher, &self.buffer, base, base + start,
+                base + end);
+            let count = blocks.len();
+            let mut offset = start;
+            for (i, (tokens, len)) in blocks.into_iter().enumerate() {
+                let raw = &self.buffer[offset..offset + len];
+                write_block(bitwriter, &tokens, raw, final_block && i + 1 == count, true)?;
+                offset += len;
+            }
+        } else {
+            let mut tokens = Vec::new();
+            match self.options.strategy {
+                Strategy::HuffmanOnly => tokens.extend(raw.iter().map(|&b| Token::Literal(b))),
+                Strategy::Rle => {
+                    lz77::parse_rle(&self.buffer, base, base + start, base + end, &mut tokens)
+                },
+                _ => lz77::parse(&mut self.matcher, self.parse_options, &self.buffer, base,
+                    base + start, base + end, &mut tokens),
+            }
+            let allow_dynamic = self.options.strategy != Strategy::Fixed;
+            write_block(bitwriter, &tokens, raw, final_block, allow_dynamic)?;
+        }
+
+        self.pending = end;
+        if self.pending > lz77::WINDOW_SIZE {
+            let discard = self.pending - lz77::WINDOW_SIZE;
+            self.buffer.drain(..discard);
+            self.buffer_start += discard;
+            self.pending -= discard;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DeflateCompressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the input is taken a block at a time, so the buffer never holds
+        // more than the window and one block, and dropping the start of it
+        // after each block stays cheap however much is written at once
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let room = BLOCK_SIZE - (self.buffer.len() - self.pending);
+            let (block, after) = rest.split_at(std::cmp::min(room, rest.len()));
+            self.buffer.extend_from_slice(block);
+            self.unflushed = true;
+            rest = after;
+            if self.buffer.len() - self.pending == BLOCK_SIZE {
+                let end = self.buffer.len();
+                self.compress_block(end, false)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Does a sync flush: compresses everything written so far as a block,
+    /// followed by an empty stored block, which pads the stream to a whole
+    /// byte and ends with the bytes `00 00 FF FF`. Then flushes the
+    /// underlying writer.
+    ///
+    /// Nothing is added to the stream if nothing has been written since the
+    /// last flush.
+    ///
+    /// # Example
+    /// ```
+    /// # use stdex::io::{DeflateCompressor, Inflater};
+    /// # use std::io::Write;
+    /// let mut compressor = DeflateCompressor::new(Vec::new());
+    /// compressor.write_all(b"ping").unwrap();
+    /// compressor.flush().unwrap();
+    /// let sent = compressor.get_ref();
+    /// assert!(sent.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+    ///
+    /// let mut inflater = Inflater::new();
+    /// let mut output = [0; 16];
+    /// let progress = inflater.feed(sent, &mut output).unwrap();
+    /// assert_eq!(&output[..progress.produced], b"ping");
+    /// ```
+    fn flush(&mut self) -> io::Result<()> {
+        if self.unflushed {
+            self.compress_pending()?;
+            let bitwriter = self.bitwriter.as_mut().unwrap();
+            write_stored_block(bitwriter, &[], false)?;
+            self.unflushed = false;
+        }
+        self.bitwriter.as_mut().unwrap().as_write_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for DeflateCompressor<W> {
+    fn drop(&mut self) {
+        if self.bitwriter.is_some() {
+            let _ = self.write_final_block();
+        }
+
+        if let Some(bitwriter) = self.bitwriter.take() {
+            if bitwriter.remaining_bits() != 0 {
+                // the writer failed partway through a byte, and BitWriterLSB
+                // can't be dropped with bits left over
+                std::mem::forget(bitwriter);
+            }
+        }
+    }
+}
+
+/// A Huffman code prepared for writing. For each symbol, holds its code with
+/// the bits reversed, as they are written least significant bit first, and
+/// the length of the code.
+struct Encoding {
+    codes: Vec<(u32, usize)>,
+}
+
+impl Encoding {
+    fn from_lengths(lengths: &[u32]) -> Encoding {
+        let mut codes = vec![(0, 0); lengths.len()];
+        for Code { value, code } in Code::canonical_from_lengths(0usize, lengths).unwrap() {
+            codes[value] = (reverse_bits(code.bits(), code.len()), code.len());
+        }
+        Encoding { codes }
+    }
+
+    fn write<W: Write>(&self, bitwriter: &mut BitWriterLSB<W>, symbol: usize)
+    -> io::Result<()> {
+        let (bits, len) = self.codes[symbol];
+        bitwriter.write_bits_32(bits, len)
+    }
+}
+
+fn reverse_bits(bits: u32, len: usize) -> u32 {
+    bits.reverse_bits() >> (32 - len)
+}
+
+pub(super) fn length_code(length: usize) -> usize {
+    match LENGTH_BASE.binary_search(&length) {
+        Ok(code) => code,
+        Err(code) => code - 1,
+    }
+}
+
+pub(super) fn dist_code(distance: usize) -> usize {
+    match DIST_BASE[..DIST_CODES].binary_search(&distance) {
+        Ok(code) => code,
+        Err(code) => code - 1,
+    }
+}
+
+fn fixed_litlen_lengths() -> [u32; 288] {
+    let mut lengths = [8; 288];
+    for length in lengths[144..256].iter_mut() { *length = 9; }
+    for length in lengths[256..280].iter_mut() { *length = 7; }
+    lengths
+}
+
+/// Makes sure at least two symbols are used, as a complete prefix code
+/// can't be built from fewer.
+fn ensure_two_used(frequencies: &mut [u32]) {
+    let used = frequencies.iter().filter(|&&f| f != 0).count();
+    for _ in used..2 {
+        if let Some(f) = frequencies.iter_mut().find(|f| **f == 0) {
+            *f = 1;
+        }
+    }
+}
+
+/// The code lengths of a dynamic block, along with their run length encoding
+/// as `(symbol, extra bits)` pairs, and the code lengths of that encoding.
+struct DynamicHeader {
+    litlen_lengths: Vec<u32>,
+    dist_lengths: Vec<u32>,
+    hlit: usize,
+    hdist: usize,
+    runs: Vec<(usize, u32)>,
+    code_length_lengths: Vec<u32>,
+    hclen: usize,
+}
+
+impl DynamicHeader {
+    fn new(litlen_frequencies: &[u32], dist_frequencies: &[u32]) -> DynamicHeader {
+        let mut litlen_frequencies = litlen_frequencies.to_vec();
+        let mut dist_frequencies = dist_frequencies.to_vec();
+        ensure_two_used(&mut litlen_frequencies);
+        ensure_two_used(&mut dist_frequencies);
+
+        let litlen_lengths = limited_code_lengths(&litlen_frequencies, 15);
+        let dist_lengths = limited_code_lengths(&dist_frequencies, 15);
+
+        let hlit = std::cmp::max(257, used_len(&litlen_lengths));
+        let hdist = std::cmp::max(1, used_len(&dist_lengths));
+
+        let mut all_lengths = litlen_lengths[..hlit].to_vec();
+        all_lengths.extend_from_slice(&dist_lengths[..hdist]);
+        let runs = run_length_encode(&all_lengths);
+
+        let mut frequencies = [0; CODE_LENGTH_CODES];
+        for &(symbol, _) in runs.iter() {
+            frequencies[symbol] += 1;
+        }
+        ensure_two_used(&mut frequencies);
+        let code_length_lengths = limited_code_lengths(&frequencies, 7);
+
+        let hclen = SWIZZLE.iter()
+            .rposition(|&symbol| code_length_lengths[symbol] != 0)
+            .map_or(4, |last| std::cmp::max(4, last + 1));
+
+        DynamicHeader {
+            litlen_lengths, dist_lengths, hlit, hdist,
+            runs, code_length_lengths, hclen,
+        }
+    }
+
+    fn size_in_bits(&self) -> usize {
+        let mut bits = 5 + 5 + 4 + 3 * self.hclen;
+        for &(symbol, _) in self.runs.iter() {
+            bits += self.code_length_lengths[symbol] as usize;
+            bits += run_extra_bits(symbol);
+        }
+        bits
+    }
+
+    fn write<W: Write>(&self, bitwriter: &mut BitWriterLSB<W>) -> io::Result<()> {
+        bitwriter.write_bits_32((self.hlit - 257) as u32, 5)?;
+        bitwriter.write_bits_32((self.hdist - 1) as u32, 5)?;
+        bitwriter.write_bits_32((self.hclen - 4) as u32, 4)?;
+        for &symbol in SWIZZLE[..self.hclen].iter() {
+            bitwriter.write_bits_32(self.code_length_lengths[symbol], 3)?;
+        }
+
+        let encoding = Encoding::from_lengths(&self.code_length_lengths);
+        for &(symbol, extra) in self.runs.iter() {
+            encoding.write(bitwriter, symbol)?;
+            bitwriter.write_bits_32(extra, run_extra_bits(symbol))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn used_len(lengths: &[u32]) -> usize {
+    lengths.iter().rposition(|&length| length != 0).map_or(0, |last| last + 1)
+}
+
+fn run_extra_bits(symbol: usize) -> usize {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+fn run_length_encode(lengths: &[u32]) -> Vec<(usize, u32)> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let length = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == length {
+            run += 1;
+        }
+        i += run;
+
+        if length == 0 {
+            while run >= 11 {
+                let repeat = std::cmp::min(run, 138);
+                runs.push((18, (repeat - 11) as u32));
+                run -= repeat;
+            }
+            if run >= 3 {
+                runs.push((17, (run - 3) as u32));
+                run = 0;
+            }
+        } else {
+            runs.push((length as usize, 0));
+            run -= 1;
+            while run >= 3 {
+                let repeat = std::cmp::min(run, 6);
+                runs.push((16, (repeat - 3) as u32));
+                run -= repeat;
+            }
+        }
+
+        for _ in 0..run {
+            runs.push((length as usize, 0));
+        }
+    }
+    runs
+}
+
+/// The sizes in bits of some tokens written as a fixed or a dynamic Huffman
+/// block, or of the bytes they stand for written as stored blocks.
+struct BlockSizes {
+    stored: usize,
+    fixed: usize,
+    dynamic: usize,
+    header: DynamicHeader,
+}
+
+impl BlockSizes {
+    fn new(tokens: &[Token], raw_len: usize) -> BlockSizes {
+        let mut litlen_frequencies = [0; LITLEN_CODES];
+        let mut dist_frequencies = [0; DIST_CODES];
+        let mut extra_bits = 0;
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => litlen_frequencies[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    let code = length_code(length);
+                    litlen_frequencies[257 + code] += 1;
+                    extra_bits += LENGTH_EXTRA[code];
+                    let code = dist_code(distance);
+                    dist_frequencies[code] += 1;
+                    extra_bits += DIST_EXTRA[code];
+                },
+            }
+        }
+        litlen_frequencies[256] = 1;
+
+        let fixed = 3 + extra_bits
+            + cost(&litlen_frequencies, &fixed_litlen_lengths())
+            + cost(&dist_frequencies, &[5; DIST_CODES]);
+
+        let header = DynamicHeader::new(&litlen_frequencies, &dist_frequencies);
+        let dynamic = 3 + extra_bits + header.size_in_bits()
+            + cost(&litlen_frequencies, &header.litlen_lengths)
+            + cost(&dist_frequencies, &header.dist_lengths);
+
+        let stored_blocks = std::cmp::max(1, raw_len.div_ceil(MAX_STORED_LEN));
+        let stored = (raw_len + 5 * stored_blocks) * 8;
+
+        BlockSizes { stored, fixed, dynamic, header }
+    }
+
+    fn smallest(&self) -> usize {
+        std::cmp::min(self.stored, std::cmp::min(self.fixed, self.dynamic))
+    }
+}
+
+/// The size in bits of `tokens`, standing for `raw_len` bytes, when written
+/// with whichever block encoding is smallest.
+pub(super) fn block_bits(tokens: &[Token], raw_len: usize) -> usize {
+    BlockSizes::new(tokens, raw_len).smallest()
+}
+
+/// Writes `tokens` as a single block, or as stored blocks holding `raw` if
+/// that is smaller. A dynamic block is only considered if `allow_dynamic`.
+fn write_block<W: Write>(bitwriter: &mut BitWriterLSB<W>, tokens: &[Token], raw: &[u8],
+final_block: bool, allow_dynamic: bool) -> io::Result<()> {
+    let BlockSizes { stored: stored_size, fixed: fixed_size, dynamic, header } =
+        BlockSizes::new(tokens, raw.len());
+    let dynamic_size = if allow_dynamic { dynamic } else { usize::MAX };
+
+    if stored_size <= fixed_size && stored_size <= dynamic_size {
+        write_stored_blocks(bitwriter, raw, final_block)
+    } else if fixed_size <= dynamic_size {
+        bitwriter.write_bit(final_block as u8)?;
+        bitwriter.write_bits_32(1, 2)?;
+        let litlen = Encoding::from_lengths(&fixed_litlen_lengths());
+        let distance = Encoding::from_lengths(&[5; 32]);
+        write_tokens(bitwriter, tokens, &litlen, &distance)
+    } else {
+        bitwriter.write_bit(final_block as u8)?;
+        bitwriter.write_bits_32(2, 2)?;
+        header.write(bitwriter)?;
+        let litlen = Encoding::from_lengths(&header.litlen_lengths);
+        let distance = Encoding::from_lengths(&header.dist_lengths);
+        write_tokens(bitwriter, tokens, &litlen, &distance)
+    }
+}
+
+fn cost(frequencies: &[u32], lengths: &[u32]) -> usize {
+    frequencies.iter().zip(lengths.iter())
+        .map(|(&frequency, &length)| (frequency * length) as usize)
+        .sum()
+}
+
+fn write_stored_blocks<W: Write>(bitwriter: &mut BitWriterLSB<W>, raw: &[u8],
+final_block: bool) -> io::Result<()> {
+    let mut chunks = raw.chunks(MAX_STORED_LEN).peekable();
+    if chunks.peek().is_none() {
+        return write_stored_block(bitwriter, &[], final_block);
+    }
+
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        write_stored_block(bitwriter, chunk, final_block && last)?;
+    }
+
+    Ok(())
+}
+
+fn write_stored_block<W: Write>(bitwriter: &mut BitWriterLSB<W>, chunk: &[u8],
+final_block: bool) -> io::Result<()> {
+    bitwriter.write_bit(final_block as u8)?;
+    bitwriter.write_bits_32(0, 2)?;
+    bitwriter.finish_byte(0)?;
+
+    let writer = bitwriter.as_write_mut();
+    let len = chunk.len() as u16;
+    write_u16_le(writer, len)?;
+    write_u16_le(writer, !len)?;
+    writer.write_all(chunk)
+}
+
+fn write_tokens<W: Write>(bitwriter: &mut BitWriterLSB<W>, tokens: &[Token],
+litlen: &Encoding, distance: &Encoding) -> io::Result<()> {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => litlen.write(bitwriter, byte as usize)?,
+            Token::Match { length, distance: dist } => {
+                let code = length_code(length);
+                litlen.write(bitwriter, 257 + code)?;
+                bitwriter.write_bits_32((length - LENGTH_BASE[code]) as u32, LENGTH_EXTRA[code])?;
+
+                let code = dist_code(dist);
+                distance.write(bitwriter, code)?;
+                bitwriter.write_bits_32((dist - DIST_BASE[code]) as u32, DIST_EXTRA[code])?;
+            },
+        }
+    }
+
+    litlen.write(bitwriter, 256)
+}
+
+mod tests {
+    #[cfg(test)]
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        use std::io::{Read, Write};
+        use crate::io::{DeflateCompressor, DeflateDecompressor};
+
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        compressor.write_all(input).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let mut decompressor = DeflateDecompressor::new(&compressed[..]).unwrap();
+        let mut output = Vec::new();
+        decompressor.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+
+        let mut output = Vec::new();
+        crate::algorithms::inflate(&mut &compressed[..], &mut output).unwrap();
+        assert_eq!(output, input);
+
+        compressed
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(round_trip(b""), vec![0x03, 0x00]);
+    }
+
+    #[test]
+    fn test_repetitive() {
+        let input: Vec<u8> = b"abcabcabd".iter().cycle().take(200_000).cloned().collect();
+        let compressed = round_trip(&input);
+        assert!(compressed.len() < input.len() / 50);
+    }
+
+    #[test]
+    fn test_random() {
+        use crate::random::MT19937_32;
+        let mut gen = MT19937_32::from_seed(1234);
+        let input: Vec<u8> = (0..150_000).map(|_| gen.generate() as u8).collect();
+        let compressed = round_trip(&input);
+        // incompressible data should fall back to stored blocks
+        assert!(compressed.len() <= input.len() + 32);
+    }
+
+    #[test]
+    fn test_text_in_pieces() {
+        use std::io::Write;
+        use crate::random::MT19937_32;
+        let words = ["the ", "quick ", "brown ", "fox ", "jumps ", "over ", "lazy ", "dog\n"];
+        let mut gen = MT19937_32::from_seed(99);
+        let mut input = Vec::new();
+        while input.len() < 300_000 {
+            let word = words[(gen.generate() % words.len() as u32) as usize];
+            input.extend_from_slice(word.as_bytes());
+        }
+
+        let compressed = round_trip(&input);
+        assert!(compressed.len() < input.len() / 2);
+
+        // writing in small, uneven pieces makes no difference to the output
+        let mut compressor = crate::io::DeflateCompressor::new(Vec::new());
+        for piece in input.chunks(777) {
+            compressor.write_all(piece).unwrap();
+        }
+        assert_eq!(compressor.finish().unwrap(), compressed);
+    }
+
+    #[test]
+    fn test_large_write() {
+        use std::io::Write;
+        use crate::io::DeflateCompressor;
+        use crate::random::MT19937_32;
+
+        let mut gen = MT19937_32::from_seed(1);
+        let input: Vec<u8> = (0..8 << 20).map(|i| match i % 1000 < 100 {
+            true => gen.generate() as u8,
+            false => b"log line "[i % 9],
+        }).collect();
+
+        // one big write is compressed a block at a time, just like small ones
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        compressor.write_all(&input).unwrap();
+        assert!(compressor.buffer.len() <= super::lz77::WINDOW_SIZE + super::BLOCK_SIZE);
+        let compressed = compressor.finish().unwrap();
+
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        for piece in input.chunks(65536) {
+            compressor.write_all(piece).unwrap();
+        }
+        assert_eq!(compressor.finish().unwrap(), compressed);
+        assert_eq!(round_trip(&input), compressed);
+    }
+
+    #[test]
+    fn test_levels_and_strategies() {
+        use std::io::Write;
+        use crate::io::{BlockType, DeflateCompressor, DeflateInspection, DeflateOptions};
+        use crate::io::{DeflateToken, Strategy};
+        use crate::random::MT19937_32;
+
+        let words = ["alpha ", "beta ", "gamma ", "delta ", "epsilon\n"];
+        let mut gen = MT19937_32::from_seed(16);
+        let mut input = Vec::new();
+        while input.len() < 100_000 {
+            let word = words[(gen.generate() % words.len() as u32) as usize];
+            input.extend_from_slice(word.as_bytes());
+            if gen.generate().is_multiple_of(8) {
+                input.extend((0..gen.generate() % 300).map(|_| 0));
+            }
+        }
+
+        let compress = |options| {
+            let mut compressor = DeflateCompressor::with_options(Vec::new(), options);
+            compressor.write_all(&input).unwrap();
+            let compressed = compressor.finish().unwrap();
+
+            let mut output = Vec::new();
+            crate::algorithms::inflate(&mut &compressed[..], &mut output).unwrap();
+            assert_eq!(output, input);
+            let inspection = DeflateInspection::from_reader(&compressed[..]).unwrap();
+            (compressed, inspection)
+        };
+
+        let strategies = [Strategy::Default, Strategy::Filtered, Strategy::HuffmanOnly,
+            Strategy::Rle, Strategy::Fixed];
+        let mut sizes = Vec::new();
+        for level in 0..10 {
+            for &strategy in strategies.iter() {
+                let (compressed, inspection) = compress(DeflateOptions { level, strategy });
+                let tokens = || inspection.blocks.iter().flat_map(|block| block.tokens.iter());
+                let matches = || tokens().filter_map(|token| match token.token {
+                    DeflateToken::Match { length, distance } => Some((length, distance)),
+                    DeflateToken::Literal(_) => None,
+                });
+
+                if level == 0 {
+                    assert!(inspection.blocks.iter().all(|b| b.block_type == BlockType::Stored));
+                    continue;
+                }
+                match strategy {
+                    Strategy::Default => sizes.push(compressed.len()),
+                    Strategy::Filtered => assert!(matches().all(|(length, _)| length >= 6)),
+                    Strategy::HuffmanOnly => assert_eq!(matches().count(), 0),
+                    Strategy::Rle => {
+                        assert!(matches().count() > 0);
+                        assert!(matches().all(|(_, distance)| distance == 1));
+                    },
+                    Strategy::Fixed => {
+                        assert!(inspection.blocks.iter().all(|b| b.block_type != BlockType::Dynamic));
+                    },
+                    Strategy::Optimal => unreachable!(),
+                }
+            }
+        }
+
+        // higher levels compress better, if only a little
+        assert!(sizes[8] <= sizes[0]);
+        assert!(sizes[5] < sizes[0]);
+
+        // the default options are level 6
+        let (compressed, _) = compress(DeflateOptions::default());
+        assert_eq!(compressed.len(), sizes[5]);
+    }
+
+    #[test]
+    fn test_flush() {
+        use std::io::Write;
+        use crate::io::{DeflateCompressor, DeflateDecompressor, Inflater};
+
+        let messages: Vec<Vec<u8>> = (0..20)
+            .map(|i| format!("message {} says hello, hello, hello", i).into_bytes())
+            .collect();
+
+        // after each flush, everything written so far can be decompressed
+        // from what has been sent
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        let mut inflater = Inflater::new();
+        let mut sent = 0;
+        let mut output = vec![0; 1000];
+        for (i, message) in messages.iter().enumerate() {
+            compressor.write_all(message).unwrap();
+            match i % 3 {
+                0 => compressor.flush().unwrap(),
+                1 => compressor.full_flush().unwrap(),
+                _ => {
+                    compressor.partial_flush().unwrap();
+                    // the partial flush's empty block holds the last byte
+                    // back, so read the rest after the next flush
+                    compressor.flush().unwrap();
+                },
+            }
+            let stream = compressor.get_ref();
+            assert!(stream.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+
+            let progress = inflater.feed(&stream[sent..], &mut output).unwrap();
+            assert_eq!(progress.consumed, stream.len() - sent);
+            assert_eq!(&output[..progress.produced], &message[..]);
+            sent = stream.len();
+
+            // flushing again without writing adds nothing
+            compressor.flush().unwrap();
+            assert_eq!(compressor.get_ref().len(), sent);
+        }
+        let compressed = compressor.finish().unwrap();
+
+        let mut decompressed = Vec::new();
+        crate::algorithms::inflate(&mut &compressed[..], &mut decompressed).unwrap();
+        assert_eq!(decompressed, messages.concat());
+
+        // after a full flush, the rest of the stream stands alone
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        compressor.write_all(&messages[0]).unwrap();
+        compressor.full_flush().unwrap();
+        let restart = compressor.get_ref().len();
+        compressor.write_all(&messages[0]).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let mut decompressor = DeflateDecompressor::new(&compressed[restart..]).unwrap();
+        let mut decompressed = Vec::new();
+        std::io::Read::read_to_end(&mut decompressor, &mut decompressed).unwrap();
+        assert_eq!(decompressed, messages[0]);
+    }
+
+    #[test]
+    fn test_optimal() {
+        use std::io::Write;
+        use crate::io::{BlockType, DeflateCompressor, DeflateInspection, DeflateOptions, Strategy};
+        use crate::random::MT19937_32;
+
+        // text, then noise, then a different kind of text, which are best
+        // compressed as separate blocks
+        let words = ["red ", "orange ", "yellow ", "green ", "blue ", "indigo ", "violet\n"];
+        let mut gen = MT19937_32::from_seed(18);
+        let mut input = Vec::new();
+        while input.len() < 20_000 {
+            let word = words[(gen.generate() % words.len() as u32) as usize];
+            input.extend_from_slice(word.as_bytes());
+        }
+        input.extend((0..5_000).map(|_| gen.generate() as u8));
+        while input.len() < 45_000 {
+            let number = format!("{},", gen.generate() % 1000);
+            input.extend_from_slice(number.as_bytes());
+        }
+
+        let compress = |strategy| {
+            let options = DeflateOptions { level: 9, strategy };
+            let mut compressor = DeflateCompressor::with_options(Vec::new(), options);
+            compressor.write_all(&input).unwrap();
+            compressor.finish().unwrap()
+        };
+        let default = compress(Strategy::Default);
+        let optimal = compress(Strategy::Optimal);
+        assert!(optimal.len() < default.len());
+
+        let mut output = Vec::new();
+        crate::algorithms::inflate(&mut &optimal[..], &mut output).unwrap();
+        assert_eq!(output, input);
+
+        let inspection = DeflateInspection::from_reader(&optimal[..]).unwrap();
+        let blocks = &inspection.blocks;
+        assert!(blocks.len() >= 3);
+        assert!(blocks.iter().any(|block| block.block_type == BlockType::Stored));
+        assert_eq!(blocks.iter().filter(|block| block.final_block).count(), 1);
+
+        // small and empty inputs work too
+        for input in [&b""[..], b"a", b"abcabcabc"].iter() {
+            let options = DeflateOptions { level: 9, strategy: Strategy::Optimal };
+            let mut compressor = DeflateCompressor::with_options(Vec::new(), options);
+            compressor.write_all(input).unwrap();
+            let compressed = compressor.finish().unwrap();
+            let mut output = Vec::new();
+            crate::algorithms::inflate(&mut &compressed[..], &mut output).unwrap();
+            assert_eq!(&output[..], *input);
+        }
+    }
+}
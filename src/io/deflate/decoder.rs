@@ -0,0 +1,842 @@
+use std::io::{self, Read, Write};
+use crate::io::read_u8;
+use crate::huffman::Table;
+use super::ring_buffer::RingBuffer;
+use super::inspect::{BlockInfo, DeflateToken, TokenInfo};
+use super::*;
+
+pub const WINDOW_SIZE: usize = 32768;
+
+/// How many bits the primary lookup table of each code covers.
+const CODE_LENGTH_TABLE_BITS: usize = 7;
+const LITLEN_TABLE_BITS: usize = 9;
+const DISTANCE_TABLE_BITS: usize = 8;
+
+/// Deflate64 lets matches reach back 64 KiB.
+pub const DEFLATE64_WINDOW_SIZE: usize = 65536;
+
+/// Where a `Decoder` gets its input from.
+pub trait Source {
+    /// Returns the next byte of input, or `None` if there is none yet.
+    fn next_byte(&mut self) -> io::Result<Option<u8>>;
+
+    /// Moves up to `max` bytes of input into `window`, returning how many
+    /// were moved. Returns 0 only if there is no input yet.
+    fn copy_to<W: Window>(&mut self, window: &mut W, max: usize) -> io::Result<usize>;
+}
+
+/// Where a `Decoder` puts its output, which matches copy from.
+pub trait Window {
+    /// How much output the window holds before the oldest is overwritten,
+    /// and so the most which can be available at once.
+    fn capacity(&self) -> usize;
+
+    /// Appends `bytes`.
+    fn write_bytes(&mut self, bytes: &[u8]);
+
+    /// Appends `len` bytes copied from `distance` bytes back, which may
+    /// overlap the bytes being appended.
+    fn copy_match(&mut self, distance: usize, len: usize);
+}
+
+impl Window for RingBuffer {
+    fn capacity(&self) -> usize {
+        RingBuffer::capacity(self)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_all(bytes).unwrap();
+    }
+
+    fn copy_match(&mut self, distance: usize, len: usize) {
+        self.self_copy(distance, len).unwrap();
+    }
+}
+
+/// Input pushed in pieces; running out just means waiting for more.
+pub struct SliceSource<'a> {
+    pub data: &'a [u8],
+    pub position: usize,
+}
+
+impl<'a> Source for SliceSource<'a> {
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.data.get(self.position).cloned();
+        if byte.is_some() {
+            self.position += 1;
+        }
+        Ok(byte)
+    }
+
+    fn copy_to<W: Window>(&mut self, window: &mut W, max: usize) -> io::Result<usize> {
+        let count = std::cmp::min(max, self.data.len() - self.position);
+        window.write_bytes(&self.data[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Input pulled from a blocking reader, where running out means the stream
+/// was truncated.
+pub struct ReaderSource<'a, R: Read>(pub &'a mut R);
+
+impl<'a, R: Read> Source for ReaderSource<'a, R> {
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        read_u8(self.0).map(Some)
+    }
+
+    fn copy_to<W: Window>(&mut self, window: &mut W, max: usize) -> io::Result<usize> {
+        let mut buffer = [0; 4096];
+        let count = std::cmp::min(max, buffer.len());
+        self.0.read_exact(&mut buffer[..count])?;
+        window.write_bytes(&buffer[..count]);
+        Ok(count)
+    }
+}
+
+/// An error from a `Decoder`. Errors in the data are kept as they are, so
+/// decoding from a slice never allocates; they're only wrapped up in an
+/// `io::Error` by callers which read from a `Read`.
+#[derive(Debug)]
+pub enum DecodeError {
+    Invalid(DeflateDecompressorError),
+    Io(io::Error),
+}
+
+impl From<DeflateDecompressorError> for DecodeError {
+    fn from(e: DeflateDecompressorError) -> DecodeError {
+        DecodeError::Invalid(e)
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> DecodeError {
+        DecodeError::Io(e)
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(e: DecodeError) -> io::Error {
+        match e {
+            DecodeError::Invalid(e) => e.into(),
+            DecodeError::Io(e) => e,
+        }
+    }
+}
+
+enum Mode {
+    BlockHeader,
+    StoredHeader,
+    Stored(usize),
+    DynamicHeader,
+    CodeLengthCodes { hlit: usize, hdist: usize, hclen: usize, index: usize, lengths: [u8; 19] },
+    CodeLengths { hlit: usize, hdist: usize, count: usize },
+    Codes,
+    Copy { length: usize, distance: usize },
+    Done,
+    Failed(DeflateDecompressorError),
+}
+
+enum Step {
+    Continue,
+    Blocked,
+    Done,
+}
+
+#[derive(Clone, Copy)]
+enum Alphabet {
+    CodeLength,
+    Litlen,
+    Distance,
+}
+
+/// The inflate state machine shared by `inflate`, `DeflateDecompressor`,
+/// `Inflater` and `inflate_into`.
+///
+/// Output is decoded into the window, where it stays available to the
+/// caller until taken with `copy_out`. Input is pulled a byte at a time as
+/// it's needed, so fewer than 8 bits are ever left over in the bit buffer,
+/// and every state can be suspended when the source runs dry.
+///
+/// The codes are kept in fixed-size tables, so with a window which is the
+/// output itself, nothing is allocated.
+///
+/// The bit buffer is wider than deflate needs, as a Deflate64 length and
+/// distance pair can take up to 60 bits.
+pub struct Decoder<W: Window = RingBuffer> {
+    mode: Mode,
+    hold: u128,
+    bits: usize,
+    last_block: bool,
+    block_type: Option<BlockType>,
+    code_length_code: Table<u16>,
+    litlen_code: Table<u16>,
+    distance_code: Table<u16>,
+    // the code lengths of a dynamic block as they're read
+    lengths: [u8; 286 + 32],
+    window: W,
+    available: usize,
+    dictionary_len: u64,
+    options: InflateOptions,
+    blocks: u64,
+    total_in: u64,
+    total_out: u64,
+    pause_between_blocks: bool,
+    paused_before: u64,
+    inspection: Option<Vec<BlockInfo>>,
+    deflate64: bool,
+}
+
+impl Decoder {
+    pub fn new(options: InflateOptions) -> Decoder {
+        Decoder::with_window(options, RingBuffer::new(WINDOW_SIZE))
+    }
+
+    /// Creates a decoder for Deflate64, the variant of deflate with a 64 KiB
+    /// window which PKWARE calls enhanced deflating.
+    pub fn new_deflate64(options: InflateOptions) -> Decoder {
+        let mut decoder = Decoder::with_window(options, RingBuffer::new(DEFLATE64_WINDOW_SIZE));
+        decoder.deflate64 = true;
+        decoder
+    }
+
+    /// Creates a decoder which starts at a block boundary part way through a
+    /// stream, `bit_offset` bits in and `output_offset` bytes into the
+    /// output, with `window` holding the output just before it.
+    ///
+    /// If the block doesn't start on a byte boundary, `partial_byte` is the
+    /// byte it starts in, and the source should begin at the byte after.
+    pub fn resume(options: InflateOptions, bit_offset: u64, partial_byte: u8,
+    output_offset: u64, block_index: u64, window: &[u8]) -> Decoder {
+        assert!(window.len() as u64 <= output_offset && window.len() <= WINDOW_SIZE);
+        let mut decoder = Decoder::new(options);
+        decoder.restart_at(bit_offset, partial_byte);
+        decoder.total_out = output_offset;
+        decoder.blocks = block_index;
+        decoder.paused_before = block_index;
+        decoder.window.write_all(window).unwrap();
+        decoder
+    }
+
+    /// The last 32 KiB of output, or all of it if there's less.
+    pub fn window_snapshot(&self) -> Vec<u8> {
+        let len = std::cmp::min(self.total_out, WINDOW_SIZE as u64) as usize;
+        let mut snapshot = vec![0; len];
+        self.window.copy_out(&mut snapshot, len);
+        snapshot
+    }
+
+    /// Primes the window with a preset dictionary, which matches may refer
+    /// back into. Only the last 32 KiB of the dictionary can be reached.
+    ///
+    /// Must be called before anything is decoded.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        assert!(self.total_in == 0, "dictionary set after decoding started");
+        let start = dictionary.len().saturating_sub(WINDOW_SIZE);
+        self.window.write_all(&dictionary[start..]).unwrap();
+        self.dictionary_len = (dictionary.len() - start) as u64;
+    }
+
+    /// The oldest available bytes, as many as are contiguous in the window.
+    pub fn available_slice(&self) -> &[u8] {
+        self.window.contiguous(self.available)
+    }
+
+    /// Takes the oldest `buf.len()` available bytes.
+    pub fn copy_out(&mut self, buf: &mut [u8]) {
+        assert!(buf.len() <= self.available);
+        self.window.copy_out(buf, self.available);
+        self.available -= buf.len();
+    }
+}
+
+impl<W: Window> Decoder<W> {
+    /// Creates a decoder which decodes into `window`.
+    pub fn with_window(options: InflateOptions, window: W) -> Decoder<W> {
+        Decoder {
+            mode: Mode::BlockHeader,
+            hold: 0,
+            bits: 0,
+            last_block: false,
+            block_type: None,
+            code_length_code: Table::new(),
+            litlen_code: Table::new(),
+            distance_code: Table::new(),
+            lengths: [0; 286 + 32],
+            window,
+            available: 0,
+            dictionary_len: 0,
+            options,
+            blocks: 0,
+            total_in: 0,
+            total_out: 0,
+            pause_between_blocks: false,
+            paused_before: 0,
+            inspection: None,
+            deflate64: false,
+        }
+    }
+
+
+    /// Starts again at the block header `bit_offset` bits into the stream,
+    /// taking the rest of its byte from `partial_byte` as `resume` does. The
+    /// window keeps its contents, but output not yet copied out is dropped.
+    pub fn restart_at(&mut self, bit_offset: u64, partial_byte: u8) {
+        let skip = (bit_offset % 8) as usize;
+        self.hold = match skip {
+            0 => 0,
+            _ => (partial_byte >> skip) as u128,
+        };
+        self.bits = (8 - skip) % 8;
+        self.mode = Mode::BlockHeader;
+        self.last_block = false;
+        self.block_type = None;
+        self.available = 0;
+        self.total_in = bit_offset.div_ceil(8);
+    }
+
+    /// Makes `run` return at each block boundary, so the state there can be
+    /// looked at.
+    pub fn set_pause_between_blocks(&mut self, pause: bool) {
+        self.pause_between_blocks = pause;
+    }
+
+    /// Makes the decoder record the structure of every block it decodes.
+    pub fn set_inspect(&mut self, inspect: bool) {
+        self.inspection = if inspect { Some(Vec::new()) } else { None };
+    }
+
+    /// Takes the blocks recorded so far, if `set_inspect` was called.
+    pub fn take_inspection(&mut self) -> Vec<BlockInfo> {
+        self.inspection.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn inspected_block(&mut self) -> Option<&mut BlockInfo> {
+        self.inspection.as_mut().and_then(|blocks| blocks.last_mut())
+    }
+
+    fn record_token(&mut self, bit_offset: u64, token: DeflateToken) {
+        let output_offset = self.total_out;
+        if let Some(block) = self.inspected_block() {
+            block.tokens.push(TokenInfo { bit_offset, output_offset, token });
+        }
+    }
+
+    /// Whether the decoder is between two blocks, about to read a header.
+    pub fn at_block_boundary(&self) -> bool {
+        matches!(self.mode, Mode::BlockHeader)
+    }
+
+    /// How many blocks have been started.
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Decodes until at least `target` bytes are available, the source runs
+    /// dry, or the stream ends. Block headers are read even once the target
+    /// is met, and so is the end of a block once the window is full.
+    ///
+    /// Errors in the data are remembered, and returned again by every later
+    /// call.
+    pub fn run<S: Source>(&mut self, source: &mut S, target: usize) -> Result<(), DecodeError> {
+        assert!(target <= self.window.capacity(), "too many bytes requested at once");
+        loop {
+            if self.pause_between_blocks && self.at_block_boundary()
+            && self.blocks != 0 && self.paused_before != self.blocks {
+                self.paused_before = self.blocks;
+                return Ok(());
+            }
+
+            match self.step(source, target) {
+                Ok(Step::Continue) => {},
+                Ok(Step::Blocked) | Ok(Step::Done) => return Ok(()),
+                Err(e) => return Err(self.fail(e)),
+            }
+        }
+    }
+
+    /// Reads the header of the next block, up to the start of its data,
+    /// without decoding any of it. Returns false if the source runs dry
+    /// first.
+    pub fn read_block_header<S: Source>(&mut self, source: &mut S) -> Result<bool, DecodeError> {
+        let blocks = self.blocks;
+        loop {
+            match self.mode {
+                Mode::Stored(_) | Mode::Codes if self.blocks > blocks => return Ok(true),
+                Mode::Done => return Ok(false),
+                _ => {},
+            }
+            match self.step(source, 0) {
+                Ok(Step::Continue) => {},
+                Ok(_) => return Ok(false),
+                Err(e) => return Err(self.fail(e)),
+            }
+        }
+    }
+
+    /// Remembers an error from `step`, turning a reader which runs out part
+    /// way through into the stream being cut short.
+    fn fail(&mut self, e: DecodeError) -> DecodeError {
+        let error = match e {
+            DecodeError::Invalid(error) => error,
+            DecodeError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.error(InflateErrorKind::UnexpectedEOF)
+            },
+            DecodeError::Io(e) => return DecodeError::Io(e),
+        };
+        self.mode = Mode::Failed(error);
+        DecodeError::Invalid(error)
+    }
+
+    /// The type of the block being decoded, once its header has been read.
+    pub fn block_type(&self) -> Option<BlockType> {
+        self.block_type
+    }
+
+    /// How much data is left in the stored block being decoded.
+    pub fn stored_remaining(&self) -> Option<usize> {
+        match self.mode {
+            Mode::Stored(remaining) => Some(remaining),
+            _ => None,
+        }
+    }
+
+    /// Whether the literal/length code of the block being decoded has a code
+    /// for the end of the block.
+    pub fn can_end_block(&self) -> bool {
+        match self.block_type {
+            Some(BlockType::Dynamic) => self.lengths[256] != 0,
+            _ => true,
+        }
+    }
+
+    /// The error for a stream which ends where the decoder has got to.
+    pub fn truncated(&self) -> DeflateDecompressorError {
+        self.error(InflateErrorKind::UnexpectedEOF)
+    }
+
+    /// The number of decoded bytes not yet taken by `copy_out`.
+    pub fn available(&self) -> usize {
+        self.available
+    }
+
+    /// Drops the oldest `len` available bytes.
+    pub fn consume(&mut self, len: usize) {
+        assert!(len <= self.available);
+        self.available -= len;
+    }
+
+    /// Whether the end of the final block has been reached. There may still
+    /// be bytes available.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.mode, Mode::Done)
+    }
+
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// How far into the input decoding has got, in bits.
+    pub fn bit_position(&self) -> u64 {
+        self.total_in * 8 - self.bits as u64
+    }
+
+    /// The base and number of extra bits of length code `code`. Deflate64
+    /// gives the last code, which is 258 in deflate, 16 extra bits instead.
+    fn length_code(&self, code: usize) -> (usize, usize) {
+        match code {
+            28 if self.deflate64 => (3, 16),
+            _ => (LENGTH_BASE[code], LENGTH_EXTRA[code]),
+        }
+    }
+
+    /// The number of distance codes which may be used, the last two only
+    /// in Deflate64.
+    fn distance_codes(&self) -> usize {
+        if self.deflate64 { 32 } else { 30 }
+    }
+
+    fn error_at(&self, kind: InflateErrorKind, bit_offset: u64) -> DeflateDecompressorError {
+        DeflateDecompressorError {
+            kind,
+            bit_offset,
+            block_index: self.blocks.saturating_sub(1),
+            block_type: self.block_type,
+        }
+    }
+
+    fn error(&self, kind: InflateErrorKind) -> DeflateDecompressorError {
+        self.error_at(kind, self.bit_position())
+    }
+
+    fn limit_error(&self, limit: Limit) -> DecodeError {
+        self.error(InflateErrorKind::LimitExceeded(limit)).into()
+    }
+
+    /// Moves one byte of input into the bit buffer, if there is one.
+    fn pull_byte<S: Source>(&mut self, source: &mut S) -> Result<bool, DecodeError> {
+        match source.next_byte()? {
+            Some(byte) => {
+                self.hold |= (byte as u128) << self.bits;
+                self.bits += 8;
+                self.total_in += 1;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn need_bits<S: Source>(&mut self, count: usize, source: &mut S) -> Result<bool, DecodeError> {
+        while self.bits < count {
+            if !self.pull_byte(source)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns `count` bits from the bit buffer, which must hold them.
+    fn take_bits(&mut self, count: usize) -> usize {
+        let value = self.hold & ((1 << count) - 1);
+        self.hold >>= count;
+        self.bits -= count;
+        value as usize
+    }
+
+    fn code(&self, alphabet: Alphabet) -> &Table<u16> {
+        match alphabet {
+            Alphabet::CodeLength => &self.code_length_code,
+            Alphabet::Litlen => &self.litlen_code,
+            Alphabet::Distance => &self.distance_code,
+        }
+    }
+
+    /// Decodes the code which starts `skip` bits into the bit buffer,
+    /// without consuming anything. Returns `None` if more input is needed.
+    fn peek_code<S: Source>(&mut self, alphabet: Alphabet, skip: usize, source: &mut S)
+    -> Result<Option<(u16, usize)>, DecodeError> {
+        loop {
+            let available = self.bits.saturating_sub(skip);
+            let bits = (self.hold >> skip) as u32;
+            let code = self.code(alphabet);
+            match code.lookup(bits) {
+                Some((&symbol, length)) if length <= available => {
+                    return Ok(Some((symbol, length)));
+                },
+                None if available >= code.max_length() => {
+                    return Err(self.error(InflateErrorKind::BadHuffmanCodes).into());
+                },
+                _ => if !self.pull_byte(source)? {
+                    return Ok(None);
+                },
+            }
+        }
+    }
+
+    /// Checks that `count` more bytes of output, after reading `input` more
+    /// bytes of input, would be within the limits, and counts the output.
+    fn produce(&mut self, input: usize, count: usize) -> Result<(), DecodeError> {
+        let total_out = self.total_out + count as u64;
+        self.options.check_output(self.total_in + input as u64, total_out)
+            .map_err(|limit| self.limit_error(limit))?;
+        self.total_out = total_out;
+        self.available += count;
+        Ok(())
+    }
+
+    fn step<S: Source>(&mut self, source: &mut S, target: usize) -> Result<Step, DecodeError> {
+        match self.mode {
+            Mode::BlockHeader => {
+                if !self.need_bits(3, source)? {
+                    return Ok(Step::Blocked);
+                }
+                self.blocks += 1;
+                self.block_type = None;
+                self.options.check_blocks(self.blocks).map_err(|limit| self.limit_error(limit))?;
+                let position = self.bit_position();
+                self.last_block = self.take_bits(1) != 0;
+                self.block_type = match self.take_bits(2) {
+                    0 => Some(BlockType::Stored),
+                    1 => Some(BlockType::Fixed),
+                    2 => Some(BlockType::Dynamic),
+                    _ => return Err(self.error_at(InflateErrorKind::InvalidBType, position).into()),
+                };
+                if let Some(ref mut blocks) = self.inspection {
+                    let block_type = self.block_type.unwrap();
+                    blocks.push(BlockInfo::new(position, self.total_out, self.last_block, block_type));
+                }
+                self.mode = match self.block_type {
+                    Some(BlockType::Stored) => Mode::StoredHeader,
+                    Some(BlockType::Fixed) => {
+                        let mut lengths = [8; 288];
+                        lengths[144..256].fill(9);
+                        lengths[256..280].fill(7);
+                        self.litlen_code.set_lengths(0, &lengths, LITLEN_TABLE_BITS);
+                        self.distance_code.set_lengths(0, &[5; 32], DISTANCE_TABLE_BITS);
+                        Mode::Codes
+                    },
+                    _ => Mode::DynamicHeader,
+                };
+            },
+            Mode::StoredHeader => {
+                let partial = self.bits % 8;
+                self.take_bits(partial);
+                if !self.need_bits(32, source)? {
+                    return Ok(Step::Blocked);
+                }
+                let position = self.bit_position();
+                let len = self.take_bits(16);
+                let nlen = self.take_bits(16);
+                if len != !nlen & 0xffff {
+                    return Err(self.error_at(InflateErrorKind::NonMatchingLenNLen, position).into());
+                }
+                self.mode = Mode::Stored(len);
+            },
+            Mode::Stored(0) => {
+                self.end_block();
+            },
+            Mode::Stored(remaining) => {
+                // bits are only pulled as they're needed, so the bit buffer
+                // is empty, and the data comes straight from the source
+                let count = std::cmp::min(remaining, target.saturating_sub(self.available));
+                if count == 0 {
+                    return Ok(Step::Blocked);
+                }
+                self.options.check_output(self.total_in + count as u64,
+                    self.total_out + count as u64).map_err(|limit| self.limit_error(limit))?;
+                let count = source.copy_to(&mut self.window, count)?;
+                if count == 0 {
+                    return Ok(Step::Blocked);
+                }
+                self.total_in += count as u64;
+                self.produce(0, count)?;
+                self.mode = Mode::Stored(remaining - count);
+            },
+            Mode::DynamicHeader => {
+                if !self.need_bits(14, source)? {
+                    return Ok(Step::Blocked);
+                }
+                let position = self.bit_position();
+                let hlit = self.take_bits(5) + 257;
+                let hdist = self.take_bits(5) + 1;
+                let hclen = self.take_bits(4) + 4;
+                if hlit > 286 || hdist > self.distance_codes() {
+                    return Err(self.error_at(InflateErrorKind::BadHuffmanCodes, position).into());
+                }
+                self.mode = Mode::CodeLengthCodes { hlit, hdist, hclen, index: 0, lengths: [0; 19] };
+            },
+            Mode::CodeLengthCodes { hlit, hdist, hclen, mut index, mut lengths } => {
+                while index < hclen {
+                    if !self.need_bits(3, source)? {
+                        self.mode = Mode::CodeLengthCodes { hlit, hdist, hclen, index, lengths };
+                        return Ok(Step::Blocked);
+                    }
+                    lengths[SWIZZLE[index]] = self.take_bits(3) as u8;
+                    index += 1;
+                }
+
+                if !self.code_length_code.set_lengths(0, &lengths, CODE_LENGTH_TABLE_BITS) {
+                    return Err(self.error(InflateErrorKind::BadHuffmanCodes).into());
+                }
+                self.mode = Mode::CodeLengths { hlit, hdist, count: 0 };
+            },
+            Mode::CodeLengths { .. } => {
+                return self.read_code_lengths(source);
+            },
+            Mode::Codes => {
+                return self.read_codes(source, target);
+            },
+            Mode::Copy { length, distance } => {
+                let count = std::cmp::min(length, target.saturating_sub(self.available));
+                if count == 0 {
+                    return Ok(Step::Blocked);
+                }
+                self.produce(0, count)?;
+                self.window.copy_match(distance, count);
+                self.mode = match length - count {
+                    0 => Mode::Codes,
+                    length => Mode::Copy { length, distance },
+                };
+            },
+            Mode::Done => return Ok(Step::Done),
+            Mode::Failed(e) => return Err(e.into()),
+        }
+
+        Ok(Step::Continue)
+    }
+
+    fn read_code_lengths<S: Source>(&mut self, source: &mut S) -> Result<Step, DecodeError> {
+        let (hlit, hdist, mut count) = match self.mode {
+            Mode::CodeLengths { hlit, hdist, count } => (hlit, hdist, count),
+            _ => unreachable!(),
+        };
+        let complete = self.read_code_length_codes(hlit + hdist, &mut count, source)?;
+        if !complete {
+            self.mode = Mode::CodeLengths { hlit, hdist, count };
+            return Ok(Step::Blocked);
+        }
+
+        let lengths = &self.lengths[..hlit + hdist];
+        if let Some(block) = self.inspection.as_mut().and_then(|blocks| blocks.last_mut()) {
+            block.litlen_lengths = lengths[..hlit].iter().map(|&length| length as u32).collect();
+            block.distance_lengths = lengths[hlit..].iter().map(|&length| length as u32).collect();
+        }
+        if !self.litlen_code.set_lengths(0, &lengths[..hlit], LITLEN_TABLE_BITS)
+        || !self.distance_code.set_lengths(0, &lengths[hlit..], DISTANCE_TABLE_BITS) {
+            return Err(self.error(InflateErrorKind::BadHuffmanCodes).into());
+        }
+        self.mode = Mode::Codes;
+        Ok(Step::Continue)
+    }
+
+    /// Reads code lengths until there are `total` of them, returning false
+    /// if more input is needed first.
+    fn read_code_length_codes<S: Source>(&mut self, total: usize, count: &mut usize,
+    source: &mut S) -> Result<bool, DecodeError> {
+        while *count < total {
+            let (symbol, code_length) = match self.peek_code(Alphabet::CodeLength, 0, source)? {
+                Some(code) => code,
+                None => return Ok(false),
+            };
+
+            let (extra_bits, base, repeated) = match symbol {
+                0..=15 => {
+                    self.take_bits(code_length);
+                    self.lengths[*count] = symbol as u8;
+                    *count += 1;
+                    continue;
+                },
+                16 => match *count {
+                    0 => return Err(self.error(InflateErrorKind::RepeatWithoutPrevious).into()),
+                    _ => (2, 3, self.lengths[*count - 1]),
+                },
+                17 => (3, 3, 0),
+                18 => (7, 11, 0),
+                _ => return Err(self.error(InflateErrorKind::BadHuffmanCodes).into()),
+            };
+
+            if !self.need_bits(code_length + extra_bits, source)? {
+                return Ok(false);
+            }
+            let position = self.bit_position();
+            self.take_bits(code_length);
+            let repeat = base + self.take_bits(extra_bits);
+            if *count + repeat > total {
+                return Err(self.error_at(InflateErrorKind::TooManyCodeLengths, position).into());
+            }
+            self.lengths[*count..*count + repeat].fill(repeated);
+            *count += repeat;
+        }
+
+        Ok(true)
+    }
+
+    fn read_codes<S: Source>(&mut self, source: &mut S, target: usize) -> Result<Step, DecodeError> {
+        loop {
+            // once the window is full, nothing more can be decoded until some
+            // of it is taken, but the end of the block can still be read, so
+            // that a decoder whose window is the whole output can tell when
+            // it's finished
+            let full = self.available >= target;
+            if full && self.available < self.window.capacity() {
+                return Ok(Step::Blocked);
+            }
+
+            let (symbol, litlen_length) = match self.peek_code(Alphabet::Litlen, 0, source)? {
+                Some(code) => code,
+                None => return Ok(Step::Blocked),
+            };
+            if full && symbol != 256 {
+                return Ok(Step::Blocked);
+            }
+
+            match symbol {
+                0..=255 => {
+                    self.record_token(self.bit_position(), DeflateToken::Literal(symbol as u8));
+                    self.produce(0, 1)?;
+                    self.take_bits(litlen_length);
+                    self.window.write_bytes(&[symbol as u8]);
+                },
+                256 => {
+                    self.take_bits(litlen_length);
+                    self.end_block();
+                    return Ok(Step::Continue);
+                },
+                257..=285 => {
+                    // the whole length/distance pair is read at once, so it
+                    // never has to be resumed part way through
+                    let (length_base, length_extra) = self.length_code(symbol as usize - 257);
+                    let length_bits = litlen_length + length_extra;
+                    if !self.need_bits(length_bits, source)? {
+                        return Ok(Step::Blocked);
+                    }
+
+                    let (distance_code, distance_length) =
+                    match self.peek_code(Alphabet::Distance, length_bits, source)? {
+                        Some(code) => code,
+                        None => return Ok(Step::Blocked),
+                    };
+                    let distance_code = distance_code as usize;
+                    if distance_code >= self.distance_codes() {
+                        return Err(self.error(InflateErrorKind::BadHuffmanCodes).into());
+                    }
+
+                    let total_bits = length_bits + distance_length + DIST_EXTRA[distance_code];
+                    if !self.need_bits(total_bits, source)? {
+                        return Ok(Step::Blocked);
+                    }
+
+                    let position = self.bit_position();
+                    self.take_bits(litlen_length);
+                    let length = length_base + self.take_bits(length_extra);
+                    self.take_bits(distance_length);
+                    let distance = DIST_BASE[distance_code] + self.take_bits(DIST_EXTRA[distance_code]);
+
+                    if distance as u64 > self.total_out + self.dictionary_len {
+                        let kind = InflateErrorKind::DistanceTooFarBack;
+                        return Err(self.error_at(kind, position).into());
+                    }
+
+                    let token = DeflateToken::Match { length: length as u16, distance: distance as u16 };
+                    self.record_token(position, token);
+                    self.mode = Mode::Copy { length, distance };
+                    return Ok(Step::Continue);
+                },
+                _ => return Err(self.error(InflateErrorKind::InvalidLitLenCode).into()),
+            }
+        }
+    }
+
+    fn end_block(&mut self) {
+        let (bit_position, total_out) = (self.bit_position(), self.total_out);
+        if let Some(block) = self.inspected_block() {
+            block.bit_len = bit_position - block.bit_offset;
+            block.output_len = total_out - block.output_offset;
+        }
+        self.mode = match self.last_block {
+            true => Mode::Done,
+            false => Mode::BlockHeader,
+        };
+    }
+}
+
+/// Decompresses all of a deflate stream from `reader`, appending it to
+/// `output`.
+pub fn inflate_to_vec<R: Read>(reader: &mut R, output: &mut Vec<u8>, options: InflateOptions,
+dictionary: &[u8]) -> io::Result<()> {
+    let mut decoder = Decoder::new(options);
+    decoder.set_dictionary(dictionary);
+    loop {
+        decoder.run(&mut ReaderSource(reader), WINDOW_SIZE)?;
+        let start = output.len();
+        output.resize(start + decoder.available(), 0);
+        decoder.copy_out(&mut output[start..]);
+        if decoder.is_finished() {
+            return Ok(());
+        }
+    }
+}
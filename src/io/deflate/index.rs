@@ -0,0 +1,484 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use crate::io::{read_u8, read_u32_le, read_u64_le, write_u8, write_u32_le, write_u64_le};
+use super::decoder::{Decoder, ReaderSource, WINDOW_SIZE};
+use super::{DeflateDecompressor, InflateOptions};
+
+const MAGIC: [u8; 4] = *b"DFIX";
+const VERSION: u8 = 1;
+
+/// A place in a deflate stream where decompression can start, without
+/// decompressing anything before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Where the block starting here begins, in bits from the start of the
+    /// deflate stream.
+    pub bit_offset: u64,
+    /// How much output comes before this point.
+    pub output_offset: u64,
+    /// The index of the block starting here.
+    pub block_index: u64,
+    /// The last 32 KiB of output before this point, which matches in the
+    /// following blocks may refer back to.
+    pub window: Vec<u8>,
+}
+
+/// An index of checkpoints into a deflate stream, for reading from anywhere
+/// in it with `SeekableDeflateReader`.
+///
+/// Checkpoints can only be made at block boundaries, so they are made at the
+/// first boundary after each `spacing` bytes of output. Each one holds a
+/// 32 KiB window, so the index takes about 32 KiB per checkpoint.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{GzEncoder, DeflateIndex, SeekableDeflateReader};
+/// # use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+/// let input: Vec<u8> = (0..1_000_000u32).map(|i| (i % 1000 / 10) as u8).collect();
+/// let mut encoder = GzEncoder::new(Vec::new()).unwrap();
+/// encoder.write_all(&input).unwrap();
+/// let compressed = encoder.finish().unwrap();
+///
+/// let index = DeflateIndex::build_gzip(Cursor::new(&compressed), 100_000).unwrap();
+/// let mut reader = SeekableDeflateReader::new(Cursor::new(&compressed), index);
+/// reader.seek(SeekFrom::Start(654_321)).unwrap();
+/// let mut buffer = [0; 100];
+/// reader.read_exact(&mut buffer).unwrap();
+/// assert_eq!(&buffer[..], &input[654_321..654_421]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeflateIndex {
+    start: u64,
+    uncompressed_size: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl DeflateIndex {
+    /// Reads a whole deflate stream, starting at the current position of
+    /// `reader`, making a checkpoint after every `spacing` bytes of output.
+    /// The reader is buffered internally.
+    pub fn build<R: Read + Seek>(reader: R, spacing: u64) -> io::Result<DeflateIndex> {
+        DeflateIndex::build_buffered(&mut BufReader::new(reader), spacing)
+    }
+
+    /// Like `build`, but for the first member of a gzip file. The header is
+    /// skipped, and the trailer isn't checked.
+    pub fn build_gzip<R: Read + Seek>(reader: R, spacing: u64) -> io::Result<DeflateIndex> {
+        let mut reader = BufReader::new(reader);
+        if crate::io::gzip::read_header(&mut reader)?.is_none() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        DeflateIndex::build_buffered(&mut reader, spacing)
+    }
+
+    fn build_buffered<R: Read + Seek>(reader: &mut BufReader<R>, spacing: u64)
+    -> io::Result<DeflateIndex> {
+        // the buffered reader's position allows for what it has buffered,
+        // and everything after comes from the decoder's own count of bits
+        let start = reader.stream_position()?;
+        let mut decoder = Decoder::new(InflateOptions::default());
+        decoder.set_pause_between_blocks(true);
+
+        // the start of the stream is always a checkpoint
+        let mut checkpoints = vec![Checkpoint {
+            bit_offset: 0,
+            output_offset: 0,
+            block_index: 0,
+            window: Vec::new(),
+        }];
+        let mut discard = vec![0; WINDOW_SIZE];
+        while !decoder.is_finished() {
+            decoder.run(&mut ReaderSource(reader), WINDOW_SIZE)?;
+            let available = decoder.available();
+            decoder.copy_out(&mut discard[..available]);
+
+            let previous = checkpoints.last().unwrap().output_offset;
+            if decoder.at_block_boundary() && decoder.total_out() - previous >= spacing {
+                checkpoints.push(Checkpoint {
+                    bit_offset: decoder.bit_position(),
+                    output_offset: decoder.total_out(),
+                    block_index: decoder.blocks(),
+                    window: decoder.window_snapshot(),
+                });
+            }
+        }
+
+        Ok(DeflateIndex { start, uncompressed_size: decoder.total_out(), checkpoints })
+    }
+
+    /// The position of the start of the deflate stream in the data indexed.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// The length of the decompressed data.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// The last checkpoint at or before `offset` in the output.
+    pub fn checkpoint_before(&self, offset: u64) -> &Checkpoint {
+        let after = self.checkpoints.iter().position(|c| c.output_offset > offset);
+        &self.checkpoints[after.unwrap_or(self.checkpoints.len()) - 1]
+    }
+
+    /// Writes the index, to be read back by `read_from`. All of the numbers
+    /// are little endian.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        write_u8(writer, VERSION)?;
+        write_u64_le(writer, self.start)?;
+        write_u64_le(writer, self.uncompressed_size)?;
+        write_u64_le(writer, self.checkpoints.len() as u64)?;
+        for checkpoint in self.checkpoints.iter() {
+            write_u64_le(writer, checkpoint.bit_offset)?;
+            write_u64_le(writer, checkpoint.output_offset)?;
+            write_u64_le(writer, checkpoint.block_index)?;
+            write_u32_le(writer, checkpoint.window.len() as u32)?;
+            writer.write_all(&checkpoint.window)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an index written by `write_to`.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<DeflateIndex> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(DeflateIndexError::BadMagic.into());
+        }
+
+        let version = read_u8(reader)?;
+        if version != VERSION {
+            return Err(DeflateIndexError::UnsupportedVersion(version).into());
+        }
+
+        let start = read_u64_le(reader)?;
+        let uncompressed_size = read_u64_le(reader)?;
+        let count = read_u64_le(reader)?;
+        let mut checkpoints: Vec<Checkpoint> = Vec::new();
+        for _ in 0..count {
+            let bit_offset = read_u64_le(reader)?;
+            let output_offset = read_u64_le(reader)?;
+            let block_index = read_u64_le(reader)?;
+            let window_len = read_u32_le(reader)? as u64;
+
+            // checkpoints must be in order, each with as much window as the
+            // output before it allows
+            let in_order = match checkpoints.last() {
+                Some(previous) => output_offset > previous.output_offset,
+                None => bit_offset == 0 && output_offset == 0,
+            };
+            if !in_order || output_offset > uncompressed_size
+            || window_len != std::cmp::min(output_offset, WINDOW_SIZE as u64) {
+                return Err(DeflateIndexError::InvalidCheckpoint.into());
+            }
+
+            let mut window = vec![0; window_len as usize];
+            reader.read_exact(&mut window)?;
+            checkpoints.push(Checkpoint { bit_offset, output_offset, block_index, window });
+        }
+
+        if checkpoints.is_empty() {
+            return Err(DeflateIndexError::InvalidCheckpoint.into());
+        }
+
+        Ok(DeflateIndex { start, uncompressed_size, checkpoints })
+    }
+}
+
+/// Reads the decompressed data of an indexed deflate stream, with seeking
+/// to anywhere in it.
+///
+/// Seeking starts decompressing again from the nearest checkpoint before
+/// the new position, unless going on from where it is would be quicker, so
+/// reaching any position takes decompressing at most about `spacing` bytes.
+/// The reader is buffered internally.
+pub struct SeekableDeflateReader<R: Read + Seek> {
+    decompressor: DeflateDecompressor<BufReader<R>>,
+    index: DeflateIndex,
+    // where the decompressor is in the output, or None if it has to be
+    // restarted from a checkpoint
+    decoded: Option<u64>,
+    position: u64,
+}
+
+impl<R: Read + Seek> SeekableDeflateReader<R> {
+    /// Creates a reader for the stream in `reader` which `index` was built
+    /// from. Nothing is read until the first call to `read`.
+    pub fn new(reader: R, index: DeflateIndex) -> SeekableDeflateReader<R> {
+        let decoder = Decoder::new(InflateOptions::default());
+        SeekableDeflateReader {
+            decompressor: DeflateDecompressor { reader: BufReader::new(reader), decoder },
+            index,
+            decoded: None,
+            position: 0,
+        }
+    }
+
+    pub fn index(&self) -> &DeflateIndex {
+        &self.index
+    }
+
+    pub fn into_inner(self) -> R {
+        self.decompressor.reader.into_inner()
+    }
+
+    /// Restarts the decompressor at the last checkpoint before `position`.
+    fn resume(&mut self) -> io::Result<()> {
+        self.decoded = None;
+        let checkpoint = self.index.checkpoint_before(self.position);
+        let reader = &mut self.decompressor.reader;
+        reader.seek(SeekFrom::Start(self.index.start + checkpoint.bit_offset / 8))?;
+        let partial_byte = match checkpoint.bit_offset % 8 {
+            0 => 0,
+            _ => read_u8(reader)?,
+        };
+
+        self.decompressor.decoder = Decoder::resume(InflateOptions::default(),
+            checkpoint.bit_offset, partial_byte, checkpoint.output_offset,
+            checkpoint.block_index, &checkpoint.window);
+        self.decoded = Some(checkpoint.output_offset);
+        Ok(())
+    }
+
+    /// Gets the decompressor to `position`, restarting from a checkpoint if
+    /// that's quicker than reading on.
+    fn reposition(&mut self) -> io::Result<()> {
+        let checkpoint = self.index.checkpoint_before(self.position).output_offset;
+        match self.decoded {
+            Some(decoded) if decoded <= self.position && decoded >= checkpoint => {},
+            _ => self.resume()?,
+        }
+
+        let mut buffer = [0; 4096];
+        while let Some(decoded) = self.decoded {
+            let remaining = self.position - decoded;
+            if remaining == 0 {
+                break;
+            }
+
+            let len = std::cmp::min(remaining, buffer.len() as u64) as usize;
+            match self.decompressor.read(&mut buffer[..len]) {
+                // past the end of the data
+                Ok(0) => break,
+                Ok(n) => self.decoded = Some(decoded + n as u64),
+                Err(e) => {
+                    self.decoded = None;
+                    return Err(e);
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableDeflateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.decoded != Some(self.position) {
+            self.reposition()?;
+            if self.decoded != Some(self.position) {
+                return Ok(0);
+            }
+        }
+
+        match self.decompressor.read(buf) {
+            Ok(n) => {
+                self.position += n as u64;
+                self.decoded = Some(self.position);
+                Ok(n)
+            },
+            Err(e) => {
+                self.decoded = None;
+                Err(e)
+            },
+        }
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableDeflateReader<R> {
+    /// Seeking past the end is allowed, and reads from there return nothing.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.index.uncompressed_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateIndexError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    InvalidCheckpoint,
+}
+
+impl std::fmt::Display for DeflateIndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::DeflateIndexError::*;
+        match self {
+            BadMagic => write!(f, "Not a deflate index"),
+            UnsupportedVersion(version) => write!(f, "Unsupported deflate index version {}", version),
+            InvalidCheckpoint => write!(f, "Invalid checkpoint in deflate index"),
+        }
+    }
+}
+
+impl std::error::Error for DeflateIndexError {}
+
+impl From<DeflateIndexError> for io::Error {
+    fn from(e: DeflateIndexError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_seeking() {
+        use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+        use crate::io::{DeflateCompressor, DeflateIndex, SeekableDeflateReader};
+        use crate::io::deflate::tests::noisy_input;
+        use crate::random::MT19937_32;
+
+        let mut gen = MT19937_32::from_seed(3);
+        let input = noisy_input(&mut gen, 600_000, 4000, 500, b"abcd");
+
+        // some junk before the stream, as if it was inside another file
+        let mut data = b"junk".to_vec();
+        let mut compressor = DeflateCompressor::new(&mut data);
+        compressor.write_all(&input).unwrap();
+        compressor.finish().unwrap();
+
+        let mut cursor = Cursor::new(&data);
+        cursor.seek(SeekFrom::Start(4)).unwrap();
+        let index = DeflateIndex::build(cursor, 100_000).unwrap();
+        assert_eq!(index.start(), 4);
+        assert_eq!(index.uncompressed_size(), input.len() as u64);
+        assert!(index.checkpoints().len() >= 4);
+        assert!(index.checkpoints().iter().any(|c| c.bit_offset % 8 != 0));
+        for checkpoint in index.checkpoints() {
+            let end = checkpoint.output_offset as usize;
+            assert_eq!(checkpoint.window, &input[end.saturating_sub(32768)..end]);
+        }
+
+        let mut serialized = Vec::new();
+        index.write_to(&mut serialized).unwrap();
+        let index = DeflateIndex::read_from(&mut &serialized[..]).unwrap();
+
+        let mut reader = SeekableDeflateReader::new(Cursor::new(&data), index);
+        let mut buffer = vec![0; 5000];
+        for _ in 0..50 {
+            let position = gen.generate() as usize % (input.len() + 1000);
+            assert_eq!(reader.seek(SeekFrom::Start(position as u64)).unwrap(), position as u64);
+            let mut read = 0;
+            loop {
+                match reader.read(&mut buffer[read..]).unwrap() {
+                    0 => break,
+                    n => read += n,
+                }
+            }
+            let end = std::cmp::min(position + 5000, input.len());
+            let expected = input.get(position..end).unwrap_or(&[]);
+            assert_eq!(&buffer[..read], expected);
+        }
+
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &input[input.len() - 10..]);
+        assert!(reader.seek(SeekFrom::Current(-(input.len() as i64) - 1)).is_err());
+    }
+
+    #[test]
+    fn test_file() {
+        use std::io::{Read, Seek, SeekFrom, Write};
+        use crate::io::{DeflateIndex, GzEncoder, SeekableDeflateReader};
+        use crate::io::deflate::tests::noisy_input;
+
+        // counts the reads which reach the file
+        struct CountingFile {
+            file: std::fs::File,
+            reads: usize,
+        }
+
+        impl Read for CountingFile {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.reads += 1;
+                self.file.read(buf)
+            }
+        }
+
+        impl Seek for CountingFile {
+            fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+                self.file.seek(pos)
+            }
+        }
+
+        let mut gen = crate::random::MT19937_32::from_seed(5);
+        let input = noisy_input(&mut gen, 400_000, 3000, 300, b"abcd");
+        let mut encoder = GzEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(&input).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let path = std::env::temp_dir().join(format!("stdex-index-{}.gz", std::process::id()));
+        std::fs::write(&path, &compressed).unwrap();
+        let open = || CountingFile { file: std::fs::File::open(&path).unwrap(), reads: 0 };
+
+        let mut file = open();
+        let index = DeflateIndex::build_gzip(&mut file, 50_000).unwrap();
+        assert!(file.reads < compressed.len() / 1000);
+        assert_eq!(index.uncompressed_size(), input.len() as u64);
+        assert!(index.checkpoints().iter().any(|c| c.bit_offset % 8 != 0));
+
+        let mut reader = SeekableDeflateReader::new(open(), index);
+        let mut buffer = [0; 1000];
+        for &position in &[300_000, 123_456, 0, 399_000] {
+            reader.seek(SeekFrom::Start(position as u64)).unwrap();
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer[..], &input[position..position + 1000]);
+        }
+        let reads = reader.into_inner().reads;
+        std::fs::remove_file(&path).unwrap();
+        assert!(reads < compressed.len() / 1000);
+    }
+
+    #[test]
+    fn test_bad_index() {
+        use crate::io::{DeflateIndex, DeflateIndexError};
+
+        let error = |data: &[u8]| {
+            let e = DeflateIndex::read_from(&mut &data[..]).unwrap_err();
+            *e.get_ref().unwrap().downcast_ref::<DeflateIndexError>().unwrap()
+        };
+
+        assert_eq!(error(b"DFIY\x01"), DeflateIndexError::BadMagic);
+        assert_eq!(error(b"DFIX\x02"), DeflateIndexError::UnsupportedVersion(2));
+
+        let mut data = b"DFIX\x01".to_vec();
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&[0; 8]);
+        assert_eq!(error(&data), DeflateIndexError::InvalidCheckpoint);
+
+        // a first checkpoint which isn't at the start
+        data.truncate(21);
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&3u64.to_le_bytes());
+        data.extend_from_slice(&[0; 12]);
+        assert_eq!(error(&data), DeflateIndexError::InvalidCheckpoint);
+    }
+}
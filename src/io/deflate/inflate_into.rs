@@ -0,0 +1,102 @@
+use super::decoder::{DecodeError, Decoder, SliceSource, Window};
+use super::{InflateIntoError, InflateOptions};
+
+/// A window which is the output itself, so matches copy from what's already
+/// been written to it.
+struct OutputWindow<'a> {
+    output: &'a mut [u8],
+    written: usize,
+}
+
+impl<'a> Window for OutputWindow<'a> {
+    fn capacity(&self) -> usize {
+        self.output.len()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.output[self.written..self.written + bytes.len()].copy_from_slice(bytes);
+        self.written += bytes.len();
+    }
+
+    fn copy_match(&mut self, distance: usize, len: usize) {
+        // byte by byte, as the match may overlap what it copies
+        for i in self.written..self.written + len {
+            self.output[i] = self.output[i - distance];
+        }
+        self.written += len;
+    }
+}
+
+/// Decompresses the deflate stream at the start of `input` into `output`,
+/// returning the length of the output. The output is the decoder's window,
+/// and its codes are kept in fixed-size tables, so nothing is allocated.
+pub fn inflate_into(input: &[u8], output: &mut [u8]) -> Result<usize, InflateIntoError> {
+    let capacity = output.len();
+    let window = OutputWindow { output, written: 0 };
+    let mut decoder = Decoder::with_window(InflateOptions::default(), window);
+    let mut source = SliceSource { data: input, position: 0 };
+    match decoder.run(&mut source, capacity) {
+        Ok(()) => {},
+        Err(DecodeError::Invalid(e)) => return Err(InflateIntoError::Invalid(e)),
+        Err(DecodeError::Io(_)) => unreachable!("reading from a slice cannot fail"),
+    }
+
+    let written = decoder.total_out() as usize;
+    if decoder.is_finished() {
+        Ok(written)
+    } else if written == capacity {
+        Err(InflateIntoError::OutputTooSmall(written))
+    } else {
+        Err(InflateIntoError::Invalid(decoder.truncated()))
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_inflate_into() {
+        use std::io::Write;
+        use crate::io::{DeflateCompressor, DeflateOptions, Strategy, InflateIntoError};
+        use crate::io::InflateErrorKind;
+        use crate::io::deflate::tests::noisy_input;
+        use crate::algorithms::inflate_into;
+
+        let mut gen = crate::random::MT19937_32::from_seed(13);
+        let input = noisy_input(&mut gen, 100_000, 1000, 200, b"abcd");
+
+        // stored, fixed and dynamic blocks
+        for &(level, strategy) in &[(0, Strategy::Default), (6, Strategy::Fixed), (6, Strategy::Default)] {
+            let mut compressor = DeflateCompressor::with_options(Vec::new(), DeflateOptions { level, strategy });
+            compressor.write_all(&input).unwrap();
+            let mut compressed = compressor.finish().unwrap();
+            // trailing data is left alone
+            compressed.extend_from_slice(b"trailer");
+
+            let mut output = vec![0; input.len()];
+            assert_eq!(inflate_into(&compressed, &mut output), Ok(input.len()));
+            assert_eq!(output, input);
+
+            // too small an output is filled with the start of the stream
+            for &len in &[input.len() - 1, 12_345, 0] {
+                let mut output = vec![0; len];
+                assert_eq!(inflate_into(&compressed, &mut output), Err(InflateIntoError::OutputTooSmall(len)));
+                assert_eq!(output, &input[..len]);
+            }
+
+            let e = match inflate_into(&compressed[..compressed.len() / 2], &mut output) {
+                Err(InflateIntoError::Invalid(e)) => e,
+                result => panic!("{:?}", result),
+            };
+            assert_eq!(e.kind, InflateErrorKind::UnexpectedEOF);
+        }
+
+        let e = match inflate_into(&[0x07], &mut [0; 10]) {
+            Err(InflateIntoError::Invalid(e)) => e,
+            result => panic!("{:?}", result),
+        };
+        assert_eq!(e.kind, InflateErrorKind::InvalidBType);
+        assert_eq!(e.bit_offset, 0);
+
+        // an empty stream fits an empty output
+        assert_eq!(inflate_into(&[0x03, 0x00], &mut []), Ok(0));
+    }
+}
@@ -0,0 +1,268 @@
+use super::decoder::{Decoder, SliceSource, WINDOW_SIZE};
+use super::*;
+
+/// What an `Inflater` was able to do with the input and output it was
+/// given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// How many bytes of input were used. Bytes which were used are never
+    /// needed again, even if the data they hold hasn't been output yet.
+    pub consumed: usize,
+    /// How many bytes were written to the output buffer.
+    pub produced: usize,
+    pub status: InflateStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InflateStatus {
+    /// Progress was made, and the stream has not ended.
+    Ok,
+    /// The end of the final block was reached, and all of the output has
+    /// been returned. Any input past `consumed` follows the deflate stream.
+    StreamEnd,
+    /// No progress was possible, because the input was exhausted or the
+    /// output buffer was full. This is not an error; call `feed` again with
+    /// more input or more room for output.
+    BufError,
+}
+
+/// Decompresses a deflate stream which arrives in pieces, without blocking.
+///
+/// Each call to `feed` decompresses as much as it can from the input given
+/// into the output buffer, like zlib's `inflate()`. Input may be split
+/// anywhere, even in the middle of a code; the partial bits are kept until
+/// the rest arrives. Bytes are only consumed as they are needed, so once the
+/// stream ends, whatever follows it is left unconsumed.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{Inflater, InflateStatus};
+/// let compressed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0xff, 0xff];
+/// let mut inflater = Inflater::new();
+/// let mut output = Vec::new();
+/// let mut buffer = [0; 2];
+/// let mut input = &compressed[..];
+/// loop {
+///     // feed the input a byte at a time
+///     let progress = inflater.feed(&input[..1], &mut buffer).unwrap();
+///     input = &input[progress.consumed..];
+///     output.extend_from_slice(&buffer[..progress.produced]);
+///     if progress.status == InflateStatus::StreamEnd {
+///         break;
+///     }
+/// }
+/// assert_eq!(output, b"hello");
+/// assert_eq!(input, [0xff, 0xff]);
+/// ```
+pub struct Inflater {
+    decoder: Decoder,
+}
+
+impl Inflater {
+    pub fn new() -> Inflater {
+        Inflater::with_options(InflateOptions::default())
+    }
+
+    /// Creates an inflater which fails if the stream exceeds the limits in
+    /// `options`.
+    pub fn with_options(options: InflateOptions) -> Inflater {
+        Inflater { decoder: Decoder::new(options) }
+    }
+
+    /// Decompresses from `input` into `output`, stopping when the input is
+    /// exhausted, the output is full, or the stream ends.
+    ///
+    /// An error in the stream is only returned by a call which has nothing
+    /// else to report. If input was consumed or output produced before it,
+    /// that progress is returned as usual, along with whatever output there
+    /// is room for, and the error comes from the next call. Once an error has
+    /// been returned, every later call returns it again.
+    pub fn feed(&mut self, input: &[u8], output: &mut [u8]) -> std::io::Result<Progress> {
+        let mut source = SliceSource { data: input, position: 0 };
+        let mut produced = 0;
+        loop {
+            let room = output.len() - produced;
+            let result = self.decoder.run(&mut source, std::cmp::min(room, WINDOW_SIZE));
+            let count = std::cmp::min(self.decoder.available(), room);
+            self.decoder.copy_out(&mut output[produced..produced + count]);
+            produced += count;
+            if let Err(e) = result {
+                // the decoder remembers the error, so it's returned again
+                // by the next call
+                if source.position == 0 && produced == 0 {
+                    return Err(e.into());
+                }
+                break;
+            }
+            if count == 0 {
+                break;
+            }
+        }
+
+        let consumed = source.position;
+
+        let status = if self.is_finished() {
+            InflateStatus::StreamEnd
+        } else if consumed == 0 && produced == 0 {
+            InflateStatus::BufError
+        } else {
+            InflateStatus::Ok
+        };
+
+        Ok(Progress { consumed, produced, status })
+    }
+
+    /// Whether the end of the final block has been reached, and all of the
+    /// output has been returned.
+    pub fn is_finished(&self) -> bool {
+        self.decoder.is_finished() && self.decoder.available() == 0
+    }
+
+    /// The number of input bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.total_in()
+    }
+
+    /// The number of bytes output so far.
+    pub fn total_out(&self) -> u64 {
+        self.decoder.total_out() - self.decoder.available() as u64
+    }
+}
+
+impl Default for Inflater {
+    fn default() -> Inflater {
+        Inflater::new()
+    }
+}
+
+mod tests {
+    #[cfg(test)]
+    fn compress(input: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        crate::algorithms::deflate(input, &mut compressed);
+        compressed
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        use crate::io::{Inflater, InflateStatus};
+        use crate::io::deflate::tests::noisy_input;
+        use crate::random::MT19937_32;
+
+        let mut gen = MT19937_32::from_seed(7);
+        let input = noisy_input(&mut gen, 200_000, 3000, 1000, b"ab");
+        let mut compressed = compress(&input);
+        compressed.extend_from_slice(b"trailer");
+
+        let mut inflater = Inflater::new();
+        let mut output = Vec::new();
+        let mut buffer = [0; 13];
+        let mut position = 0;
+        loop {
+            let end = std::cmp::min(position + 1, compressed.len());
+            let progress = inflater.feed(&compressed[position..end], &mut buffer).unwrap();
+            position += progress.consumed;
+            output.extend_from_slice(&buffer[..progress.produced]);
+            if progress.status == InflateStatus::StreamEnd {
+                break;
+            }
+            assert_eq!(progress.status, InflateStatus::Ok);
+        }
+
+        assert_eq!(output, input);
+        assert_eq!(&compressed[position..], b"trailer");
+        assert_eq!(inflater.total_in(), position as u64);
+        assert_eq!(inflater.total_out(), input.len() as u64);
+        assert!(inflater.is_finished());
+    }
+
+    #[test]
+    fn test_buf_error() {
+        use crate::io::{Inflater, InflateStatus, Progress};
+
+        let compressed = compress(b"hello hello hello");
+        let mut inflater = Inflater::new();
+        let mut buffer = [0; 64];
+        let progress = inflater.feed(&[], &mut buffer).unwrap();
+        assert_eq!(progress, Progress { consumed: 0, produced: 0, status: InflateStatus::BufError });
+
+        // with no room for output, only the block header can be read
+        let progress = inflater.feed(&compressed, &mut []).unwrap();
+        assert_eq!(progress.status, InflateStatus::Ok);
+        let position = progress.consumed;
+        let progress = inflater.feed(&compressed[position..], &mut []).unwrap();
+        assert_eq!(progress.status, InflateStatus::BufError);
+
+        let progress = inflater.feed(&compressed[position..], &mut buffer).unwrap();
+        assert_eq!(progress.status, InflateStatus::StreamEnd);
+        assert_eq!(&buffer[..progress.produced], b"hello hello hello");
+        assert_eq!(position + progress.consumed, compressed.len());
+    }
+
+    #[test]
+    fn test_stored_and_errors() {
+        use crate::io::{Inflater, InflateStatus, Progress};
+
+        // a stored block, then a fixed block with an invalid distance
+        let stored = [0x00, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        let mut inflater = Inflater::new();
+        let mut buffer = [0; 8];
+        let progress = inflater.feed(&stored, &mut buffer).unwrap();
+        assert_eq!(progress.status, InflateStatus::Ok);
+        assert_eq!(&buffer[..progress.produced], b"abc");
+
+        // length 3 (code 257, 0000001), distance 5 (code 4, 00100, 1 extra bit)
+        let far = [0x03, 0x12];
+        let progress = inflater.feed(&far, &mut buffer).unwrap();
+        assert_eq!(progress, Progress { consumed: 2, produced: 0, status: InflateStatus::Ok });
+        let e = inflater.feed(&[], &mut buffer).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        let e = inflater.feed(&far, &mut buffer).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(inflater.total_in(), stored.len() as u64 + 2);
+        assert_eq!(inflater.total_out(), 3);
+    }
+
+    #[test]
+    fn test_error_after_output() {
+        use std::io::Write;
+        use crate::io::{DeflateCompressor, DeflateDecompressorError, Inflater, InflateErrorKind};
+        use crate::io::InflateStatus;
+        use crate::io::deflate::tests::noisy_input;
+        use crate::random::MT19937_32;
+
+        let mut gen = MT19937_32::from_seed(9);
+        let input = noisy_input(&mut gen, 50_000, 2000, 200, b"abc");
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        compressor.write_all(&input).unwrap();
+        compressor.flush().unwrap();
+        // a final block with an invalid type after everything
+        let mut compressed = compressor.get_ref().clone();
+        compressed.push(0x07);
+
+        for &size in &[100_000, 1000] {
+            let mut inflater = Inflater::new();
+            let mut output = Vec::new();
+            let mut buffer = vec![0; size];
+            let mut position = 0;
+            let e = loop {
+                match inflater.feed(&compressed[position..], &mut buffer) {
+                    Ok(progress) => {
+                        assert_eq!(progress.status, InflateStatus::Ok);
+                        position += progress.consumed;
+                        output.extend_from_slice(&buffer[..progress.produced]);
+                    },
+                    Err(e) => break e,
+                }
+            };
+
+            // nothing decoded before the error is lost
+            let e = e.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap();
+            assert_eq!(e.kind, InflateErrorKind::InvalidBType);
+            assert_eq!(output, input);
+            assert_eq!(position, compressed.len());
+            assert_eq!(inflater.total_out(), input.len() as u64);
+            assert_eq!(inflater.total_in(), position as u64);
+        }
+    }
+}
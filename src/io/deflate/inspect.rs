@@ -0,0 +1,275 @@
+use std::fmt;
+use std::io::{self, Read};
+use super::decoder::{DecodeError, Decoder, ReaderSource, WINDOW_SIZE};
+use super::{BlockType, DeflateDecompressorError, InflateOptions};
+
+/// A literal byte or a match, as coded in a deflate stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateToken {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// One token of a Huffman coded block, and where it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenInfo {
+    /// Where the token's code starts, in bits from the start of the stream.
+    pub bit_offset: u64,
+    /// Where the token's output starts.
+    pub output_offset: u64,
+    pub token: DeflateToken,
+}
+
+/// The structure of one block of a deflate stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Where the block header starts, in bits from the start of the stream.
+    pub bit_offset: u64,
+    /// The length of the block, header included, in bits.
+    pub bit_len: u64,
+    pub output_offset: u64,
+    pub output_len: u64,
+    /// BFINAL: whether this is the last block.
+    pub final_block: bool,
+    /// BTYPE.
+    pub block_type: BlockType,
+    /// The literal/length code lengths from the header of a dynamic block,
+    /// indexed by symbol. Empty for other blocks.
+    pub litlen_lengths: Vec<u32>,
+    /// The distance code lengths from the header of a dynamic block.
+    pub distance_lengths: Vec<u32>,
+    /// Every literal and match in the block, in order. Empty for stored
+    /// blocks.
+    pub tokens: Vec<TokenInfo>,
+}
+
+impl BlockInfo {
+    pub(crate) fn new(bit_offset: u64, output_offset: u64, final_block: bool,
+    block_type: BlockType) -> BlockInfo {
+        BlockInfo {
+            bit_offset,
+            bit_len: 0,
+            output_offset,
+            output_len: 0,
+            final_block,
+            block_type,
+            litlen_lengths: Vec::new(),
+            distance_lengths: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+/// A description of every block in a deflate stream, for finding out why
+/// some data compresses the way it does.
+///
+/// The stream is fully decoded along the way, so this also finds errors in
+/// it. The blocks up to an error are still described.
+///
+/// `Display` gives a dump of the whole stream, one line per token.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{BlockType, DeflateInspection, DeflateToken};
+/// let mut compressed = Vec::new();
+/// stdex::algorithms::deflate(b"abcabcabcabc", &mut compressed);
+///
+/// let inspection = DeflateInspection::from_reader(&compressed[..]).unwrap();
+/// let block = &inspection.blocks[0];
+/// assert_eq!(block.block_type, BlockType::Fixed);
+/// assert!(block.final_block);
+/// assert_eq!(block.tokens[3].token, DeflateToken::Match { length: 9, distance: 3 });
+/// assert_eq!(block.tokens[3].output_offset, 3);
+///
+/// let dump = inspection.to_string();
+/// assert!(dump.contains("match length 9 distance 3"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeflateInspection {
+    pub blocks: Vec<BlockInfo>,
+    pub total_in: u64,
+    pub total_out: u64,
+    /// The error which stopped decoding, if the stream is corrupt.
+    pub error: Option<DeflateDecompressorError>,
+}
+
+impl DeflateInspection {
+    /// Decodes the deflate stream from `reader`, describing every block.
+    ///
+    /// Errors in the stream are kept in `error`; only errors from `reader`
+    /// itself are returned.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<DeflateInspection> {
+        let mut decoder = Decoder::new(InflateOptions::default());
+        decoder.set_inspect(true);
+
+        let mut discard = vec![0; WINDOW_SIZE];
+        let mut error = None;
+        while !decoder.is_finished() {
+            if let Err(e) = decoder.run(&mut ReaderSource(&mut reader), WINDOW_SIZE) {
+                match e {
+                    DecodeError::Invalid(e) => error = Some(e),
+                    DecodeError::Io(e) => return Err(e),
+                }
+                break;
+            }
+            let available = decoder.available();
+            decoder.copy_out(&mut discard[..available]);
+        }
+
+        let mut blocks = decoder.take_inspection();
+        if error.is_some() {
+            // the block with the error goes as far as decoding got
+            if let Some(block) = blocks.last_mut() {
+                block.bit_len = decoder.bit_position() - block.bit_offset;
+                block.output_len = decoder.total_out() - block.output_offset;
+            }
+        }
+
+        Ok(DeflateInspection {
+            blocks,
+            total_in: decoder.total_in(),
+            total_out: decoder.total_out(),
+            error,
+        })
+    }
+}
+
+impl fmt::Display for DeflateToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeflateToken::Literal(byte) if byte.is_ascii_graphic() || byte == b' ' => {
+                write!(f, "literal '{}'", byte as char)
+            },
+            DeflateToken::Literal(byte) => write!(f, "literal 0x{:02x}", byte),
+            DeflateToken::Match { length, distance } => {
+                write!(f, "match length {} distance {}", length, distance)
+            },
+        }
+    }
+}
+
+/// Writes the non-zero code lengths as `symbol:length` pairs.
+fn write_lengths(f: &mut fmt::Formatter, name: &str, lengths: &[u32]) -> fmt::Result {
+    write!(f, "  {} code lengths:", name)?;
+    for (symbol, &length) in lengths.iter().enumerate() {
+        if length != 0 {
+            write!(f, " {}:{}", symbol, length)?;
+        }
+    }
+    writeln!(f)
+}
+
+impl fmt::Display for BlockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let matches = self.tokens.iter()
+            .filter(|t| matches!(t.token, DeflateToken::Match { .. }))
+            .count();
+        writeln!(f, "{} block at bit {}{}: {} bits in, {} bytes out, {} literals, {} matches",
+            self.block_type, self.bit_offset, if self.final_block { " (final)" } else { "" },
+            self.bit_len, self.output_len, self.tokens.len() - matches, matches)?;
+
+        if self.block_type == BlockType::Dynamic {
+            write_lengths(f, "literal/length", &self.litlen_lengths)?;
+            write_lengths(f, "distance", &self.distance_lengths)?;
+        }
+
+        for token in self.tokens.iter() {
+            writeln!(f, "  bit {} out {}: {}", token.bit_offset, token.output_offset, token.token)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DeflateInspection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} blocks, {} bytes in, {} bytes out", self.blocks.len(),
+            self.total_in, self.total_out)?;
+        for block in self.blocks.iter() {
+            write!(f, "{}", block)?;
+        }
+        if let Some(ref error) = self.error {
+            writeln!(f, "error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_inspect() {
+        use std::io::Write;
+        use crate::io::{BlockType, DeflateCompressor, DeflateInspection, DeflateToken};
+        use crate::io::InflateErrorKind;
+        use crate::random::MT19937_32;
+
+        let mut gen = MT19937_32::from_seed(4);
+        let mut input: Vec<u8> = (0..70_000).map(|_| gen.generate() as u8).collect();
+        input.extend(b"to be or not to be, that is the question. ".iter().cycle().take(70_000));
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        compressor.write_all(&input).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let inspection = DeflateInspection::from_reader(&compressed[..]).unwrap();
+        assert_eq!(inspection.error, None);
+        assert_eq!(inspection.total_in, compressed.len() as u64);
+        assert_eq!(inspection.total_out, input.len() as u64);
+
+        let blocks = &inspection.blocks;
+        assert_eq!(blocks[0].block_type, BlockType::Stored);
+        assert!(blocks.iter().any(|block| block.block_type == BlockType::Dynamic));
+        assert!(blocks.last().unwrap().final_block);
+        assert_eq!(blocks.iter().filter(|block| block.final_block).count(), 1);
+
+        // the blocks and tokens cover the input and output without gaps, and
+        // replaying the tokens gives back the input
+        let mut bit_offset = 0;
+        let mut output = Vec::new();
+        for block in blocks.iter() {
+            assert_eq!(block.bit_offset, bit_offset);
+            assert_eq!(block.output_offset, output.len() as u64);
+            bit_offset += block.bit_len;
+            if block.block_type == BlockType::Stored {
+                let start = output.len();
+                output.extend_from_slice(&input[start..start + block.output_len as usize]);
+                continue;
+            }
+
+            if block.block_type == BlockType::Dynamic {
+                assert!(block.litlen_lengths.len() >= 257 && block.litlen_lengths[256] != 0);
+                assert!(!block.distance_lengths.is_empty());
+            }
+
+            for token in block.tokens.iter() {
+                assert_eq!(token.output_offset, output.len() as u64);
+                assert!(token.bit_offset > block.bit_offset);
+                match token.token {
+                    DeflateToken::Literal(byte) => output.push(byte),
+                    DeflateToken::Match { length, distance } => {
+                        for _ in 0..length {
+                            let byte = output[output.len() - distance as usize];
+                            output.push(byte);
+                        }
+                    },
+                }
+            }
+            assert_eq!(output.len() as u64, block.output_offset + block.output_len);
+        }
+        assert_eq!(output, input);
+        assert_eq!(bit_offset.div_ceil(8), compressed.len() as u64);
+
+        let dump = inspection.to_string();
+        assert!(dump.starts_with(&format!("{} blocks, {} bytes in", blocks.len(), compressed.len())));
+        assert!(dump.contains("stored block at bit 0: "));
+        assert!(dump.contains("literal 't'"));
+
+        // a corrupt stream is described up to the error
+        let damaged = &compressed[..compressed.len() - 100];
+        let inspection = DeflateInspection::from_reader(damaged).unwrap();
+        assert_eq!(inspection.error.unwrap().kind, InflateErrorKind::UnexpectedEOF);
+        assert!(inspection.blocks.len() >= 2);
+        let last = inspection.blocks.last().unwrap();
+        assert_eq!(last.output_offset + last.output_len, inspection.total_out);
+        assert!(last.output_len > 0);
+        assert!(inspection.to_string().contains("\nerror: Unexpected end of file at bit "));
+    }
+}
@@ -0,0 +1,26 @@
+pub use crate::algorithms::lz77::{parse, HashChain, MatchFinder, ParseOptions, Token, MIN_MATCH};
+
+pub const MAX_MATCH: usize = 258;
+pub const WINDOW_SIZE: usize = 32768;
+
+/// Parses the positions `start..end` into literals and runs of the byte
+/// before, as matches with a distance of 1.
+pub fn parse_rle(data: &[u8], base: usize, start: usize, end: usize, tokens: &mut Vec<Token>) {
+    let mut pos = start;
+    while pos < end {
+        let index = pos - base;
+        let max_length = std::cmp::min(MAX_MATCH, end - pos);
+        let run = match index {
+            0 => 0,
+            _ => data[index..index + max_length].iter().take_while(|&&b| b == data[index - 1]).count(),
+        };
+
+        if run >= MIN_MATCH {
+            tokens.push(Token::Match { length: run, distance: 1 });
+            pos += run;
+        } else {
+            tokens.push(Token::Literal(data[index]));
+            pos += 1;
+        }
+    }
+}
@@ -1,350 +1,327 @@
-use std::io::Read;
-use crate::huffman::{Node, Code, CodeString};
-use crate::io::{BitRead, BitReaderLSB};
-use crate::collections::BitString;
-use crate::io::{write_u8};
+use std::io::{BufRead, Read};
 
 mod ring_buffer;
-
-struct Codes {
-    litlen: Vec<Code<u16>>,
-    distance: Vec<Code<u16>>,
+#[cfg(test)]
+pub(crate) use self::ring_buffer::RingBuffer;
+
+mod lz77;
+
+mod compressor;
+mod optimal;
+pub use self::compressor::{DeflateCompressor, DeflateOptions, Strategy};
+
+
+mod decoder;
+use self::decoder::{Decoder, ReaderSource};
+pub(crate) use self::decoder::inflate_to_vec;
+
+mod inflate_into;
+pub(crate) use self::inflate_into::inflate_into;
+
+mod inflater;
+pub use self::inflater::{Inflater, InflateStatus, Progress};
+
+mod index;
+pub use self::index::{DeflateIndex, Checkpoint, SeekableDeflateReader, DeflateIndexError};
+
+mod inspect;
+pub use self::inspect::{DeflateInspection, BlockInfo, TokenInfo, DeflateToken};
+
+mod salvage;
+pub use self::salvage::{DeflateSalvage, SalvageGap};
+
+const LENGTH_BASE: [usize;29] = [
+    3,4,5,6,7,8,9,10,11,13,
+    15,17,19,23,27,31,35,43,51,59,
+    67,83,99,115,131,163,195,227,258
+];
+
+const LENGTH_EXTRA: [usize;29] = [
+    0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2,3,3,3,3,4,4,4,4,5,5,5,5,0
+];
+
+// the last two distance codes are only used by Deflate64
+const DIST_BASE: [usize;32] = [
+    1,2,3,4,5,7,9,13,17,25,33,49,65,97,129,193,
+    257,385,513,769,1025,1537,2049,3073,4097,6145,8193,12289,16385,24577,32769,49153
+];
+
+const DIST_EXTRA: [usize;32] = [
+    0,0,0,0,1,1,2,2,3,3,4,4,5,5,6,6,7,7,8,8,9,9,10,10,11,11,12,12,13,13,14,14
+];
+
+const SWIZZLE: [usize;19] =
+    [ 16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15 ];
+
+/// Limits on how much a deflate stream may expand, for decompressing
+/// untrusted input. Every limit is off by default.
+///
+/// A stream which exceeds a limit fails with
+/// `InflateErrorKind::LimitExceeded`, before any output past the
+/// limit is produced.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{DeflateDecompressor, DeflateDecompressorError, InflateErrorKind};
+/// # use stdex::io::{InflateOptions, Limit};
+/// # use std::io::Read;
+/// let mut compressed = Vec::new();
+/// stdex::algorithms::deflate(&[0; 100_000], &mut compressed);
+///
+/// let options = InflateOptions { max_ratio: Some(100), ..InflateOptions::default() };
+/// let mut decompressor = DeflateDecompressor::with_options(&compressed[..], options).unwrap();
+/// let e = decompressor.read_to_end(&mut Vec::new()).unwrap_err();
+/// let e = e.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap();
+/// assert_eq!(e.kind, InflateErrorKind::LimitExceeded(Limit::Ratio));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InflateOptions {
+    /// The most bytes of output allowed.
+    pub max_output: Option<u64>,
+    /// The most bytes of output allowed for each byte of input read so far.
+    /// DEFLATE can't expand data more than about 1032 times.
+    pub max_ratio: Option<u64>,
+    /// The most blocks allowed, which bounds the work spent on headers for
+    /// blocks with little or no output.
+    pub max_blocks: Option<u64>,
 }
 
-fn fixed_huffman_codes() -> Codes {
-    let mut litlen = Vec::with_capacity(288);
-
-    let mut bits = 0b00110000;
-    for value in 0..=143 {
-        let code = CodeString::new(8, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
-    }
-
-    let mut bits = 0b110010000;
-    for value in 144..=255 {
-        let code = CodeString::new(9, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
-    }
+impl InflateOptions {
+    /// Checks whether `total_out` bytes of output, from `total_in` bytes of
+    /// input, would be within the limits.
+    pub(crate) fn check_output(&self, total_in: u64, total_out: u64) -> Result<(), Limit> {
+        if let Some(max_output) = self.max_output {
+            if total_out > max_output {
+                return Err(Limit::Output);
+            }
+        }
 
-    let mut bits = 0;
-    for value in 256..=279 {
-        let code = CodeString::new(7, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
-    }
+        if let Some(max_ratio) = self.max_ratio {
+            if total_out > max_ratio.saturating_mul(total_in) {
+                return Err(Limit::Ratio);
+            }
+        }
 
-    let mut bits = 0b11000000;
-    for value in 280..=287 {
-        let code = CodeString::new(8, bits);
-        litlen.push(Code { value, code });
-        bits += 1;
+        Ok(())
     }
 
-    let mut distance = Vec::new();
-    for value in 0..=31 {
-        let code = CodeString::new(5, value as u32);
-        distance.push(Code { value, code });
+    pub(crate) fn check_blocks(&self, blocks: u64) -> Result<(), Limit> {
+        match self.max_blocks {
+            Some(max_blocks) if blocks > max_blocks => Err(Limit::Blocks),
+            _ => Ok(()),
+        }
     }
-
-    Codes { litlen, distance }
 }
 
-fn dynamic_huffman_codes<R: Read>(bitreader: &mut BitReaderLSB<R>)
--> std::io::Result<Codes> {
-    let hlit = bitreader.read_bits_32(5)? as usize + 257;
-    let hdist = bitreader.read_bits_32(5)? as usize + 1;
-    let hclen = bitreader.read_bits_32(4)? as usize + 4;
-
-    const SWIZZLE: [usize;19] =
-        [ 16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15 ];
+pub struct DeflateDecompressor<R: Read> {
+    reader: R,
+    decoder: Decoder,
+}
 
-    let mut code_lengths = [0;19];
-    for i in 0..hclen {
-        code_lengths[SWIZZLE[i]] = bitreader.read_bits_32(3)? as u32;
+impl<R: Read> DeflateDecompressor<R> {
+    pub fn new(reader: R) -> std::io::Result<DeflateDecompressor<R>> {
+        DeflateDecompressor::with_options(reader, InflateOptions::default())
     }
 
-    let codes = Code::canonical_from_lengths(0, &code_lengths).or_else(
-        |_| Err(DeflateDecompressorError::BadHuffmanCodes)
-    )?;
-
-    let tree = Node::from_codes(&codes).or_else(
-        |_| Err(DeflateDecompressorError::BadHuffmanCodes)
-    )?;
-
-    let mut code_lengths = [0;288 + 32];
-    let mut i = 0;
-    let mut previous = 0;
-    while i < hlit + hdist {
-        match tree.read_value(bitreader)? {
-            n @ 0...15 => {
-                code_lengths[i] = n as u32;
-                previous = n;
-                i += 1;
-            },
-            16 => {
-                let repeat_len = bitreader.read_bits_32(2)? + 3;
-                for _ in 0..repeat_len {
-                    code_lengths[i] = previous as u32;
-                    i += 1;
-                }
-            },
-            17 => {
-                let repeat_len = bitreader.read_bits_32(3)? + 3;
-                previous = 0;
-                i += repeat_len as usize;
-            },
-            18 => {
-                let repeat_len = bitreader.read_bits_32(7)? + 11;
-                previous = 0;
-                i += repeat_len as usize;
-            },
-            _ => {
-                return Err(DeflateDecompressorError::BadHuffmanCodes.into());
-            }
-        }
+    /// Creates a decompressor which fails if the stream exceeds the limits
+    /// in `options`.
+    pub fn with_options(reader: R, options: InflateOptions)
+    -> std::io::Result<DeflateDecompressor<R>> {
+        DeflateDecompressor::from_decoder(reader, Decoder::new(options))
     }
 
-    let litlen = Code::canonical_from_lengths(0, &code_lengths[..hlit]).or_else(
-        |_| Err(DeflateDecompressorError::BadHuffmanCodes)
-    )?;
-
-    let distance = Code::canonical_from_lengths(0, &code_lengths[hlit..]).or_else(
-        |_| Err(DeflateDecompressorError::BadHuffmanCodes)
-    )?;
-
-    Ok(Codes {
-        litlen,
-        distance,
-    })
-}
-
-fn read_length_distance_pair<R: Read>(code : u16, dist_tree: &Node<u16>, bitreader: &mut BitReaderLSB<R>)
--> std::io::Result<(u16, u16)> {
-    const LENGTH_BASE: [usize;29] = [
-        3,4,5,6,7,8,9,10,11,13,
-        15,17,19,23,27,31,35,43,51,59,
-        67,83,99,115,131,163,195,227,258
-    ];
-
-    const LENGTH_EXTRA: [usize;29] = [
-        0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2,3,3,3,3,4,4,4,4,5,5,5,5,0
-    ];
-
-    const DIST_BASE: [usize;32] = [
-        1,2,3,4,5,7,9,13,17,25,33,49,65,97,129,193,
-        257,385,513,769,1025,1537,2049,3073,4097,6145,8193,12289,16385,24577,0,0
-    ];
-
-    const DIST_EXTRA: [usize;32] = [
-        0,0,0,0,1,1,2,2,3,3,4,4,5,5,6,6,7,7,8,8,9,9,10,10,11,11,12,12,13,13,0,0
-    ];
-
-    let mut len = LENGTH_BASE[code as usize];
-    if LENGTH_EXTRA[code as usize] != 0 {
-        len += bitreader.read_bits_32(LENGTH_EXTRA[code as usize])? as usize;
+    /// Creates a decompressor for Deflate64, as found in zip files with
+    /// compression method 9. It differs from deflate in having a 64 KiB
+    /// window, distance codes 30 and 31, and 16 extra bits for length code
+    /// 285, which covers lengths from 3 to 65538.
+    pub fn new_deflate64(reader: R) -> std::io::Result<DeflateDecompressor<R>> {
+        DeflateDecompressor::from_decoder(reader, Decoder::new_deflate64(InflateOptions::default()))
     }
 
-    let code = dist_tree.read_value(bitreader)?;
-    let mut dist = DIST_BASE[code as usize];
-    if DIST_EXTRA[code as usize] != 0 {
-        dist += bitreader.read_bits_32(DIST_EXTRA[code as usize])? as usize;
+    /// Creates a decompressor for a stream which was compressed with a preset
+    /// dictionary, as used by zlib's FDICT and some network protocols.
+    ///
+    /// # Example
+    /// ```
+    /// # use stdex::io::{DeflateCompressor, DeflateDecompressor};
+    /// # use std::io::{Read, Write};
+    /// let dictionary = b"the quick brown fox jumps over the lazy dog";
+    /// let mut compressor = DeflateCompressor::with_dictionary(Vec::new(), dictionary);
+    /// compressor.write_all(b"the lazy brown dog").unwrap();
+    /// let compressed = compressor.finish().unwrap();
+    ///
+    /// let mut decompressor = DeflateDecompressor::with_dictionary(&compressed[..], dictionary).unwrap();
+    /// let mut output = String::new();
+    /// decompressor.read_to_string(&mut output).unwrap();
+    /// assert_eq!(output, "the lazy brown dog");
+    /// ```
+    pub fn with_dictionary(reader: R, dictionary: &[u8])
+    -> std::io::Result<DeflateDecompressor<R>> {
+        let mut decoder = Decoder::new(InflateOptions::default());
+        decoder.set_dictionary(dictionary);
+        DeflateDecompressor::from_decoder(reader, decoder)
     }
 
-    Ok((len as u16, dist as u16))
-}
+    fn from_decoder(mut reader: R, mut decoder: Decoder)
+    -> std::io::Result<DeflateDecompressor<R>> {
+        // read the first block header now, so a stream which isn't deflate
+        // at all fails straight away
+        decoder.run(&mut ReaderSource(&mut reader), 0)?;
+        Ok(DeflateDecompressor { reader, decoder })
+    }
 
-pub struct DeflateDecompressor<R: Read> {
-    bitreader: crate::io::BitReaderLSB<R>,
-    state: DeflateDecompressorState,
-    window: ring_buffer::RingBuffer,
-    available: usize,
-}
+    pub fn make_available(&mut self, required: usize) -> std::io::Result<usize> {
+        self.decoder.run(&mut ReaderSource(&mut self.reader), required)?;
+        Ok(self.decoder.available())
+    }
 
-struct HuffmanState {
-    litlen_tree: crate::huffman::Node<u16>,
-    distance_tree: crate::huffman::Node<u16>,
-    distance: u16,
-    copy_len: u16,
-}
+    /// The number of bytes read from the underlying reader so far.
+    ///
+    /// Input is only read a byte at a time as it's needed, so once the end
+    /// of the stream has been reached, this is the length of the stream.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.total_in()
+    }
 
-enum DeflateDecompressorState {
-    Uncompressed((bool, u16)),
-    Huffman((bool, HuffmanState)),
-    Complete,
-}
+    /// The number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.decoder.total_out() - self.decoder.available() as u64
+    }
 
-impl DeflateDecompressorState {
-    fn from_bitreader<R: Read>(bitreader: &mut crate::io::BitReaderLSB<R>)
-    -> std::io::Result<DeflateDecompressorState> {
-        let bfinal = bitreader.read_bit()? != 0;
-        let state = match bitreader.read_bits_32(2)? {
-            0 => {
-                bitreader.flush_byte();
-                let reader = bitreader.as_read_mut();
-                let len = crate::io::read_u16_le(reader)?;
-                let nlen = crate::io::read_u16_le(reader)?;
-
-                if len != !nlen {
-                    return Err(DeflateDecompressorError::NonMatchingLenNLen.into());
-                }
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
 
-                DeflateDecompressorState::Uncompressed((bfinal, len))
-            },
-            btype @ 1...2 => {
-                let codes = match btype {
-                    1 => fixed_huffman_codes(),
-                    2 => dynamic_huffman_codes(bitreader)?,
-                    _ => unreachable!(),
-                };
-
-                let litlen_tree = crate::huffman::Node::from_codes(&codes.litlen).or_else(
-                    |_| Err(DeflateDecompressorError::BadHuffmanCodes)
-                )?;
-
-                let distance_tree = crate::huffman::Node::from_codes(&codes.distance).or_else(
-                    |_| Err(DeflateDecompressorError::BadHuffmanCodes)
-                )?;
-
-                DeflateDecompressorState::Huffman(
-                    (bfinal, HuffmanState {
-                        litlen_tree, distance_tree, distance: 0, copy_len: 0,
-                    })
-                )
-            },
-            3 => {
-                return Err(DeflateDecompressorError::InvalidBType.into());
-            },
-            _ => unreachable!(),
-        };
+    /// Returns the underlying reader. Once everything has been read from the
+    /// stream, it's positioned at the first byte after the end, for reading
+    /// whatever follows.
+    ///
+    /// Reading from it before then will corrupt the stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
 
-        Ok(state)
+    /// Consumes the decompressor, returning the underlying reader. Once
+    /// everything has been read from the stream, it's positioned at the
+    /// first byte after the end, for reading whatever follows.
+    ///
+    /// Any decompressed data which hasn't been read yet is lost.
+    ///
+    /// # Example
+    /// ```
+    /// # use stdex::io::{DeflateCompressor, DeflateDecompressor};
+    /// # use std::io::{Read, Write};
+    /// let mut compressor = DeflateCompressor::new(Vec::new());
+    /// compressor.write_all(b"payload").unwrap();
+    /// let mut data = compressor.finish().unwrap();
+    /// let compressed_len = data.len();
+    /// data.extend_from_slice(b"trailer");
+    ///
+    /// let mut decompressor = DeflateDecompressor::new(&data[..]).unwrap();
+    /// let mut output = Vec::new();
+    /// decompressor.read_to_end(&mut output).unwrap();
+    /// assert_eq!(decompressor.total_in(), compressed_len as u64);
+    /// assert_eq!(decompressor.total_out(), 7);
+    ///
+    /// let rest = decompressor.into_inner();
+    /// assert_eq!(rest, b"trailer");
+    /// ```
+    pub fn into_inner(self) -> R {
+        self.reader
     }
 }
 
-impl<R: Read> DeflateDecompressor<R> {
-    pub fn new(reader: R) -> std::io::Result<DeflateDecompressor<R>> {
-        let mut bitreader = crate::io::BitReaderLSB::new(reader);
-        let state = DeflateDecompressorState::from_bitreader(&mut bitreader)?;
-        let window = ring_buffer::RingBuffer::new(32768);
-
-        Ok(DeflateDecompressor {
-            bitreader,
-            state,
-            window,
-            available: 0,
-        })
+impl<R: Read> Read for DeflateDecompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.fill_buf()?;
+        let len = std::cmp::min(self.decoder.available(), buf.len());
+        self.decoder.copy_out(&mut buf[..len]);
+        Ok(len)
     }
+}
 
-    pub fn make_available(&mut self, required: usize) -> std::io::Result<usize> {
-        assert!(required <= 32768, "too many bytes requested at once");
-        while required > self.available {
-            let mut state_change = None;
-            match &mut self.state {
-                DeflateDecompressorState::Uncompressed((bfinal,uncompressed)) => {
-                    let can_read = 32768 - self.available;
-                    let to_read = std::cmp::min(*uncompressed as usize, can_read);
-                    io_copy(self.bitreader.as_read_mut(), &mut self.window, to_read)?;
-                    self.available += to_read;
-
-                    *uncompressed -= to_read as u16;
-                    if *uncompressed == 0 {
-                        state_change = Some(*bfinal);
-                    }
-                },
-                DeflateDecompressorState::Huffman((bfinal, huffstate)) => {
-                    if huffstate.copy_len == 0 {
-                        match huffstate.litlen_tree.read_value(&mut self.bitreader)? {
-                            value @ 0...255 => {
-                                write_u8(&mut self.window, value as u8)?;
-                                self.available += 1;
-                            },
-                            256 => {
-                                state_change = Some(*bfinal);
-                            },
-                            value @ 257...285 => {
-                                let (len, dist) = read_length_distance_pair(value - 257,
-                                    &huffstate.distance_tree, &mut self.bitreader)?;
-                                huffstate.distance = dist;
-                                huffstate.copy_len = len;
-                            },
-                            286...287 => { // error in data
-                                return Err(DeflateDecompressorError::InvalidLitLenCode.into());
-                            },
-                            _ => unreachable!(), // error in my code
-                        }
-                    } else {
-                        let required = required - self.available;
-                        let copy_len = std::cmp::min(huffstate.copy_len as usize, required);
-                        self.window.self_copy(huffstate.distance as usize, copy_len)?;
-                        self.available += copy_len;
-                        huffstate.copy_len -= copy_len as u16;
-                    }
-                },
-                DeflateDecompressorState::Complete => {
-                    break;
-                }
-            }
-
-            if let Some(bfinal) = state_change {
-                self.state = match bfinal {
-                    true => DeflateDecompressorState::Complete,
-                    false => DeflateDecompressorState::from_bitreader(&mut self.bitreader)?,
-                }
+/// Decompressed data is read straight out of the window, without copying it
+/// anywhere first, so there's no need for a `BufReader`.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{DeflateCompressor, DeflateDecompressor};
+/// # use std::io::{BufRead, Write};
+/// let mut compressor = DeflateCompressor::new(Vec::new());
+/// compressor.write_all(b"GET /\nPOST /login\nGET /about\n").unwrap();
+/// let compressed = compressor.finish().unwrap();
+///
+/// let decompressor = DeflateDecompressor::new(&compressed[..]).unwrap();
+/// let lines: Vec<String> = decompressor.lines().map(|line| line.unwrap()).collect();
+/// assert_eq!(lines, ["GET /", "POST /login", "GET /about"]);
+/// ```
+impl<R: Read> BufRead for DeflateDecompressor<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.decoder.available() == 0 {
+            // decode as much as the window can hold; an error part way
+            // through comes back again on the next call, once what was
+            // decoded before it has been read
+            let result = self.make_available(decoder::WINDOW_SIZE);
+            if self.decoder.available() == 0 {
+                result?;
             }
         }
-        Ok(self.available)
+        Ok(self.decoder.available_slice())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.decoder.consume(amt);
     }
 }
 
-impl<R: Read> Read for DeflateDecompressor<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let mut read_so_far = 0;
-        while buf.len() - read_so_far > 32768 {
-            let end = read_so_far + 32768;
-            match self.read(&mut buf[read_so_far..end]) {
-                Ok(0) => return Ok(read_so_far),
-                Ok(n) => read_so_far += n,
-                Err(e) => return match read_so_far {
-                    0 => Err(e),
-                    _ => Ok(read_so_far)
-                },
-            }
-        }
+/// Which of the `InflateOptions` limits a stream exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Output,
+    Ratio,
+    Blocks,
+}
 
-        let to_read = buf.len() - read_so_far;
-        match self.make_available(to_read) {
-            Ok(0) => Ok(0),
-            Ok(available) => {
-                let to_read = std::cmp::min(available, to_read);
-                let write_end = read_so_far + to_read;
-                self.window.copy_out(&mut buf[read_so_far..write_end], available);
-                self.available -= to_read;
-                read_so_far += to_read;
-                Ok(read_so_far)
-            },
-            Err(e) => match read_so_far {
-                0 => Err(e.into()),
-                _ => Ok(read_so_far),
-            }
+/// The three kinds of deflate block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+impl std::fmt::Display for BlockType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockType::Stored => write!(f, "stored"),
+            BlockType::Fixed => write!(f, "fixed"),
+            BlockType::Dynamic => write!(f, "dynamic"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum DeflateDecompressorError {
+/// What was wrong with a deflate stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InflateErrorKind {
     General,
     NonMatchingLenNLen,
     BadHuffmanCodes,
     InvalidBType,
+    /// The stream ended part way through.
     UnexpectedEOF,
     InvalidLitLenCode,
+    /// A match reached back before the start of the output.
+    DistanceTooFarBack,
+    /// Code length code 16, which repeats the previous length, came first.
+    RepeatWithoutPrevious,
+    /// The code lengths ran past the number given in the block header.
+    TooManyCodeLengths,
+    LimitExceeded(Limit),
 }
 
-impl std::fmt::Display for DeflateDecompressorError {
+impl std::fmt::Display for InflateErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use self::DeflateDecompressorError::*;
+        use self::InflateErrorKind::*;
         match self {
             General => write!(f, "Deflate Decompressor Error"),
             NonMatchingLenNLen => write!(f, "Non-matching len/nlen in uncompressed block"),
@@ -352,6 +329,52 @@ impl std::fmt::Display for DeflateDecompressorError {
             InvalidBType => write!(f, "Invalie btype code"),
             UnexpectedEOF => write!(f, "Unexpected end of file"),
             InvalidLitLenCode => write!(f, "Invalid Lit/Len code"),
+            DistanceTooFarBack => write!(f, "Distance refers back before the start of the output"),
+            RepeatWithoutPrevious => write!(f, "Code length repeat with no previous length"),
+            TooManyCodeLengths => write!(f, "Code length repeat runs past the end of the code lengths"),
+            LimitExceeded(Limit::Output) => write!(f, "Output size limit exceeded"),
+            LimitExceeded(Limit::Ratio) => write!(f, "Expansion ratio limit exceeded"),
+            LimitExceeded(Limit::Blocks) => write!(f, "Block count limit exceeded"),
+        }
+    }
+}
+
+/// An error in a deflate stream, and where in the stream it was found.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{DeflateDecompressor, DeflateDecompressorError, InflateErrorKind, BlockType};
+/// # use std::io::Read;
+/// // a fixed block which starts with a match, when there's nothing to copy
+/// let data = [0x03, 0x02];
+/// let mut decompressor = DeflateDecompressor::new(&data[..]).unwrap();
+/// let e = decompressor.read_to_end(&mut Vec::new()).unwrap_err();
+/// let e = e.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap();
+/// assert_eq!(e.kind, InflateErrorKind::DistanceTooFarBack);
+/// assert_eq!(e.bit_offset, 3);
+/// assert_eq!(e.block_index, 0);
+/// assert_eq!(e.block_type, Some(BlockType::Fixed));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateDecompressorError {
+    pub kind: InflateErrorKind,
+    /// How far into the input the error was found, in bits. This is the
+    /// start of the code or field which was wrong, or the end of the input
+    /// for a stream which ended early.
+    pub bit_offset: u64,
+    /// Which block the error was in, counting from 0.
+    pub block_index: u64,
+    /// The type of the block the error was in, or `None` if the error was
+    /// in the first three bits of the block, before the type was known.
+    pub block_type: Option<BlockType>,
+}
+
+impl std::fmt::Display for DeflateDecompressorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at bit {} (block {}", self.kind, self.bit_offset, self.block_index)?;
+        match self.block_type {
+            Some(block_type) => write!(f, ", {})", block_type),
+            None => write!(f, ")"),
         }
     }
 }
@@ -367,25 +390,435 @@ impl From<DeflateDecompressorError> for std::io::Error {
     }
 }
 
-fn io_copy<R: std::io::Read, W: std::io::Write>(
-    reader: &mut R, writer: &mut W, byte_count: usize,
-) -> std::io::Result<()> {
-    let mut buffer: [u8; 16384] = unsafe { std::mem::uninitialized() };
-    let mut remaining = byte_count;
-    while remaining > 0 {
-        let to_read = if remaining > 16384 { 16384 } else { remaining };
-
-        reader.read_exact(&mut buffer[0..to_read])?;
-        remaining -= to_read;
-        writer.write(&buffer[0..to_read])?;
-    }
+/// Why `stdex::algorithms::inflate_into` failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InflateIntoError {
+    /// The output filled up before the end of the stream. Holds the number
+    /// of bytes written, which is all of the output.
+    OutputTooSmall(usize),
+    Invalid(DeflateDecompressorError),
+}
 
-    Ok(())
+impl std::fmt::Display for InflateIntoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InflateIntoError::OutputTooSmall(written) => {
+                write!(f, "Output too small for the stream ({} bytes written)", written)
+            },
+            InflateIntoError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
 }
 
+impl std::error::Error for InflateIntoError {}
+
 mod tests {
+    /// `len` bytes which are random for `noise` bytes out of every `period`,
+    /// and otherwise a pattern made from `alphabet`.
+    #[cfg(test)]
+    pub fn noisy_input(gen: &mut crate::random::MT19937_32, len: u32, period: u32, noise: u32,
+    alphabet: &[u8]) -> Vec<u8> {
+        (0..len)
+            .map(|i| match i % period < noise {
+                true => gen.generate() as u8,
+                false => alphabet[i as usize % 7 % alphabet.len()],
+            })
+            .collect()
+    }
+
     #[test]
     fn test_deflate_decompressor() {
         
     }
+
+    #[test]
+    fn test_limits() {
+        use std::io::{Read, Write};
+        use crate::io::{DeflateCompressor, DeflateDecompressor, DeflateDecompressorError};
+        use crate::io::{Inflater, InflateOptions, InflateStatus, InflateErrorKind, Limit};
+        use crate::algorithms::inflate_with_options;
+
+        // three blocks of 65536 zeros, then an empty final block
+        let input = vec![0; 3 * 65536];
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        compressor.write_all(&input).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let check = |options: InflateOptions| -> Vec<Option<InflateErrorKind>> {
+            let error = |e: std::io::Error| {
+                e.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap().kind
+            };
+
+            let mut output = Vec::new();
+            let one_shot = inflate_with_options(&mut &compressed[..], &mut output, options)
+                .err().map(|e| e.downcast_ref::<DeflateDecompressorError>().unwrap().kind);
+
+            let mut output = Vec::new();
+            let streaming = DeflateDecompressor::with_options(&compressed[..], options)
+                .and_then(|mut decompressor| decompressor.read_to_end(&mut output))
+                .err().map(error);
+            assert!(output.len() as u64 <= options.max_output.unwrap_or(u64::MAX));
+
+            let mut inflater = Inflater::with_options(options);
+            let mut buffer = vec![0; 4096];
+            let mut position = 0;
+            let pushed = loop {
+                match inflater.feed(&compressed[position..], &mut buffer) {
+                    Ok(progress) => {
+                        position += progress.consumed;
+                        if progress.status == InflateStatus::StreamEnd {
+                            break None;
+                        }
+                    },
+                    Err(e) => break Some(error(e)),
+                }
+            };
+
+            vec![one_shot, streaming, pushed]
+        };
+
+        let unlimited = InflateOptions::default();
+        assert_eq!(check(unlimited), vec![None; 3]);
+
+        let exact = InflateOptions { max_output: Some(3 * 65536), max_blocks: Some(4), ..unlimited };
+        assert_eq!(check(exact), vec![None; 3]);
+
+        let output = InflateOptions { max_output: Some(100_000), ..unlimited };
+        let expected = Some(InflateErrorKind::LimitExceeded(Limit::Output));
+        assert_eq!(check(output), vec![expected; 3]);
+
+        let ratio = InflateOptions { max_ratio: Some(50), ..unlimited };
+        let expected = Some(InflateErrorKind::LimitExceeded(Limit::Ratio));
+        assert_eq!(check(ratio), vec![expected; 3]);
+
+        let blocks = InflateOptions { max_blocks: Some(3), ..unlimited };
+        let expected = Some(InflateErrorKind::LimitExceeded(Limit::Blocks));
+        assert_eq!(check(blocks), vec![expected; 3]);
+    }
+
+    #[test]
+    fn test_implementations_agree() {
+        use std::io::Read;
+        use crate::io::{DeflateDecompressor, DeflateDecompressorError, Inflater, InflateStatus};
+        use crate::io::{InflateErrorKind, InflateIntoError, BlockType};
+        use crate::random::MT19937_32;
+
+        // the inflater can't tell that the input has ended, so all it knows
+        // is that it wanted more
+        let truncated = DeflateDecompressorError {
+            kind: InflateErrorKind::UnexpectedEOF, bit_offset: 0, block_index: 0, block_type: None,
+        };
+
+        let decode_all = |compressed: &[u8]| -> Vec<Result<Vec<u8>, DeflateDecompressorError>> {
+            let error = |e: std::io::Error| {
+                *e.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap()
+            };
+
+            let mut output = Vec::new();
+            let one_shot = crate::algorithms::inflate(&mut &compressed[..], &mut output)
+                .map(|_| output)
+                .map_err(|e| *e.downcast_ref::<DeflateDecompressorError>().unwrap());
+
+            let mut output = Vec::new();
+            let streaming = DeflateDecompressor::new(compressed)
+                .and_then(|mut decompressor| decompressor.read_to_end(&mut output))
+                .map(|_| output)
+                .map_err(error);
+
+            let mut inflater = Inflater::new();
+            let mut output = Vec::new();
+            let mut buffer = [0; 1000];
+            let mut position = 0;
+            let pushed = loop {
+                match inflater.feed(&compressed[position..], &mut buffer) {
+                    Ok(progress) => {
+                        position += progress.consumed;
+                        output.extend_from_slice(&buffer[..progress.produced]);
+                        match progress.status {
+                            InflateStatus::StreamEnd => break Ok(output),
+                            InflateStatus::BufError => break Err(truncated),
+                            InflateStatus::Ok => {},
+                        }
+                    },
+                    Err(e) => break Err(error(e)),
+                }
+            };
+
+            let mut output = vec![0; 1 << 20];
+            let sliced = crate::algorithms::inflate_into(compressed, &mut output)
+                .map(|len| output[..len].to_vec())
+                .map_err(|e| match e {
+                    InflateIntoError::Invalid(e) => e,
+                    InflateIntoError::OutputTooSmall(_) => panic!("{}", e),
+                });
+
+            vec![one_shot, streaming, pushed, sliced]
+        };
+
+        // a dynamic block whose code length repeats run past hlit + hdist
+        let overrun = [0x05, 0x00, 0x80, 0xe4, 0xff, 0x1f];
+        let expected = DeflateDecompressorError {
+            kind: InflateErrorKind::TooManyCodeLengths,
+            bit_offset: 37,
+            block_index: 0,
+            block_type: Some(BlockType::Dynamic),
+        };
+        assert_eq!(decode_all(&overrun), vec![Err(expected); 4]);
+
+        let mut gen = MT19937_32::from_seed(9);
+        let input = noisy_input(&mut gen, 20_000, 500, 100, b"xyz");
+        let mut compressed = Vec::new();
+        crate::algorithms::deflate(&input, &mut compressed);
+        assert_eq!(decode_all(&compressed), vec![Ok(input); 4]);
+
+        // however the data is damaged, they should all fail the same way,
+        // or produce the same output
+        for _ in 0..200 {
+            let mut damaged = compressed.clone();
+            let index = gen.generate() as usize % damaged.len();
+            damaged[index] ^= 1 << (gen.generate() % 8);
+            damaged.truncate(damaged.len() - gen.generate() as usize % 8);
+            let results = decode_all(&damaged);
+            assert_eq!(results[0], results[1]);
+            assert_eq!(results[0], results[3]);
+            match (&results[0], &results[2]) {
+                (Err(e), Err(pushed)) if *pushed == truncated => {
+                    assert_eq!(e.kind, InflateErrorKind::UnexpectedEOF);
+                },
+                (one_shot, pushed) => assert_eq!(one_shot, pushed),
+            }
+        }
+    }
+
+    #[test]
+    fn test_dictionary() {
+        use std::io::{Read, Write};
+        use crate::io::{DeflateCompressor, DeflateDecompressor, DeflateDecompressorError};
+        use crate::io::InflateErrorKind;
+        use crate::algorithms::{inflate, inflate_with_dictionary};
+
+        // compressed by zlib as a raw deflate stream, with the dictionary
+        let dictionary = b"one fish two fish red fish blue fish";
+        let compressed = [0xc3, 0x14, 0x51, 0xc8, 0x87, 0x6a, 0x03, 0x00];
+        let mut output = Vec::new();
+        inflate_with_dictionary(&mut &compressed[..], &mut output, dictionary).unwrap();
+        assert_eq!(output, b"red fish blue fish one fish");
+
+        let e = inflate(&mut &compressed[..], &mut Vec::new()).unwrap_err();
+        let e = e.downcast_ref::<DeflateDecompressorError>().unwrap();
+        assert_eq!(e.kind, InflateErrorKind::DistanceTooFarBack);
+
+        // only the last 32 KiB of a longer dictionary is used
+        let mut gen = crate::random::MT19937_32::from_seed(11);
+        let dictionary: Vec<u8> = (0..50_000).map(|_| gen.generate() as u8).collect();
+        let input = [&dictionary[20_000..21_000], &dictionary[49_000..]].concat();
+        let mut compressor = DeflateCompressor::with_dictionary(Vec::new(), &dictionary);
+        compressor.write_all(&input).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(compressed.len() < 50);
+
+        let mut decompressor = DeflateDecompressor::with_dictionary(&compressed[..], &dictionary).unwrap();
+        let mut output = Vec::new();
+        decompressor.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+
+        let mut output = Vec::new();
+        inflate_with_dictionary(&mut &compressed[..], &mut output, &dictionary[10_000..]).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_buf_read() {
+        use std::io::{BufRead, Write};
+        use crate::io::{DeflateCompressor, DeflateDecompressor};
+
+        let lines: Vec<String> = (0..20_000).map(|i| format!("line {} {}", i, "x".repeat(i % 37))).collect();
+        let text = lines.join("\n");
+        let mut compressor = DeflateCompressor::new(Vec::new());
+        compressor.write_all(text.as_bytes()).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let decompressor = DeflateDecompressor::new(&compressed[..]).unwrap();
+        let read: Vec<String> = decompressor.lines().map(|line| line.unwrap()).collect();
+        assert_eq!(read, lines);
+
+        // consuming part of what's buffered, across the wrap of the window
+        let mut decompressor = DeflateDecompressor::new(&compressed[..]).unwrap();
+        let mut output = Vec::new();
+        loop {
+            let buffer = decompressor.fill_buf().unwrap();
+            if buffer.is_empty() {
+                break;
+            }
+            let len = std::cmp::min(buffer.len(), 1000);
+            output.extend_from_slice(&buffer[..len]);
+            decompressor.consume(len);
+        }
+        assert_eq!(output, text.as_bytes());
+
+        // everything before an error is returned before the error is
+        let truncated = &compressed[..compressed.len() / 2];
+        let mut decompressor = DeflateDecompressor::new(truncated).unwrap();
+        let mut output = Vec::new();
+        let e = loop {
+            match decompressor.read_until(b'\n', &mut output) {
+                Ok(0) => panic!("truncated stream read to the end"),
+                Ok(_) => {},
+                Err(e) => break e,
+            }
+        };
+        assert!(output.len() > text.len() / 3);
+        assert_eq!(&output[..], &text.as_bytes()[..output.len()]);
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_into_inner() {
+        use std::io::{BufRead, Read, Write};
+        use crate::io::{DeflateCompressor, DeflateDecompressor};
+        use crate::random::MT19937_32;
+
+        let mut gen = MT19937_32::from_seed(2);
+        let random: Vec<u8> = (0..70_000).map(|_| gen.generate() as u8).collect();
+        let text = b"abracadabra ".repeat(1000);
+        // ending in a stored block, a fixed block and a dynamic block
+        for input in [&random[..], b"tiny", &text[..]].iter() {
+            let mut compressor = DeflateCompressor::new(Vec::new());
+            compressor.write_all(input).unwrap();
+            let mut data = compressor.finish().unwrap();
+            let compressed_len = data.len() as u64;
+            data.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+            let mut decompressor = DeflateDecompressor::new(&data[..]).unwrap();
+            let mut output = vec![0; 3];
+            decompressor.read_exact(&mut output).unwrap();
+            assert_eq!(decompressor.total_out(), 3);
+            decompressor.fill_buf().unwrap();
+            decompressor.consume(1);
+            assert_eq!(decompressor.total_out(), 4);
+            assert!(decompressor.total_in() <= compressed_len);
+
+            decompressor.read_to_end(&mut output).unwrap();
+            assert_eq!(output.len() + 1, input.len());
+            assert_eq!(decompressor.total_in(), compressed_len);
+            assert_eq!(decompressor.total_out(), input.len() as u64);
+            assert_eq!(decompressor.into_inner(), [0xde, 0xad, 0xbe, 0xef]);
+        }
+    }
+
+    #[test]
+    fn test_deflate64() {
+        use std::io::Read;
+        use crate::io::{BitWrite, BitWriterLSB, DeflateDecompressor, DeflateDecompressorError};
+        use crate::io::InflateErrorKind;
+        use crate::random::MT19937_32;
+
+        // fixed Huffman codes go most significant bit first
+        fn code(writer: &mut BitWriterLSB<&mut Vec<u8>>, bits: u32, len: usize) {
+            writer.write_bits_32(bits.reverse_bits() >> (32 - len), len).unwrap();
+        }
+
+        let mut gen = MT19937_32::from_seed(64);
+        let random: Vec<u8> = (0..70_000).map(|_| gen.generate() as u8).collect();
+        let mut compressed = Vec::new();
+        {
+            let mut writer = BitWriterLSB::new(&mut compressed);
+            for block in [&random[..40_000], &random[40_000..]].iter() {
+                writer.write_bits_32(0, 3).unwrap();
+                writer.finish_byte(0).unwrap();
+                writer.write_bits_32(block.len() as u32, 16).unwrap();
+                writer.write_bits_32(!block.len() as u32 & 0xffff, 16).unwrap();
+                for &byte in block.iter() {
+                    writer.write_bits_32(byte as u32, 8).unwrap();
+                }
+            }
+
+            writer.write_bits_32(0b011, 3).unwrap();
+            // length 1000 with code 285, distance 65536 with code 31
+            code(&mut writer, 0b11000101, 8);
+            writer.write_bits_32(1000 - 3, 16).unwrap();
+            code(&mut writer, 31, 5);
+            writer.write_bits_32(65536 - 49153, 14).unwrap();
+            // length 258 with code 284, distance 40000 with code 30
+            code(&mut writer, 0b11000100, 8);
+            writer.write_bits_32(258 - 227, 5).unwrap();
+            code(&mut writer, 30, 5);
+            writer.write_bits_32(40000 - 32769, 14).unwrap();
+            // length 10, distance 3
+            code(&mut writer, 264 - 256, 7);
+            code(&mut writer, 2, 5);
+            code(&mut writer, 0, 7);
+            writer.finish_byte(0).unwrap();
+        }
+
+        let mut expected = random.clone();
+        for &(length, distance) in [(1000, 65536), (258, 40000), (10, 3)].iter() {
+            for _ in 0..length {
+                expected.push(expected[expected.len() - distance]);
+            }
+        }
+
+        let mut decompressor = DeflateDecompressor::new_deflate64(&compressed[..]).unwrap();
+        let mut output = Vec::new();
+        decompressor.read_to_end(&mut output).unwrap();
+        assert!(output == expected);
+        assert_eq!(decompressor.total_in(), compressed.len() as u64);
+
+        // distance code 30 isn't allowed in plain deflate
+        let mut compressed = Vec::new();
+        {
+            let mut writer = BitWriterLSB::new(&mut compressed);
+            writer.write_bits_32(0b011, 3).unwrap();
+            code(&mut writer, 0b00110000 + b'a' as u32, 8);
+            code(&mut writer, 257 - 256, 7);
+            code(&mut writer, 30, 5);
+            writer.write_bits_32(0, 14).unwrap();
+            code(&mut writer, 0, 7);
+            writer.finish_byte(0).unwrap();
+        }
+        let e = DeflateDecompressor::new(&compressed[..]).unwrap()
+            .read_to_end(&mut Vec::new()).unwrap_err();
+        let e = e.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap();
+        assert_eq!(e.kind, InflateErrorKind::BadHuffmanCodes);
+        let e = DeflateDecompressor::new_deflate64(&compressed[..]).unwrap()
+            .read_to_end(&mut Vec::new()).unwrap_err();
+        let e = e.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap();
+        assert_eq!(e.kind, InflateErrorKind::DistanceTooFarBack);
+    }
+
+    #[test]
+    fn test_error_positions() {
+        use crate::io::{DeflateDecompressorError, InflateErrorKind, BlockType};
+
+        let error = |data: &[u8]| {
+            let e = crate::algorithms::inflate(&mut &data[..], &mut Vec::new()).unwrap_err();
+            *e.downcast_ref::<DeflateDecompressorError>().unwrap()
+        };
+
+        // a dynamic block whose first code length is a repeat of the one before
+        let e = error(&[0x05, 0x00, 0x02, 0x24]);
+        assert_eq!(e.kind, InflateErrorKind::RepeatWithoutPrevious);
+        assert_eq!((e.bit_offset, e.block_index, e.block_type), (29, 0, Some(BlockType::Dynamic)));
+
+        // an empty stored block, then a block with btype 3
+        let e = error(&[0x00, 0x00, 0x00, 0xff, 0xff, 0x07]);
+        assert_eq!(e.kind, InflateErrorKind::InvalidBType);
+        assert_eq!((e.bit_offset, e.block_index, e.block_type), (40, 1, None));
+
+        // a stored block whose length doesn't match its complement
+        let e = error(&[0x00, 0x00, 0x00, 0xfe, 0xff]);
+        assert_eq!(e.kind, InflateErrorKind::NonMatchingLenNLen);
+        assert_eq!((e.bit_offset, e.block_index, e.block_type), (8, 0, Some(BlockType::Stored)));
+
+        // just the header of a fixed block
+        let e = error(&[0x03]);
+        assert_eq!(e.kind, InflateErrorKind::UnexpectedEOF);
+        assert_eq!((e.bit_offset, e.block_index, e.block_type), (3, 0, Some(BlockType::Fixed)));
+
+        let e = error(&[]);
+        assert_eq!(e.kind, InflateErrorKind::UnexpectedEOF);
+        assert_eq!((e.bit_offset, e.block_index, e.block_type), (0, 0, None));
+
+        assert_eq!(e.to_string(), "Unexpected end of file at bit 0 (block 0)");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,275 @@
+use super::compressor::{block_bits, dist_code, length_code};
+use super::lz77::{HashChain, MatchFinder, Token, MAX_MATCH, MIN_MATCH};
+use super::{LENGTH_EXTRA, DIST_EXTRA};
+
+/// How many times a block is parsed, each time with the costs from the
+/// parse before.
+const ITERATIONS: usize = 15;
+
+/// How many split points are tried at once when looking for the best place
+/// to split a block.
+const SPLIT_SAMPLES: usize = 9;
+
+/// Every match available at each position of a block, found once and then
+/// reused by each parse.
+struct MatchTable {
+    // for each position, where its matches start in `matches`
+    starts: Vec<usize>,
+    // `(length, distance)` pairs from `MatchFinder::find_matches`
+    matches: Vec<(u16, u16)>,
+}
+
+impl MatchTable {
+    fn new(matcher: &mut HashChain, data: &[u8], base: usize, start: usize, end: usize)
+    -> MatchTable {
+        let mut starts = Vec::with_capacity(end - start + 1);
+        let mut matches = Vec::new();
+        let mut found = Vec::new();
+        for pos in start..end {
+            starts.push(matches.len());
+            found.clear();
+            matcher.find_matches(data, base, pos, std::cmp::min(MAX_MATCH, end - pos), &mut found);
+            matches.extend(found.iter().map(|&(length, distance)| (length as u16, distance as u16)));
+        }
+        starts.push(matches.len());
+        MatchTable { starts, matches }
+    }
+
+    fn at(&self, offset: usize) -> &[(u16, u16)] {
+        &self.matches[self.starts[offset]..self.starts[offset + 1]]
+    }
+}
+
+/// The estimated cost in bits of each literal/length and distance symbol,
+/// extra bits not included.
+struct CostModel {
+    litlen: Vec<f64>,
+    distance: Vec<f64>,
+}
+
+impl CostModel {
+    /// The costs of the fixed Huffman codes.
+    fn fixed() -> CostModel {
+        let litlen = (0..286)
+            .map(|symbol| match symbol {
+                0..=143 => 8.0,
+                144..=255 => 9.0,
+                256..=279 => 7.0,
+                _ => 8.0,
+            })
+            .collect();
+        CostModel { litlen, distance: vec![5.0; 30] }
+    }
+
+    /// The costs of ideal codes for the symbols in `tokens`. Symbols which
+    /// aren't used cost a little more than the rarest one.
+    fn from_tokens(tokens: &[Token]) -> CostModel {
+        let mut litlen_frequencies = vec![0; 286];
+        let mut dist_frequencies = vec![0; 30];
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => litlen_frequencies[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    litlen_frequencies[257 + length_code(length)] += 1;
+                    dist_frequencies[dist_code(distance)] += 1;
+                },
+            }
+        }
+        litlen_frequencies[256] = 1;
+
+        CostModel {
+            litlen: entropy(&litlen_frequencies),
+            distance: entropy(&dist_frequencies),
+        }
+    }
+
+    fn literal(&self, byte: u8) -> f64 {
+        self.litlen[byte as usize]
+    }
+
+    fn length(&self, length: usize) -> f64 {
+        let code = length_code(length);
+        self.litlen[257 + code] + LENGTH_EXTRA[code] as f64
+    }
+
+    fn distance(&self, distance: usize) -> f64 {
+        let code = dist_code(distance);
+        self.distance[code] + DIST_EXTRA[code] as f64
+    }
+}
+
+fn entropy(frequencies: &[u32]) -> Vec<f64> {
+    let total: u32 = frequencies.iter().sum();
+    let log_total = (std::cmp::max(total, 1) as f64).log2();
+    frequencies.iter()
+        .map(|&f| if f == 0 { log_total + 1.0 } else { log_total - (f as f64).log2() })
+        .collect()
+}
+
+/// Finds the cheapest parse of `input` under `model`, where `input` starts
+/// at `offset` in `table`. Matches are cut short at the end of `input`.
+fn shortest_path(table: &MatchTable, input: &[u8], offset: usize, model: &CostModel)
+-> Vec<Token> {
+    let n = input.len();
+    let mut costs = vec![f64::INFINITY; n + 1];
+    // how each position was reached most cheaply, as a `(length, distance)`
+    // step back, with a distance of 0 for a literal
+    let mut steps = vec![(0, 0); n + 1];
+    costs[0] = 0.0;
+
+    for i in 0..n {
+        let cost = costs[i] + model.literal(input[i]);
+        if cost < costs[i + 1] {
+            costs[i + 1] = cost;
+            steps[i + 1] = (1, 0);
+        }
+
+        let mut length = MIN_MATCH;
+        for &(longest, distance) in table.at(offset + i) {
+            let distance = distance as usize;
+            let longest = std::cmp::min(longest as usize, n - i);
+            let distance_cost = costs[i] + model.distance(distance);
+            while length <= longest {
+                let cost = distance_cost + model.length(length);
+                if cost < costs[i + length] {
+                    costs[i + length] = cost;
+                    steps[i + length] = (length, distance);
+                }
+                length += 1;
+            }
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut i = n;
+    while i > 0 {
+        let (length, distance) = steps[i];
+        i -= length;
+        tokens.push(match distance {
+            0 => Token::Literal(input[i]),
+            _ => Token::Match { length, distance },
+        });
+    }
+    tokens.reverse();
+    tokens
+}
+
+/// Parses `input` again and again, each time with the costs of the symbols
+/// the parse before used, starting from `tokens`. Returns whichever parse
+/// encodes smallest.
+fn iterate(table: &MatchTable, input: &[u8], offset: usize, mut tokens: Vec<Token>)
+-> Vec<Token> {
+    let mut best_bits = block_bits(&tokens, input.len());
+    let mut model = CostModel::from_tokens(&tokens);
+    for _ in 0..ITERATIONS {
+        let candidate = shortest_path(table, input, offset, &model);
+        let bits = block_bits(&candidate, input.len());
+        model = CostModel::from_tokens(&candidate);
+        if bits < best_bits {
+            best_bits = bits;
+            tokens = candidate;
+        }
+    }
+    tokens
+}
+
+/// A parse split into blocks, with the offset of each token in the input.
+struct Splitter<'a> {
+    tokens: &'a [Token],
+    offsets: Vec<usize>,
+}
+
+impl<'a> Splitter<'a> {
+    fn new(tokens: &'a [Token]) -> Splitter<'a> {
+        let mut offsets = Vec::with_capacity(tokens.len() + 1);
+        let mut offset = 0;
+        offsets.push(offset);
+        for token in tokens {
+            offset += match *token {
+                Token::Literal(_) => 1,
+                Token::Match { length, .. } => length,
+            };
+            offsets.push(offset);
+        }
+        Splitter { tokens, offsets }
+    }
+
+    /// The size in bits of the tokens `start..end` as a block of their own.
+    fn bits(&self, start: usize, end: usize) -> usize {
+        block_bits(&self.tokens[start..end], self.offsets[end] - self.offsets[start])
+    }
+
+    /// Finds the split point in `start..end` which makes the two halves
+    /// smallest, by trying evenly spaced points and then narrowing in around
+    /// the best.
+    fn best_split(&self, start: usize, end: usize) -> (usize, usize) {
+        let bits = |split| self.bits(start, split) + self.bits(split, end);
+        let (mut low, mut high) = (start + 1, end);
+        loop {
+            if high - low <= SPLIT_SAMPLES {
+                return (low..high).map(|split| (bits(split), split)).min().unwrap();
+            }
+
+            let step = (high - low) / (SPLIT_SAMPLES + 1);
+            let points: Vec<usize> = (1..=SPLIT_SAMPLES).map(|k| low + k * step).collect();
+            let best = (0..SPLIT_SAMPLES).min_by_key(|&k| bits(points[k])).unwrap();
+            if best > 0 {
+                low = points[best - 1];
+            }
+            if best + 1 < SPLIT_SAMPLES {
+                high = points[best + 1];
+            }
+        }
+    }
+
+    /// Appends the token indices at which `start..end` should be split to
+    /// `splits`, so long as splitting makes it smaller.
+    fn split(&self, start: usize, end: usize, splits: &mut Vec<usize>) {
+        if end - start < 2 {
+            return;
+        }
+
+        let (bits, split) = self.best_split(start, end);
+        if bits < self.bits(start, end) {
+            self.split(start, split, splits);
+            splits.push(split);
+            self.split(split, end, splits);
+        }
+    }
+}
+
+/// Parses the positions `start..end` of `data` into blocks of literals and
+/// matches chosen to take the fewest bits, returning each block's tokens and
+/// its length in bytes.
+///
+/// Rather than taking the longest match it can, the parse finds the
+/// cheapest path through every match available under a model of what each
+/// symbol costs. This is repeated with costs taken from the last parse,
+/// keeping the best. The parse is then split wherever separate Huffman
+/// codes pay for their headers, and each block is parsed again with costs
+/// of its own.
+pub fn optimal_parse(matcher: &mut HashChain, data: &[u8], base: usize, start: usize,
+end: usize) -> Vec<(Vec<Token>, usize)> {
+    let table = MatchTable::new(matcher, data, base, start, end);
+    let input = &data[start - base..end - base];
+    let first = shortest_path(&table, input, 0, &CostModel::fixed());
+    let tokens = iterate(&table, input, 0, first);
+
+    let splitter = Splitter::new(&tokens);
+    let mut splits = vec![0];
+    splitter.split(0, tokens.len(), &mut splits);
+    splits.push(tokens.len());
+    if splits.len() == 2 {
+        return vec![(tokens, input.len())];
+    }
+
+    splits.windows(2)
+        .map(|pair| {
+            let block_start = splitter.offsets[pair[0]];
+            let block_end = splitter.offsets[pair[1]];
+            let block_tokens = tokens[pair[0]..pair[1]].to_vec();
+            let block = iterate(&table, &input[block_start..block_end], block_start, block_tokens);
+            (block, block_end - block_start)
+        })
+        .collect()
+}
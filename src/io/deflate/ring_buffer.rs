@@ -42,6 +42,10 @@ impl RingBuffer {
         RingBuffer { data, write: 0, }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
     // pub fn parts(&self) -> (&[u8], &[u8]) {
     //     let first = &self.data[self.write..];
     //     let second = &self.data[..self.write];
@@ -61,36 +65,43 @@ impl RingBuffer {
 
         let mut write = self.write;
         let mut read = (write + self.data.len() - distance) % self.data.len();
-
-        let can_write = self.data.len() - write;
-        let can_read = self.data.len() - read;
-        let len1 = std::cmp::min(can_write, can_read);
-        let len1 = std::cmp::min(len, len1);
-
-        for _ in 0..len1 {
-            self.data[write] = self.data[read];
-            read += 1;
-            write += 1;
+        let mut remaining = len;
+
+        // the copy may wrap around the end of the buffer several times if
+        // the distance is short
+        while remaining > 0 {
+            let can_write = self.data.len() - write;
+            let can_read = self.data.len() - read;
+            let len1 = std::cmp::min(can_write, can_read);
+            let len1 = std::cmp::min(remaining, len1);
+
+            for _ in 0..len1 {
+                self.data[write] = self.data[read];
+                read += 1;
+                write += 1;
+            }
+
+            remaining -= len1;
+            read %= self.data.len();
+            write %= self.data.len();
         }
 
-        let len = len - len1;
-        read %= self.data.len();
-        write %= self.data.len();
+        self.write = write;
 
-        let can_write = self.data.len() - write;
-        let can_read = self.data.len() - read;
-        let len1 = std::cmp::min(can_write, can_read);
-        let len1 = std::cmp::min(len, len1);
+        Ok(())
+    }
 
-        for _ in 0..len1 {
-            self.data[write] = self.data[read];
-            read += 1;
-            write += 1;
+    /// Returns as much as can be had in one slice of the `back_offset` bytes
+    /// before the write position, starting with the oldest. The rest follows
+    /// from the start of the buffer.
+    pub fn contiguous(&self, back_offset: usize) -> &[u8] {
+        assert!(back_offset <= self.data.len());
+        if back_offset == 0 {
+            return &[];
         }
-
-        self.write = write % self.data.len();
-
-        Ok(())
+        let start = (self.write + self.data.len() - back_offset) % self.data.len();
+        let len = std::cmp::min(back_offset, self.data.len() - start);
+        &self.data[start..start + len]
     }
 
     pub fn copy_out(&self, buf: &mut [u8], back_offset: usize) {
@@ -169,4 +180,31 @@ impl std::fmt::Display for RingBufferUnderflowError {
 }
 
 mod tests {
+    #[test]
+    fn test_self_copy_wrapping() {
+        use std::io::Write;
+        use super::RingBuffer;
+        let mut buffer = RingBuffer::new(16);
+        buffer.write(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]).unwrap();
+        buffer.self_copy(3, 10).unwrap();
+
+        let mut output = [0; 10];
+        buffer.copy_out(&mut output, 10);
+        assert_eq!(output, [12, 13, 14, 12, 13, 14, 12, 13, 14, 12]);
+    }
+
+    #[test]
+    fn test_contiguous() {
+        use std::io::Write;
+        use super::RingBuffer;
+        let mut buffer = RingBuffer::new(8);
+        buffer.write_all(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(buffer.contiguous(4), &[3, 4, 5, 6]);
+        assert_eq!(buffer.contiguous(0), &[]);
+
+        buffer.write_all(&[7, 8, 9, 10]).unwrap();
+        assert_eq!(buffer.contiguous(5), &[6, 7, 8]);
+        assert_eq!(buffer.contiguous(2), &[9, 10]);
+        assert_eq!(buffer.contiguous(8), &[3, 4, 5, 6, 7, 8]);
+    }
 }
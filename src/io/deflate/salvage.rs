@@ -0,0 +1,319 @@
+use std::io;
+use super::decoder::{DecodeError, Decoder, ReaderSource, SliceSource, WINDOW_SIZE};
+use super::{BlockType, DeflateDecompressorError, InflateOptions};
+
+/// A stretch of a damaged deflate stream which had to be skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SalvageGap {
+    /// The error which stopped decoding.
+    pub error: DeflateDecompressorError,
+    /// Where in the output the gap is: everything before it was decoded
+    /// before the error.
+    pub output_offset: u64,
+    /// Where decoding carried on, as the bit offset of a block header, or
+    /// `None` if no block after the error could be decoded.
+    pub resumed_at: Option<u64>,
+}
+
+/// What could be decoded from a damaged deflate stream, and where the gaps
+/// in it are.
+///
+/// Decoding goes as far as it can, keeping the output from before the
+/// error. From the start of the block with the error, the stream is then
+/// searched a bit at a time for a plausible block header: a stored block
+/// with a valid LEN and NLEN, or a dynamic block whose code lengths form
+/// valid codes. Decoding carries on from the first such block which
+/// decodes to its end without error.
+///
+/// The data lost in a gap can't be recovered, and matches after it which
+/// reach back across it copy the wrong bytes, so the output just after a
+/// gap may be garbled. A stored block's data can't be checked either, so
+/// a stored block header found by chance salvages garbage.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{DeflateSalvage, InflateErrorKind};
+/// # use stdex::algorithms::deflate;
+/// let mut compressed = Vec::new();
+/// deflate(b"one fish two fish red fish blue fish", &mut compressed);
+/// compressed.truncate(compressed.len() - 4);
+///
+/// let salvage = DeflateSalvage::from_slice(&compressed);
+/// assert!(salvage.output.starts_with(b"one fish two fish"));
+/// assert_eq!(salvage.gaps.len(), 1);
+/// assert_eq!(salvage.gaps[0].error.kind, InflateErrorKind::UnexpectedEOF);
+/// assert_eq!(salvage.gaps[0].resumed_at, None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeflateSalvage {
+    pub output: Vec<u8>,
+    /// Empty if the stream wasn't damaged.
+    pub gaps: Vec<SalvageGap>,
+}
+
+impl DeflateSalvage {
+    /// Decodes as much as possible of the deflate stream at the start of
+    /// `input`.
+    pub fn from_slice(input: &[u8]) -> DeflateSalvage {
+        let mut output = Vec::new();
+        let mut gaps = Vec::new();
+        let mut decoder = Decoder::new(InflateOptions::default());
+        decoder.set_pause_between_blocks(true);
+        let mut scratch = Decoder::new(InflateOptions::default());
+        let (mut block_start, mut block_output) = (0, 0);
+        // whether the last block was a stored block found after a gap which
+        // may really have been the final block
+        let mut maybe_final = false;
+
+        while !decoder.is_finished() {
+            let error = match decode_block(&mut decoder, input, &mut output) {
+                Ok(()) => {
+                    block_start = decoder.bit_position();
+                    block_output = output.len();
+                    maybe_final = false;
+                    continue;
+                },
+                Err(_) if maybe_final => {
+                    output.truncate(block_output);
+                    break;
+                },
+                Err(error) => error,
+            };
+
+            let output_offset = output.len() as u64;
+            match resume(&mut scratch, input, block_start + 1, &mut output, decoder.blocks()) {
+                Some((resumed_at, resumed)) => {
+                    gaps.push(SalvageGap { error, output_offset, resumed_at: Some(resumed_at) });
+                    maybe_final = resumed.block_type() == Some(BlockType::Stored)
+                        && could_be_final(&mut scratch, input, resumed_at);
+                    decoder = resumed;
+                    block_start = decoder.bit_position();
+                    block_output = output.len();
+                },
+                None => {
+                    gaps.push(SalvageGap { error, output_offset, resumed_at: None });
+                    break;
+                },
+            }
+        }
+
+        DeflateSalvage { output, gaps }
+    }
+
+    /// Like `from_slice`, but for the first member of a gzip file. The
+    /// header must be intact, and the trailer isn't checked.
+    pub fn from_gzip(input: &[u8]) -> io::Result<DeflateSalvage> {
+        let mut reader = input;
+        if crate::io::gzip::read_header(&mut reader)?.is_none() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(DeflateSalvage::from_slice(reader))
+    }
+}
+
+/// Decodes to the end of the current block, or as far as an error, adding
+/// the output to `output`.
+fn decode_block(decoder: &mut Decoder, input: &[u8], output: &mut Vec<u8>)
+-> Result<(), DeflateDecompressorError> {
+    let blocks = decoder.blocks();
+    // input is only pulled as it's needed, so the decoder has taken
+    // exactly `total_in` bytes
+    let mut reader = &input[decoder.total_in() as usize..];
+    loop {
+        let result = decoder.run(&mut ReaderSource(&mut reader), WINDOW_SIZE);
+        let start = output.len();
+        output.resize(start + decoder.available(), 0);
+        decoder.copy_out(&mut output[start..]);
+
+        match result {
+            Ok(()) => {},
+            Err(DecodeError::Invalid(e)) => return Err(e),
+            Err(DecodeError::Io(_)) => unreachable!("reading from a slice cannot fail"),
+        }
+        if decoder.is_finished() || (decoder.at_block_boundary() && decoder.blocks() > blocks) {
+            return Ok(());
+        }
+    }
+}
+
+/// The type of the block whose header is `bit_offset` bits into `input`, if
+/// it looks like a real stored or dynamic block, for finding where to carry
+/// on decoding after damage.
+///
+/// A stored block must have a LEN matching its NLEN, zeros padding its
+/// header to a byte boundary, and all its data in the input. A dynamic
+/// block's code lengths must form valid codes, with a code for the end of
+/// the block. `scratch` is any decoder, reused so that each bit tried
+/// doesn't need a new one.
+fn plausible_block_type(scratch: &mut Decoder, input: &[u8], bit_offset: u64) -> Option<BlockType> {
+    let header = bit_offset + 1;
+    let btype = input[(header / 8) as usize..].iter().take(2).enumerate()
+        .fold(0, |bits, (i, &byte)| bits | (byte as u32) << (8 * i)) >> (header % 8) & 3;
+    // anything can be read as the header of a fixed block
+    if btype != 0 && btype != 2 {
+        return None;
+    }
+
+    let partial_byte = input[(bit_offset / 8) as usize];
+    let decoder = scratch;
+    decoder.restart_at(bit_offset, partial_byte);
+    let mut source = SliceSource { data: &input[bit_offset.div_ceil(8) as usize..], position: 0 };
+    if !decoder.read_block_header(&mut source).unwrap_or(false) {
+        return None;
+    }
+
+    let plausible = match decoder.block_type() {
+        Some(BlockType::Stored) => {
+            let padding = (bit_offset + 3) % 8;
+            let zero_padded = padding == 0 || input[((bit_offset + 3) / 8) as usize] >> padding == 0;
+            let remaining = source.data.len() - source.position;
+            zero_padded && decoder.stored_remaining().is_some_and(|len| len <= remaining)
+        },
+        _ => decoder.can_end_block(),
+    };
+    match plausible {
+        true => decoder.block_type(),
+        false => None,
+    }
+}
+
+/// Whether the stored block header found at `bit_offset` could also be read
+/// from a few bits earlier with BFINAL set. `resume` reads stored blocks as
+/// late as possible, taking BFINAL from a zero after the real one, so a
+/// final stored block is only known to be final when nothing decodes after
+/// it.
+fn could_be_final(scratch: &mut Decoder, input: &[u8], bit_offset: u64) -> bool {
+    let boundary = (bit_offset + 3).div_ceil(8);
+    (bit_offset.saturating_sub(7)..bit_offset)
+        .filter(|&earlier| (earlier + 3).div_ceil(8) == boundary)
+        .any(|earlier| input[(earlier / 8) as usize] >> (earlier % 8) & 1 == 1
+            && plausible_block_type(scratch, input, earlier) == Some(BlockType::Stored))
+}
+
+/// Finds the first plausible block header from bit `from` on which decodes
+/// to the end of its block, adding the block's output to `output`. Returns
+/// where the block starts, and a decoder to carry on with.
+fn resume(scratch: &mut Decoder, input: &[u8], from: u64, output: &mut Vec<u8>, block_index: u64)
+-> Option<(u64, Decoder)> {
+    let window_start = output.len().saturating_sub(WINDOW_SIZE);
+    let mut block = Vec::new();
+    for bit_offset in from..input.len() as u64 * 8 {
+        let block_type = match plausible_block_type(scratch, input, bit_offset) {
+            Some(block_type) => block_type,
+            None => continue,
+        };
+        // a stored block header can be read from a few bits early or late,
+        // as long as it ends before the same byte boundary, which gives the
+        // same block with BFINAL taken from a different bit. Read as late
+        // as possible, BFINAL comes from the zeros after BTYPE, so a stored
+        // block found this way never ends the stream early; a final one is
+        // caught by `from_slice` when nothing decodes after it
+        if block_type == BlockType::Stored && (bit_offset + 3) % 8 != 0
+        && plausible_block_type(scratch, input, bit_offset + 1) == Some(BlockType::Stored) {
+            continue;
+        }
+
+        let partial_byte = input[(bit_offset / 8) as usize];
+        let mut decoder = Decoder::resume(InflateOptions::default(), bit_offset, partial_byte,
+            output.len() as u64, block_index, &output[window_start..]);
+        decoder.set_pause_between_blocks(true);
+        block.clear();
+        if decode_block(&mut decoder, input, &mut block).is_ok() {
+            output.extend_from_slice(&block);
+            return Some((bit_offset, decoder));
+        }
+    }
+    None
+}
+
+mod tests {
+    #[test]
+    fn test_salvage() {
+        use std::io::Write;
+        use crate::io::{DeflateCompressor, DeflateOptions, Strategy, DeflateInspection, BlockType};
+        use crate::io::{DeflateSalvage, DeflateDecompressorError, InflateErrorKind, GzEncoder};
+
+        // a log flushed every 100 lines, with no matches to reach back
+        // across a gap
+        let mut gen = crate::random::MT19937_32::from_seed(25);
+        let options = DeflateOptions { strategy: Strategy::HuffmanOnly, ..DeflateOptions::default() };
+        let mut compressor = DeflateCompressor::with_options(Vec::new(), options);
+        let mut input = Vec::new();
+        for i in 0..1000 {
+            let line = format!("{} GET /page/{} {}\n", i, gen.generate() % 1000, gen.generate() % 600);
+            input.extend_from_slice(line.as_bytes());
+            compressor.write_all(line.as_bytes()).unwrap();
+            if i % 100 == 99 {
+                compressor.flush().unwrap();
+            }
+        }
+        let compressed = compressor.finish().unwrap();
+
+        let salvage = DeflateSalvage::from_slice(&compressed);
+        assert_eq!(salvage.output, input);
+        assert!(salvage.gaps.is_empty());
+
+        // damage in one block loses the rest of it, but decoding carries on
+        // from the empty stored block after it
+        let blocks = DeflateInspection::from_reader(&compressed[..]).unwrap().blocks;
+        let damaged_block = blocks.iter().position(|block| block.block_type == BlockType::Dynamic
+            && block.output_offset > 3000).unwrap();
+        let (block, next) = (&blocks[damaged_block], &blocks[damaged_block + 1]);
+        assert_eq!(next.block_type, BlockType::Stored);
+        // most damage to Huffman coded literals just changes them, so find
+        // some that's caught in the block
+        let damage = |data: &[u8], bit: u64| {
+            let mut damaged = data.to_vec();
+            damaged[(bit / 8) as usize] ^= 1 << (bit % 8);
+            damaged
+        };
+        let bit = (block.bit_offset + 3..block.bit_offset + block.bit_len).find(|&bit| {
+            let damaged = damage(&compressed, bit);
+            let e = crate::algorithms::inflate(&mut &damaged[..], &mut Vec::new()).err();
+            e.and_then(|e| e.downcast_ref::<DeflateDecompressorError>().cloned())
+                .is_some_and(|e| e.block_index == damaged_block as u64)
+        }).unwrap();
+        let damaged = damage(&compressed, bit);
+
+        let salvage = DeflateSalvage::from_slice(&damaged);
+        assert_eq!(salvage.gaps.len(), 1);
+        let gap = salvage.gaps[0];
+        assert!(gap.output_offset >= block.output_offset);
+        // read as late as possible, the empty stored block's header is
+        // found in its padding
+        assert!((next.bit_offset..next.bit_offset + 8).contains(&gap.resumed_at.unwrap()));
+        assert_eq!(&salvage.output[..block.output_offset as usize], &input[..block.output_offset as usize]);
+        assert_eq!(&salvage.output[gap.output_offset as usize..], &input[next.output_offset as usize..]);
+
+        // make the empty stored block the final one, with some data, which
+        // found after the gap is read as not final
+        let mut ended = compressed[..((next.bit_offset + next.bit_len) / 8) as usize].to_vec();
+        ended[(next.bit_offset / 8) as usize] |= 1 << (next.bit_offset % 8);
+        let len = ended.len();
+        ended[len - 4..].copy_from_slice(&[8, 0, !8, 0xff]);
+        ended.extend_from_slice(b"the end\n");
+        let salvage = DeflateSalvage::from_slice(&damage(&ended, bit));
+        assert_eq!(salvage.gaps.len(), 1);
+        assert!(salvage.output.ends_with(b"the end\n"));
+        assert_eq!(salvage.output.len() as u64, salvage.gaps[0].output_offset + 8);
+
+        // cut short, everything before the end is kept
+        let salvage = DeflateSalvage::from_slice(&damaged[..damaged.len() / 2]);
+        assert_eq!(salvage.gaps.len(), 2);
+        assert_eq!(salvage.gaps[1].error.kind, InflateErrorKind::UnexpectedEOF);
+        assert_eq!(salvage.gaps[1].resumed_at, None);
+        let (resumed, end) = (salvage.gaps[0].output_offset as usize, salvage.gaps[1].output_offset as usize);
+        let start = next.output_offset as usize;
+        assert!(end > resumed);
+        assert_eq!(&salvage.output[resumed..], &input[start..start + end - resumed]);
+
+        let mut encoder = GzEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(&input).unwrap();
+        let mut gzipped = encoder.finish().unwrap();
+        gzipped.truncate(gzipped.len() - 100);
+        let salvage = DeflateSalvage::from_gzip(&gzipped).unwrap();
+        assert_eq!(salvage.gaps.len(), 1);
+        assert!(input.starts_with(&salvage.output));
+        assert!(DeflateSalvage::from_gzip(&compressed).is_err());
+    }
+}
@@ -0,0 +1,838 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use crate::algorithms::{crc32, crc32_combine, Crc32};
+use crate::io::{DeflateCompressor, DeflateDecompressor, DeflateOptions};
+use crate::io::{read_u8, read_u16_le, read_u32_le, write_u8, write_u16_le, write_u32_le};
+
+const ID1: u8 = 0x1f;
+const ID2: u8 = 0x8b;
+const CM_DEFLATE: u8 = 8;
+
+const FTEXT: u8 = 0x01;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const RESERVED_FLAGS: u8 = 0xe0;
+
+const OS_UNKNOWN: u8 = 255;
+
+/// How much input `ParallelGzEncoder` compresses on each thread at a time.
+const CHUNK_SIZE: usize = 128 * 1024;
+/// How much of the input before each chunk is its dictionary.
+const DICTIONARY_SIZE: usize = 32 * 1024;
+
+/// The header of a gzip member (RFC 1952).
+///
+/// The file name and comment are stored as ISO 8859-1 text, and are kept
+/// here as the raw bytes, without the terminating zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzHeader {
+    /// FTEXT, set if the data is probably text.
+    pub text: bool,
+    /// Modification time of the original file, in seconds since the Unix
+    /// epoch, or 0 if there is none.
+    pub mtime: u32,
+    /// XFL, which for deflate hints at how hard the compressor tried.
+    pub extra_flags: u8,
+    /// The operating system the member was compressed on, 255 if unknown.
+    pub os: u8,
+    /// The FEXTRA field.
+    pub extra: Option<Vec<u8>>,
+    /// The FNAME field, the name of the original file.
+    pub filename: Option<Vec<u8>>,
+    /// The FCOMMENT field.
+    pub comment: Option<Vec<u8>>,
+}
+
+impl GzHeader {
+    /// Returns the modification time, if there is one.
+    pub fn modified(&self) -> Option<std::time::SystemTime> {
+        match self.mtime {
+            0 => None,
+            mtime => {
+                let since_epoch = std::time::Duration::from_secs(mtime as u64);
+                Some(std::time::SystemTime::UNIX_EPOCH + since_epoch)
+            }
+        }
+    }
+}
+
+impl Default for GzHeader {
+    fn default() -> GzHeader {
+        GzHeader {
+            text: false,
+            mtime: 0,
+            extra_flags: 0,
+            os: OS_UNKNOWN,
+            extra: None,
+            filename: None,
+            comment: None,
+        }
+    }
+}
+
+/// Passes reads through, keeping a CRC-32 of everything read.
+struct CrcReader<'a, R: Read> {
+    reader: &'a mut R,
+    crc: Crc32,
+}
+
+impl<'a, R: Read> Read for CrcReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn read_zero_terminated<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    loop {
+        match read_u8(reader)? {
+            0 => return Ok(result),
+            byte => result.push(byte),
+        }
+    }
+}
+
+/// Reads the header of a gzip member, or returns `None` if the reader is
+/// already at its end.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> io::Result<Option<GzHeader>> {
+    let mut id1 = [0; 1];
+    loop {
+        match reader.read(&mut id1) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut reader = CrcReader { reader, crc: Crc32::new() };
+    reader.crc.update(&id1);
+
+    let id2 = read_u8(&mut reader)?;
+    if id1[0] != ID1 || id2 != ID2 {
+        return Err(GzError::BadMagic.into());
+    }
+
+    let cm = read_u8(&mut reader)?;
+    if cm != CM_DEFLATE {
+        return Err(GzError::UnsupportedMethod(cm).into());
+    }
+
+    let flags = read_u8(&mut reader)?;
+    if flags & RESERVED_FLAGS != 0 {
+        return Err(GzError::ReservedFlags(flags).into());
+    }
+
+    let mut header = GzHeader {
+        text: flags & FTEXT != 0,
+        mtime: read_u32_le(&mut reader)?,
+        extra_flags: read_u8(&mut reader)?,
+        os: read_u8(&mut reader)?,
+        extra: None,
+        filename: None,
+        comment: None,
+    };
+
+    if flags & FEXTRA != 0 {
+        let len = read_u16_le(&mut reader)?;
+        let mut extra = vec![0; len as usize];
+        reader.read_exact(&mut extra)?;
+        header.extra = Some(extra);
+    }
+
+    if flags & FNAME != 0 {
+        header.filename = Some(read_zero_terminated(&mut reader)?);
+    }
+
+    if flags & FCOMMENT != 0 {
+        header.comment = Some(read_zero_terminated(&mut reader)?);
+    }
+
+    if flags & FHCRC != 0 {
+        let computed = reader.crc.value() as u16;
+        let expected = read_u16_le(reader.reader)?;
+        if expected != computed {
+            return Err(GzError::HeaderChecksumMismatch { expected, computed }.into());
+        }
+    }
+
+    Ok(Some(header))
+}
+
+fn write_header<W: Write>(writer: &mut W, header: &GzHeader) -> io::Result<()> {
+    let mut flags = 0;
+    if header.text { flags |= FTEXT; }
+    if header.extra.is_some() { flags |= FEXTRA; }
+    if header.filename.is_some() { flags |= FNAME; }
+    if header.comment.is_some() { flags |= FCOMMENT; }
+
+    write_u8(writer, ID1)?;
+    write_u8(writer, ID2)?;
+    write_u8(writer, CM_DEFLATE)?;
+    write_u8(writer, flags)?;
+    write_u32_le(writer, header.mtime)?;
+    write_u8(writer, header.extra_flags)?;
+    write_u8(writer, header.os)?;
+
+    if let Some(ref extra) = header.extra {
+        if extra.len() > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "gzip extra field too long"));
+        }
+        write_u16_le(writer, extra.len() as u16)?;
+        writer.write_all(extra)?;
+    }
+
+    for text in [&header.filename, &header.comment].iter() {
+        if let Some(ref text) = **text {
+            if text.contains(&0) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    "gzip file name or comment contains a zero byte"));
+            }
+            writer.write_all(text)?;
+            write_u8(writer, 0)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decompresses a gzip file (RFC 1952).
+///
+/// Each member's CRC-32 and size are verified at its end. If another member
+/// follows, it is decompressed too, so concatenated gzip files read back as
+/// the concatenation of their contents, as with `gunzip`.
+///
+/// # Example
+/// ```
+/// # use stdex::io::GzDecoder;
+/// # use std::io::Read;
+/// let compressed = [
+///     0x1f, 0x8b, 0x08, 0x08, 0xd2, 0x02, 0x96, 0x49, 0x02, 0xff, 0x68, 0x65,
+///     0x6c, 0x6c, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, 0xcb, 0x48, 0xcd, 0xc9,
+///     0xc9, 0x57, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0xe1, 0x02, 0x00, 0x2d, 0x3b,
+///     0x08, 0xaf, 0x0c, 0x00, 0x00, 0x00,
+/// ];
+/// let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+/// assert_eq!(decoder.header().filename, Some(b"hello.txt".to_vec()));
+/// assert_eq!(decoder.header().mtime, 1234567890);
+///
+/// let mut output = String::new();
+/// decoder.read_to_string(&mut output).unwrap();
+/// assert_eq!(output, "hello world\n");
+/// ```
+pub struct GzDecoder<R: Read> {
+    // `None` once every member has been read
+    decompressor: Option<DeflateDecompressor<R>>,
+    header: GzHeader,
+    crc: Crc32,
+    size: u32,
+}
+
+impl<R: Read> GzDecoder<R> {
+    /// Reads the header of the first member, then the start of its deflate
+    /// stream.
+    pub fn new(mut reader: R) -> io::Result<GzDecoder<R>> {
+        let header = match read_header(&mut reader)? {
+            Some(header) => header,
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "empty gzip file")),
+        };
+
+        Ok(GzDecoder {
+            decompressor: Some(DeflateDecompressor::new(reader)?),
+            header,
+            crc: Crc32::new(),
+            size: 0,
+        })
+    }
+
+    /// Returns the header of the member currently being read.
+    pub fn header(&self) -> &GzHeader {
+        &self.header
+    }
+
+    /// Checks the trailer of the member just finished, and moves on to the
+    /// next member if there is one.
+    fn finish_member(&mut self) -> io::Result<()> {
+        let header = {
+            let reader = self.decompressor.as_mut().unwrap().get_mut();
+            let expected = read_u32_le(reader)?;
+            let computed = self.crc.value();
+            if expected != computed {
+                return Err(GzError::ChecksumMismatch { expected, computed }.into());
+            }
+
+            let expected = read_u32_le(reader)?;
+            let computed = self.size;
+            if expected != computed {
+                return Err(GzError::SizeMismatch { expected, computed }.into());
+            }
+
+            read_header(reader)?
+        };
+
+        let decompressor = self.decompressor.take().unwrap();
+        if let Some(header) = header {
+            let reader = decompressor.into_inner();
+            self.decompressor = Some(DeflateDecompressor::new(reader)?);
+            self.header = header;
+            self.crc = Crc32::new();
+            self.size = 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let n = match self.decompressor.as_mut() {
+                Some(decompressor) => decompressor.read(buf)?,
+                None => return Ok(0),
+            };
+
+            if n != 0 {
+                self.crc.update(&buf[..n]);
+                self.size = self.size.wrapping_add(n as u32);
+                return Ok(n);
+            }
+
+            self.finish_member()?;
+        }
+    }
+}
+
+/// Compresses data into a single member gzip file (RFC 1952).
+///
+/// The header is written when the encoder is created. `finish()` must be
+/// called to write the final deflate block and the trailer, and to get back
+/// the underlying writer.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{GzDecoder, GzEncoder, GzHeader};
+/// # use std::io::{Read, Write};
+/// let header = GzHeader {
+///     filename: Some(b"notes.txt".to_vec()),
+///     mtime: 1546300800,
+///     ..GzHeader::default()
+/// };
+/// let mut encoder = GzEncoder::with_header(Vec::new(), &header).unwrap();
+/// encoder.write_all(b"remember the milk").unwrap();
+/// let compressed = encoder.finish().unwrap();
+///
+/// let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+/// assert_eq!(decoder.header(), &header);
+/// let mut output = String::new();
+/// decoder.read_to_string(&mut output).unwrap();
+/// assert_eq!(output, "remember the milk");
+/// ```
+pub struct GzEncoder<W: Write> {
+    compressor: DeflateCompressor<W>,
+    crc: Crc32,
+    size: u32,
+}
+
+impl<W: Write> GzEncoder<W> {
+    /// Creates an encoder which writes a header with no file name or
+    /// modification time.
+    pub fn new(writer: W) -> io::Result<GzEncoder<W>> {
+        GzEncoder::with_header(writer, &GzHeader::default())
+    }
+
+    pub fn with_header(mut writer: W, header: &GzHeader) -> io::Result<GzEncoder<W>> {
+        write_header(&mut writer, header)?;
+        Ok(GzEncoder {
+            compressor: DeflateCompressor::new(writer),
+            crc: Crc32::new(),
+            size: 0,
+        })
+    }
+
+    /// Finishes the deflate stream, writes the trailer, and returns the
+    /// underlying `Write` object.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self.compressor.finish()?;
+        write_u32_le(&mut writer, self.crc.value())?;
+        write_u32_le(&mut writer, self.size)?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.compressor.write(buf)?;
+        self.crc.update(&buf[..n]);
+        self.size = self.size.wrapping_add(n as u32);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.compressor.flush()
+    }
+}
+
+/// A chunk of input for a `ParallelGzEncoder` thread to compress.
+struct Job {
+    index: u64,
+    // the dictionary, followed by the chunk
+    input: Vec<u8>,
+    dictionary_len: usize,
+    last: bool,
+}
+
+/// A compressed chunk, with the CRC-32 and length of its input.
+struct Compressed {
+    output: Vec<u8>,
+    crc: u32,
+    len: usize,
+}
+
+impl Job {
+    fn compress(&self, options: DeflateOptions) -> io::Result<Compressed> {
+        let (dictionary, chunk) = self.input.split_at(self.dictionary_len);
+        let mut compressor = DeflateCompressor::with_options(Vec::new(), options);
+        compressor.set_dictionary(dictionary);
+        compressor.write_all(chunk)?;
+        let output = match self.last {
+            true => compressor.finish()?,
+            false => compressor.into_inner()?,
+        };
+        Ok(Compressed { output, crc: crc32(chunk), len: chunk.len() })
+    }
+}
+
+/// The threads of a `ParallelGzEncoder`. Each takes jobs from a shared
+/// queue, and sends back the compressed chunk tagged with the job's index.
+struct Workers {
+    jobs: Option<mpsc::Sender<Job>>,
+    results: mpsc::Receiver<(u64, io::Result<Compressed>)>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Workers {
+    fn new(count: usize, options: DeflateOptions) -> Workers {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        let (sender, results) = mpsc::channel();
+        let threads = (0..count)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let sender = sender.clone();
+                thread::spawn(move || loop {
+                    // the lock is only held while waiting for a job
+                    let job = match queue.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    if sender.send((job.index, job.compress(options))).is_err() {
+                        return;
+                    }
+                })
+            })
+            .collect();
+        Workers { jobs: Some(jobs), results, threads }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        // closing the queue makes each thread stop once it's idle
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Compresses data into a single member gzip file like `GzEncoder`, but on
+/// several threads at once, the way pigz does.
+///
+/// Input is gathered into 128 KiB chunks, which are queued for a pool of
+/// threads to compress, each with the 32 KiB of input before it as a preset
+/// dictionary, so little is lost to compressing them separately. Each chunk
+/// but the last ends with a sync flush, so that they join up into a single
+/// deflate stream, and the CRC-32 of each is combined for the trailer.
+///
+/// Compressed chunks are written out in order as they come back, so `write`
+/// only waits when every thread already has a chunk to compress.
+///
+/// The output decompresses to the same data as `GzEncoder`'s, but isn't
+/// byte for byte the same.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{GzDecoder, GzHeader, ParallelGzEncoder};
+/// # use stdex::io::DeflateOptions;
+/// # use std::io::{Read, Write};
+/// let input = b"all work and no play makes jack a dull boy\n".repeat(10_000);
+/// let mut encoder = ParallelGzEncoder::with_options(Vec::new(), &GzHeader::default(),
+///     DeflateOptions::default(), 4).unwrap();
+/// encoder.write_all(&input).unwrap();
+/// let compressed = encoder.finish().unwrap();
+///
+/// let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+/// let mut output = Vec::new();
+/// decoder.read_to_end(&mut output).unwrap();
+/// assert_eq!(output, input);
+/// ```
+pub struct ParallelGzEncoder<W: Write> {
+    writer: W,
+    threads: usize,
+    workers: Workers,
+    // up to a dictionary's worth of the input already queued
+    dictionary: Vec<u8>,
+    chunk: Vec<u8>,
+    queued: u64,
+    written: u64,
+    // compressed chunks which came back before the ones ahead of them
+    reorder: BTreeMap<u64, Compressed>,
+    crc: u32,
+    size: u32,
+}
+
+impl<W: Write> ParallelGzEncoder<W> {
+    /// Creates an encoder which uses as many threads as there are CPUs, and
+    /// writes a header with no file name or modification time.
+    pub fn new(writer: W) -> io::Result<ParallelGzEncoder<W>> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        ParallelGzEncoder::with_options(writer, &GzHeader::default(), DeflateOptions::default(),
+            threads)
+    }
+
+    /// Creates an encoder which compresses on `threads` threads with the
+    /// given level and strategy.
+    ///
+    /// # Panics
+    /// If `threads` is 0, or the level is over 9.
+    pub fn with_options(mut writer: W, header: &GzHeader, options: DeflateOptions,
+    threads: usize) -> io::Result<ParallelGzEncoder<W>> {
+        assert!(threads > 0, "at least one thread is needed");
+        assert!(options.level <= 9, "compression level must be from 0 to 9");
+        write_header(&mut writer, header)?;
+        Ok(ParallelGzEncoder {
+            writer,
+            threads,
+            workers: Workers::new(threads, options),
+            dictionary: Vec::new(),
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            queued: 0,
+            written: 0,
+            reorder: BTreeMap::new(),
+            crc: 0,
+            size: 0,
+        })
+    }
+
+    /// Compresses any remaining input, ending the deflate stream, writes the
+    /// trailer, and returns the underlying `Write` object.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.queue_chunk(true)?;
+        self.wait_for_all()?;
+        write_u32_le(&mut self.writer, self.crc)?;
+        write_u32_le(&mut self.writer, self.size)?;
+        Ok(self.writer)
+    }
+
+    /// Queues the chunk being gathered to be compressed, first waiting for a
+    /// thread to be free, and writes out whatever has already come back.
+    fn queue_chunk(&mut self, last: bool) -> io::Result<()> {
+        while self.queued - self.written == self.threads as u64 {
+            self.receive()?;
+        }
+
+        let dictionary_len = self.dictionary.len();
+        let mut input = Vec::with_capacity(dictionary_len + self.chunk.len());
+        input.extend_from_slice(&self.dictionary);
+        input.extend_from_slice(&self.chunk);
+        self.chunk.clear();
+        self.dictionary = input[input.len().saturating_sub(DICTIONARY_SIZE)..].to_vec();
+        let job = Job { index: self.queued, input, dictionary_len, last };
+        self.workers.jobs.as_ref().unwrap().send(job).map_err(|_| thread_failed())?;
+        self.queued += 1;
+
+        while let Ok(result) = self.workers.results.try_recv() {
+            self.accept(result)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for a chunk to come back, and writes out any which are next.
+    fn receive(&mut self) -> io::Result<()> {
+        let result = self.workers.results.recv().map_err(|_| thread_failed())?;
+        self.accept(result)
+    }
+
+    fn wait_for_all(&mut self) -> io::Result<()> {
+        while self.written < self.queued {
+            self.receive()?;
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, (index, result): (u64, io::Result<Compressed>)) -> io::Result<()> {
+        self.reorder.insert(index, result?);
+        while let Some(compressed) = self.reorder.remove(&self.written) {
+            self.writer.write_all(&compressed.output)?;
+            self.crc = crc32_combine(self.crc, compressed.crc, compressed.len as u64);
+            self.size = self.size.wrapping_add(compressed.len as u32);
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+fn thread_failed() -> io::Error {
+    io::Error::other("a compression thread stopped")
+}
+
+impl<W: Write> Write for ParallelGzEncoder<W> {
+    /// Takes as much of `buf` as fits in the chunk being gathered, and
+    /// queues the chunk once it's full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = std::cmp::min(CHUNK_SIZE - self.chunk.len(), buf.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        if self.chunk.len() == CHUNK_SIZE {
+            self.queue_chunk(false)?;
+        }
+        Ok(n)
+    }
+
+    /// Compresses everything written so far, ending with a sync flush, and
+    /// flushes the underlying writer.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.chunk.is_empty() {
+            self.queue_chunk(false)?;
+        }
+        self.wait_for_all()?;
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GzError {
+    BadMagic,
+    UnsupportedMethod(u8),
+    ReservedFlags(u8),
+    HeaderChecksumMismatch { expected: u16, computed: u16 },
+    ChecksumMismatch { expected: u32, computed: u32 },
+    SizeMismatch { expected: u32, computed: u32 },
+}
+
+impl std::fmt::Display for GzError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::GzError::*;
+        match self {
+            BadMagic => write!(f, "Not a gzip member (bad magic number)"),
+            UnsupportedMethod(method) => write!(f, "Unsupported gzip compression method {}", method),
+            ReservedFlags(flags) => write!(f, "Reserved gzip flags set ({:02x})", flags),
+            HeaderChecksumMismatch { expected, computed } => write!(f,
+                "gzip header CRC mismatch: stream says {:04x}, header is {:04x}", expected, computed),
+            ChecksumMismatch { expected, computed } => write!(f,
+                "gzip CRC-32 mismatch: stream says {:08x}, data is {:08x}", expected, computed),
+            SizeMismatch { expected, computed } => write!(f,
+                "gzip size mismatch: stream says {}, data is {} (mod 2^32)", expected, computed),
+        }
+    }
+}
+
+impl std::error::Error for GzError {}
+
+impl From<GzError> for io::Error {
+    fn from(e: GzError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+mod tests {
+    #[cfg(test)]
+    fn gz_error(e: std::io::Error) -> super::GzError {
+        *e.get_ref().unwrap().downcast_ref::<super::GzError>().unwrap()
+    }
+
+    #[cfg(test)]
+    const HELLO: [u8; 42] = [
+        0x1f, 0x8b, 0x08, 0x08, 0xd2, 0x02, 0x96, 0x49, 0x02, 0xff, 0x68, 0x65,
+        0x6c, 0x6c, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, 0xcb, 0x48, 0xcd, 0xc9,
+        0xc9, 0x57, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0xe1, 0x02, 0x00, 0x2d, 0x3b,
+        0x08, 0xaf, 0x0c, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_multiple_members() {
+        use std::io::{Read, Write};
+        use crate::io::{GzDecoder, GzEncoder};
+
+        let mut encoder = GzEncoder::new(HELLO.to_vec()).unwrap();
+        encoder.write_all(b"second member\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+        let mut output = String::new();
+        decoder.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "hello world\nsecond member\n");
+        assert_eq!(decoder.header().filename, None);
+    }
+
+    #[test]
+    fn test_header_fields() {
+        use std::io::Read;
+        use crate::algorithms::crc32;
+        use crate::io::GzDecoder;
+
+        let mut compressed = vec![0x1f, 0x8b, 0x08, 0x1f, 0, 0, 0, 0, 0x02, 0x03];
+        compressed.extend_from_slice(&[4, 0, b'A', b'B', 0, 0]);
+        compressed.extend_from_slice(b"name\0comment\0");
+        let header_crc = crc32(&compressed) as u16;
+        compressed.extend_from_slice(&[header_crc as u8, (header_crc >> 8) as u8]);
+        compressed.extend_from_slice(&[0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+        {
+            let header = decoder.header();
+            assert!(header.text);
+            assert_eq!(header.extra_flags, 2);
+            assert_eq!(header.os, 3);
+            assert_eq!(header.extra, Some(vec![b'A', b'B', 0, 0]));
+            assert_eq!(header.filename, Some(b"name".to_vec()));
+            assert_eq!(header.comment, Some(b"comment".to_vec()));
+            assert_eq!(header.modified(), None);
+        }
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output.is_empty());
+
+        let len = compressed.len();
+        compressed[len - 12] ^= 1;
+        let e = GzDecoder::new(&compressed[..]).err().unwrap();
+        assert_eq!(gz_error(e), super::GzError::HeaderChecksumMismatch {
+            expected: header_crc ^ 1,
+            computed: header_crc,
+        });
+    }
+
+    #[test]
+    fn test_bad_trailer() {
+        use std::io::Read;
+        use crate::io::{GzDecoder, GzError};
+
+        let mut compressed = HELLO;
+        compressed[38] = 13;
+        let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+        let e = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(gz_error(e), GzError::SizeMismatch { expected: 13, computed: 12 });
+
+        let mut compressed = HELLO;
+        compressed[34] = 0;
+        let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+        let e = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(gz_error(e), GzError::ChecksumMismatch {
+            expected: 0xaf083b00,
+            computed: 0xaf083b2d,
+        });
+
+        let mut compressed = HELLO.to_vec();
+        compressed.extend_from_slice(b"junk");
+        let mut decoder = GzDecoder::new(&compressed[..]).unwrap();
+        let e = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(gz_error(e), GzError::BadMagic);
+    }
+
+    #[test]
+    fn test_parallel() {
+        use std::io::{Read, Write};
+        use crate::io::{DeflateInspection, DeflateOptions, GzDecoder, GzEncoder, GzHeader};
+        use crate::io::ParallelGzEncoder;
+        use crate::random::MT19937_32;
+
+        let words = ["build ", "artifact ", "linker ", "object ", "archive ", "debug\n"];
+        let mut gen = MT19937_32::from_seed(20);
+        let mut input = Vec::new();
+        while input.len() < 700_000 {
+            let word = words[(gen.generate() % words.len() as u32) as usize];
+            input.extend_from_slice(word.as_bytes());
+        }
+
+        let header = GzHeader { filename: Some(b"out.bin".to_vec()), ..GzHeader::default() };
+        let compress = |input: &[u8], threads| {
+            let options = DeflateOptions::default();
+            let mut encoder = ParallelGzEncoder::with_options(Vec::new(), &header, options, threads)
+                .unwrap();
+            for piece in input.chunks(100_000) {
+                encoder.write_all(piece).unwrap();
+            }
+            encoder.finish().unwrap()
+        };
+        let decompress = |compressed: &[u8]| {
+            let mut decoder = GzDecoder::new(compressed).unwrap();
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(decoder.header(), &header);
+            output
+        };
+
+        let mut encoder = GzEncoder::with_header(Vec::new(), &header).unwrap();
+        encoder.write_all(&input).unwrap();
+        let serial = encoder.finish().unwrap();
+
+        for &threads in [1, 3].iter() {
+            let compressed = compress(&input, threads);
+            assert_eq!(decompress(&compressed), input);
+
+            // one member, holding one deflate stream with one final block
+            let header_len = 10 + b"out.bin\0".len();
+            let deflate = &compressed[header_len..compressed.len() - 8];
+            let inspection = DeflateInspection::from_reader(deflate).unwrap();
+            assert_eq!(inspection.total_in, deflate.len() as u64);
+            assert_eq!(inspection.blocks.iter().filter(|block| block.final_block).count(), 1);
+
+            // the dictionaries keep the chunks from costing much
+            assert!(compressed.len() < serial.len() + serial.len() / 50);
+        }
+
+        assert_eq!(decompress(&compress(b"", 2)), b"");
+        assert_eq!(decompress(&compress(b"tiny", 2)), b"tiny");
+
+        // flushing part way through makes an extra chunk boundary
+        let options = DeflateOptions::default();
+        let mut encoder = ParallelGzEncoder::with_options(Vec::new(), &header, options, 2).unwrap();
+        encoder.write_all(&input[..1000]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&input[1000..]).unwrap();
+        assert_eq!(decompress(&encoder.finish().unwrap()), input);
+
+        // chunks are written as they come back, not all at the end
+        let written = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut encoder = ParallelGzEncoder::with_options(SharedWriter(written.clone()), &header,
+            options, 2).unwrap();
+        encoder.write_all(&input[..5 * 128 * 1024]).unwrap();
+        assert!(written.borrow().len() > 1000);
+        encoder.write_all(&input[5 * 128 * 1024..]).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(decompress(&written.borrow()), input);
+    }
+
+    #[cfg(test)]
+    struct SharedWriter(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    #[cfg(test)]
+    impl std::io::Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -8,7 +8,30 @@ pub use self::bitio::{
 };
 
 mod deflate;
-pub use self::deflate::DeflateDecompressor;
+pub use self::deflate::{
+    DeflateDecompressor, DeflateCompressor, DeflateOptions, Strategy, Inflater, InflateStatus, Progress,
+    InflateOptions, Limit, DeflateDecompressorError, InflateIntoError, InflateErrorKind, BlockType,
+    DeflateIndex, Checkpoint, SeekableDeflateReader, DeflateIndexError,
+    DeflateInspection, BlockInfo, TokenInfo, DeflateToken, DeflateSalvage, SalvageGap
+};
+pub(crate) use self::deflate::{inflate_to_vec, inflate_into};
+#[cfg(test)]
+pub(crate) use self::deflate::RingBuffer;
+
+mod zlib;
+pub use self::zlib::{ZlibDecoder, ZlibEncoder, ZlibError};
+
+mod gzip;
+pub use self::gzip::{GzDecoder, GzEncoder, ParallelGzEncoder, GzHeader, GzError};
+
+mod zip;
+pub use self::zip::{
+    ZipArchive, ZipEntry, ZipEntryReader, ZipWriter, FileOptions,
+    CompressionMethod, DosDateTime, ZipError
+};
+
+mod png;
+pub use self::png::{PngDecoder, PngEncoder, PngHeader, PngOptions, ColorType, Image, Pixels, PngError};
 
 unsafe fn as_u8_slice<T>(data: &T) -> &[u8] {
     let ptr = data as *const T as *const u8;
@@ -60,13 +83,13 @@ macro_rules! impl_endian_readers {
         pub fn $write_be(writer: &mut impl Write, item: $type)
         -> io::Result<()> {
             let item = <$type>::to_be(item);
-            write_item(writer, &item)
+            write_item(writer, item)
         }
 
         pub fn $write_le(writer: &mut impl Write, item: $type)
         -> io::Result<()> {
             let item = <$type>::to_le(item);
-            write_item(writer, &item)
+            write_item(writer, item)
         }
     };
 }
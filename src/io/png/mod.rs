@@ -0,0 +1,264 @@
+mod reader;
+pub use self::reader::PngDecoder;
+
+mod writer;
+pub use self::writer::PngEncoder;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const IHDR: [u8; 4] = *b"IHDR";
+const PLTE: [u8; 4] = *b"PLTE";
+const TRNS: [u8; 4] = *b"tRNS";
+const IDAT: [u8; 4] = *b"IDAT";
+const IEND: [u8; 4] = *b"IEND";
+
+/// The Adam7 passes, as `(x, y, x step, y step)`: each covers the pixels
+/// from `(x, y)` onwards, `x step` apart across and `y step` apart down.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// How the pixels of a PNG image are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    /// Each pixel is an index into a palette.
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    fn from_u8(color_type: u8) -> Option<ColorType> {
+        match color_type {
+            0 => Some(ColorType::Grayscale),
+            2 => Some(ColorType::Rgb),
+            3 => Some(ColorType::Indexed),
+            4 => Some(ColorType::GrayscaleAlpha),
+            6 => Some(ColorType::Rgba),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// The number of samples in each pixel.
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Whether a PNG image of this color type can have this bit depth.
+    pub fn allows_bit_depth(self, bit_depth: u8) -> bool {
+        match self {
+            ColorType::Grayscale => [1, 2, 4, 8, 16].contains(&bit_depth),
+            ColorType::Indexed => [1, 2, 4, 8].contains(&bit_depth),
+            _ => bit_depth == 8 || bit_depth == 16,
+        }
+    }
+}
+
+/// The IHDR chunk of a PNG image, describing its size and format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PngHeader {
+    pub width: u32,
+    pub height: u32,
+    /// The number of bits in each sample, or in each palette index.
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    /// Whether the image is stored as Adam7 passes.
+    pub interlaced: bool,
+}
+
+impl PngHeader {
+    /// Whether PNG allows the header's size and bit depth, and the number
+    /// of bytes in an image of that size fits in a `usize`. There may still
+    /// not be the memory for it; `PngOptions` limits what's decoded.
+    fn is_valid(&self) -> bool {
+        let max_size = (1 << 31) - 1;
+        self.width != 0 && self.width <= max_size && self.height != 0 && self.height <= max_size
+            && self.color_type.allows_bit_depth(self.bit_depth)
+            && (self.width as usize).checked_mul(self.height as usize)
+                .and_then(|pixels| pixels.checked_mul(8)).is_some()
+    }
+
+    /// The number of bytes in a row of `width` pixels, filter type byte not
+    /// included.
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.color_type.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    /// The distance between a byte and the byte of the pixel before which
+    /// it's filtered against.
+    fn filter_distance(&self) -> usize {
+        std::cmp::max(1, self.color_type.channels() * self.bit_depth as usize / 8)
+    }
+
+    /// The number of bytes the decoded RGBA pixels take.
+    fn decoded_bytes(&self) -> u64 {
+        let bytes_per_pixel = if self.bit_depth == 16 { 8 } else { 4 };
+        self.width as u64 * self.height as u64 * bytes_per_pixel
+    }
+
+    /// The length of the filtered image data, every row of every pass with
+    /// its filter type byte.
+    fn image_data_len(&self) -> u64 {
+        self.passes().iter()
+            .map(|&(_, _, _, _, width, height)| (self.row_bytes(width) as u64 + 1) * height as u64)
+            .sum()
+    }
+
+    /// The `(x, y, x step, y step, width, height)` of each pass the image is
+    /// stored as, leaving out empty passes.
+    fn passes(&self) -> Vec<(usize, usize, usize, usize, usize, usize)> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        if !self.interlaced {
+            return vec![(0, 0, 1, 1, width, height)];
+        }
+
+        ADAM7.iter()
+            .map(|&(x, y, dx, dy)| {
+                (x, y, dx, dy, (width + dx - 1 - x) / dx, (height + dy - 1 - y) / dy)
+            })
+            .filter(|pass| pass.4 != 0 && pass.5 != 0)
+            .collect()
+    }
+}
+
+/// Limits on the size of the images `PngDecoder` decodes, for reading
+/// untrusted files. Every limit is off by default.
+///
+/// The limits are checked against the IHDR chunk, so an image which exceeds
+/// one fails with `PngError::LimitExceeded` before any of it is decoded.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{PngDecoder, PngError, PngOptions};
+/// // the header of a 200000x200000 image, which would take 320 GB
+/// let png = [
+///     0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d,
+///     0x49, 0x48, 0x44, 0x52, 0x00, 0x03, 0x0d, 0x40, 0x00, 0x03, 0x0d, 0x40,
+///     0x10, 0x06, 0x00, 0x00, 0x00, 0xa9, 0xab, 0x54, 0x49,
+/// ];
+/// let options = PngOptions { max_pixels: Some(50_000_000), ..PngOptions::default() };
+/// let e = PngDecoder::with_options(&png[..], options).err().unwrap();
+/// let e = e.get_ref().unwrap().downcast_ref::<PngError>().unwrap();
+/// assert_eq!(*e, PngError::LimitExceeded);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PngOptions {
+    /// The most pixels allowed, width times height.
+    pub max_pixels: Option<u64>,
+    /// The most bytes allowed for the decoded pixels: 4 a pixel, or 8 if
+    /// the bit depth is 16.
+    pub max_bytes: Option<u64>,
+}
+
+impl PngOptions {
+    fn allows(&self, header: &PngHeader) -> bool {
+        let pixels = header.width as u64 * header.height as u64;
+        self.max_pixels.is_none_or(|max| pixels <= max)
+            && self.max_bytes.is_none_or(|max| header.decoded_bytes() <= max)
+    }
+}
+
+/// The pixels of an image, row by row from the top, as red, green, blue
+/// and alpha samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pixels {
+    Rgba8(Vec<u8>),
+    Rgba16(Vec<u16>),
+}
+
+/// A decoded image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Pixels,
+}
+
+/// The Paeth predictor: whichever of the bytes to the left, above, and
+/// above left is closest to `left + above - above_left`.
+fn paeth(left: u8, above: u8, above_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - above_left as i16;
+    let to_left = (estimate - left as i16).abs();
+    let to_above = (estimate - above as i16).abs();
+    let to_above_left = (estimate - above_left as i16).abs();
+    if to_left <= to_above && to_left <= to_above_left {
+        left
+    } else if to_above <= to_above_left {
+        above
+    } else {
+        above_left
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngError {
+    BadSignature,
+    ChecksumMismatch { chunk: [u8; 4], expected: u32, computed: u32 },
+    /// The first chunk isn't IHDR.
+    MissingHeader,
+    /// The IHDR chunk has a size, bit depth or color type PNG doesn't allow.
+    InvalidHeader,
+    /// A chunk the decoder needs to understand, but doesn't.
+    UnknownCriticalChunk([u8; 4]),
+    /// A chunk whose length is wrong for its type.
+    InvalidChunk([u8; 4]),
+    MissingPalette,
+    PaletteIndexOutOfRange(u8),
+    MissingImageData,
+    InvalidFilter(u8),
+    /// The image is bigger than the `PngOptions` allow.
+    LimitExceeded,
+}
+
+impl std::fmt::Display for PngError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::PngError::*;
+        match self {
+            BadSignature => write!(f, "Not a PNG file (bad signature)"),
+            ChecksumMismatch { chunk, expected, computed } => write!(f,
+                "PNG {} chunk CRC-32 mismatch: file says {:08x}, chunk is {:08x}",
+                String::from_utf8_lossy(chunk), expected, computed),
+            MissingHeader => write!(f, "PNG file doesn't start with an IHDR chunk"),
+            InvalidHeader => write!(f, "Invalid PNG IHDR chunk"),
+            UnknownCriticalChunk(chunk) => write!(f, "Unknown critical PNG chunk {}",
+                String::from_utf8_lossy(chunk)),
+            InvalidChunk(chunk) => write!(f, "Invalid PNG {} chunk", String::from_utf8_lossy(chunk)),
+            MissingPalette => write!(f, "PNG image has no palette"),
+            PaletteIndexOutOfRange(index) => write!(f, "PNG palette index {} out of range", index),
+            MissingImageData => write!(f, "PNG image data is missing or too short"),
+            InvalidFilter(filter) => write!(f, "Invalid PNG filter type {}", filter),
+            LimitExceeded => write!(f, "PNG image is bigger than the limits allow"),
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
+impl From<PngError> for std::io::Error {
+    fn from(e: PngError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
@@ -0,0 +1,717 @@
+use std::io::{self, Read};
+use crate::algorithms::Crc32;
+use crate::io::{read_u32_be, InflateOptions, ZlibDecoder};
+use super::{paeth, ColorType, Image, Pixels, PngError, PngHeader, PngOptions};
+use super::{SIGNATURE, IHDR, PLTE, TRNS, IDAT, IEND};
+
+/// Reads the chunks of a PNG file one at a time, checking the CRC-32 of
+/// each once it has all been read.
+struct ChunkReader<R: Read> {
+    reader: R,
+    kind: [u8; 4],
+    remaining: usize,
+    crc: Crc32,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads the length and type of the next chunk, returning the type.
+    fn next_chunk(&mut self) -> io::Result<[u8; 4]> {
+        self.remaining = read_u32_be(&mut self.reader)? as usize;
+        self.reader.read_exact(&mut self.kind)?;
+        self.crc = Crc32::new();
+        self.crc.update(&self.kind);
+        Ok(self.kind)
+    }
+
+    fn read_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), self.remaining);
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 && len != 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.crc.update(&buf[..n]);
+        self.remaining -= n;
+        Ok(n)
+    }
+
+    /// Skips whatever is left of the chunk's data, and checks its CRC-32.
+    fn finish_chunk(&mut self) -> io::Result<()> {
+        self.read_rest(|_| ())?;
+        let expected = read_u32_be(&mut self.reader)?;
+        let computed = self.crc.value();
+        if expected != computed {
+            return Err(PngError::ChecksumMismatch { chunk: self.kind, expected, computed }.into());
+        }
+        Ok(())
+    }
+
+    /// Reads the whole of the chunk's data, and checks its CRC-32.
+    fn read_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_rest(|piece| data.extend_from_slice(piece))?;
+        self.finish_chunk()?;
+        Ok(data)
+    }
+
+    fn read_rest<F: FnMut(&[u8])>(&mut self, mut f: F) -> io::Result<()> {
+        let mut buf = [0; 4096];
+        while self.remaining > 0 {
+            let n = self.read_data(&mut buf)?;
+            f(&buf[..n]);
+        }
+        Ok(())
+    }
+}
+
+/// The image data of a PNG file: the data of every IDAT chunk in a row,
+/// ending with the header of the chunk after them read.
+struct IdatReader<'a, R: Read>(&'a mut ChunkReader<R>);
+
+impl<'a, R: Read> Read for IdatReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.0.kind != IDAT {
+                return Ok(0);
+            }
+            if self.0.remaining > 0 || buf.is_empty() {
+                return self.0.read_data(buf);
+            }
+            self.0.finish_chunk()?;
+            self.0.next_chunk()?;
+        }
+    }
+}
+
+fn is_critical(kind: [u8; 4]) -> bool {
+    kind[0] & 0x20 == 0
+}
+
+fn read_header(data: &[u8]) -> Result<PngHeader, PngError> {
+    if data.len() != 13 {
+        return Err(PngError::InvalidChunk(IHDR));
+    }
+
+    let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let bit_depth = data[8];
+    let color_type = ColorType::from_u8(data[9]).ok_or(PngError::InvalidHeader)?;
+    let (compression, filter, interlace) = (data[10], data[11], data[12]);
+
+    let header = PngHeader { width, height, bit_depth, color_type, interlaced: interlace == 1 };
+    if !header.is_valid() || compression != 0 || filter != 0 || interlace > 1 {
+        return Err(PngError::InvalidHeader);
+    }
+    Ok(header)
+}
+
+/// Reverses the filter of a row, given the row above it, already
+/// unfiltered, and how many bytes back the pixel before is.
+fn unfilter(filter: u8, row: &mut [u8], above: &[u8], distance: usize) -> Result<(), PngError> {
+    match filter {
+        0 => {},
+        1 => {
+            for i in distance..row.len() {
+                row[i] = row[i].wrapping_add(row[i - distance]);
+            }
+        },
+        2 => {
+            for (byte, &above) in row.iter_mut().zip(above.iter()) {
+                *byte = byte.wrapping_add(above);
+            }
+        },
+        3 => {
+            for i in 0..row.len() {
+                let left = if i >= distance { row[i - distance] } else { 0 };
+                let average = (left as u16 + above[i] as u16) / 2;
+                row[i] = row[i].wrapping_add(average as u8);
+            }
+        },
+        4 => {
+            for i in 0..row.len() {
+                let (left, above_left) = match i >= distance {
+                    true => (row[i - distance], above[i - distance]),
+                    false => (0, 0),
+                };
+                row[i] = row[i].wrapping_add(paeth(left, above[i], above_left));
+            }
+        },
+        _ => return Err(PngError::InvalidFilter(filter)),
+    }
+    Ok(())
+}
+
+/// Sample `index` of a row of samples `bit_depth` bits each.
+fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let per_byte = 8 / bit_depth as usize;
+            let shift = 8 - bit_depth as usize * (index % per_byte + 1);
+            (row[index / per_byte] >> shift) as u16 & ((1 << bit_depth) - 1)
+        },
+    }
+}
+
+/// Decodes a PNG image (ISO 15948) into RGBA pixels.
+///
+/// Every bit depth and color type is supported, with or without Adam7
+/// interlacing. Palettes are looked up, and the tRNS chunk turns into
+/// alpha. Images with a bit depth of 16 decode to `Pixels::Rgba16`, and all
+/// others to `Pixels::Rgba8`, with samples of fewer bits scaled up to 8.
+///
+/// The CRC-32 of every chunk is checked, as is the Adler-32 checksum of the
+/// image data. Ancillary chunks other than tRNS are skipped.
+///
+/// Memory for the pixels is only taken as the image data decodes, and the
+/// image data can't decompress past what the header says it should be. Use
+/// `with_options` to limit the size of image accepted at all.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{ColorType, Pixels, PngDecoder};
+/// // a 2x1 image: a black pixel and a white one, one bit each
+/// let png = [
+///     0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d,
+///     0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+///     0x01, 0x00, 0x00, 0x00, 0x00, 0xdc, 0x59, 0x42, 0x27, 0x00, 0x00, 0x00,
+///     0x0a, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x70, 0x00, 0x00, 0x00,
+///     0x42, 0x00, 0x41, 0x84, 0xbf, 0x8e, 0x62, 0x00, 0x00, 0x00, 0x00, 0x49,
+///     0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+/// ];
+/// let decoder = PngDecoder::new(&png[..]).unwrap();
+/// assert_eq!(decoder.header().color_type, ColorType::Grayscale);
+/// assert_eq!(decoder.header().bit_depth, 1);
+///
+/// let image = decoder.decode().unwrap();
+/// assert_eq!((image.width, image.height), (2, 1));
+/// assert_eq!(image.pixels, Pixels::Rgba8(vec![0, 0, 0, 255, 255, 255, 255, 255]));
+/// ```
+pub struct PngDecoder<R: Read> {
+    chunks: ChunkReader<R>,
+    format: Format,
+}
+
+/// What's needed to turn samples into RGBA pixels.
+struct Format {
+    header: PngHeader,
+    // RGB triples
+    palette: Vec<u8>,
+    transparency: Vec<u8>,
+}
+
+impl<R: Read> PngDecoder<R> {
+    /// Reads the signature and the chunks before the image data.
+    pub fn new(reader: R) -> io::Result<PngDecoder<R>> {
+        PngDecoder::with_options(reader, PngOptions::default())
+    }
+
+    /// Like `new`, but fails with `PngError::LimitExceeded` if the image is
+    /// bigger than `options` allow.
+    pub fn with_options(mut reader: R, options: PngOptions) -> io::Result<PngDecoder<R>> {
+        let mut signature = [0; 8];
+        reader.read_exact(&mut signature)?;
+        if signature != SIGNATURE {
+            return Err(PngError::BadSignature.into());
+        }
+
+        let mut chunks = ChunkReader { reader, kind: [0; 4], remaining: 0, crc: Crc32::new() };
+        if chunks.next_chunk()? != IHDR {
+            return Err(PngError::MissingHeader.into());
+        }
+        let header = read_header(&chunks.read_chunk()?)?;
+        if !options.allows(&header) {
+            return Err(PngError::LimitExceeded.into());
+        }
+
+        let mut palette = Vec::new();
+        let mut transparency = Vec::new();
+        loop {
+            match chunks.next_chunk()? {
+                IDAT => break,
+                PLTE => {
+                    palette = chunks.read_chunk()?;
+                    if palette.is_empty() || palette.len() > 3 * 256 || palette.len() % 3 != 0 {
+                        return Err(PngError::InvalidChunk(PLTE).into());
+                    }
+                },
+                TRNS => {
+                    transparency = chunks.read_chunk()?;
+                    let valid = match header.color_type {
+                        ColorType::Grayscale => transparency.len() == 2,
+                        ColorType::Rgb => transparency.len() == 6,
+                        ColorType::Indexed => transparency.len() <= 256,
+                        _ => false,
+                    };
+                    if !valid {
+                        return Err(PngError::InvalidChunk(TRNS).into());
+                    }
+                },
+                IEND => return Err(PngError::MissingImageData.into()),
+                kind if is_critical(kind) => {
+                    return Err(PngError::UnknownCriticalChunk(kind).into());
+                },
+                _ => chunks.finish_chunk()?,
+            }
+        }
+
+        if header.color_type == ColorType::Indexed && palette.is_empty() {
+            return Err(PngError::MissingPalette.into());
+        }
+
+        Ok(PngDecoder { chunks, format: Format { header, palette, transparency } })
+    }
+
+    pub fn header(&self) -> &PngHeader {
+        &self.format.header
+    }
+
+    /// Decodes the image, and reads the rest of the file up to the end of
+    /// its IEND chunk.
+    pub fn decode(mut self) -> io::Result<Image> {
+        let header = self.format.header;
+        let mut pixels = match header.bit_depth {
+            16 => Pixels::Rgba16(Vec::new()),
+            _ => Pixels::Rgba8(Vec::new()),
+        };
+        // Adam7 passes each cover the whole image, so they're kept until
+        // they've all been read, rather than placing pixels before the data
+        // for them has turned up
+        let mut passes = Vec::new();
+
+        {
+            let options = InflateOptions {
+                max_output: Some(header.image_data_len()),
+                ..InflateOptions::default()
+            };
+            let mut zlib = ZlibDecoder::with_options(IdatReader(&mut self.chunks), options)?;
+            for (_, _, _, _, pass_width, pass_height) in header.passes() {
+                let row_len = header.row_bytes(pass_width);
+                let mut above = vec![0; row_len];
+                let mut row = vec![0; row_len + 1];
+                for _ in 0..pass_height {
+                    zlib.read_exact(&mut row).map_err(|e| match e.kind() {
+                        io::ErrorKind::UnexpectedEof => PngError::MissingImageData.into(),
+                        _ => e,
+                    })?;
+                    unfilter(row[0], &mut row[1..], &above, header.filter_distance())?;
+                    if header.interlaced {
+                        passes.extend_from_slice(&row[1..]);
+                    } else {
+                        for x in 0..pass_width {
+                            pixels.push(self.format.pixel(&row[1..], x)?);
+                        }
+                    }
+                    above.copy_from_slice(&row[1..]);
+                }
+            }
+
+            // check the Adler-32 checksum
+            io::copy(&mut zlib, &mut io::sink())?;
+        }
+
+        if header.interlaced {
+            self.deinterlace(&passes, &mut pixels)?;
+        }
+
+        io::copy(&mut IdatReader(&mut self.chunks), &mut io::sink())?;
+        while self.chunks.kind != IEND {
+            self.chunks.finish_chunk()?;
+            self.chunks.next_chunk()?;
+        }
+        self.chunks.finish_chunk()?;
+
+        Ok(Image { width: header.width, height: header.height, pixels })
+    }
+
+    /// Places the pixels of the unfiltered Adam7 `passes` in the image.
+    fn deinterlace(&self, passes: &[u8], pixels: &mut Pixels) -> Result<(), PngError> {
+        let header = self.format.header;
+        let width = header.width as usize;
+        pixels.resize(width * header.height as usize);
+
+        let mut rows = passes;
+        for (x0, y0, dx, dy, pass_width, pass_height) in header.passes() {
+            let row_len = header.row_bytes(pass_width);
+            for y in 0..pass_height {
+                let (row, rest) = rows.split_at(row_len);
+                rows = rest;
+                for x in 0..pass_width {
+                    pixels.set((y0 + y * dy) * width + x0 + x * dx, self.format.pixel(row, x)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Pixels {
+    fn push(&mut self, rgba: [u16; 4]) {
+        match self {
+            Pixels::Rgba8(pixels) => pixels.extend(rgba.iter().map(|&value| value as u8)),
+            Pixels::Rgba16(pixels) => pixels.extend_from_slice(&rgba),
+        }
+    }
+
+    fn resize(&mut self, count: usize) {
+        match self {
+            Pixels::Rgba8(pixels) => pixels.resize(count * 4, 0),
+            Pixels::Rgba16(pixels) => pixels.resize(count * 4, 0),
+        }
+    }
+
+    fn set(&mut self, index: usize, rgba: [u16; 4]) {
+        match self {
+            Pixels::Rgba8(pixels) => {
+                for (sample, &value) in pixels[4 * index..4 * index + 4].iter_mut().zip(rgba.iter()) {
+                    *sample = value as u8;
+                }
+            },
+            Pixels::Rgba16(pixels) => pixels[4 * index..4 * index + 4].copy_from_slice(&rgba),
+        }
+    }
+}
+
+impl Format {
+    /// The RGBA samples of pixel `x` in an unfiltered row, 16 bits each if
+    /// the image's are and 8 otherwise.
+    fn pixel(&self, row: &[u8], x: usize) -> Result<[u16; 4], PngError> {
+        let bit_depth = self.header.bit_depth;
+        let channels = self.header.color_type.channels();
+        let max = if bit_depth == 16 { 0xffff } else { 0xff };
+        let scale = |value: u16| match bit_depth {
+            1 | 2 | 4 => value * 0xff / ((1 << bit_depth) - 1),
+            _ => value,
+        };
+        let at = |channel| sample(row, x * channels + channel, bit_depth);
+        let key = |channel: usize| {
+            u16::from_be_bytes([self.transparency[2 * channel], self.transparency[2 * channel + 1]])
+        };
+
+        Ok(match self.header.color_type {
+            ColorType::Grayscale => {
+                let gray = at(0);
+                let opaque = self.transparency.is_empty() || gray != key(0);
+                let gray = scale(gray);
+                [gray, gray, gray, if opaque { max } else { 0 }]
+            },
+            ColorType::Rgb => {
+                let rgb = [at(0), at(1), at(2)];
+                let opaque = self.transparency.is_empty() || rgb != [key(0), key(1), key(2)];
+                [rgb[0], rgb[1], rgb[2], if opaque { max } else { 0 }]
+            },
+            ColorType::Indexed => {
+                let index = at(0) as usize;
+                let color = self.palette.get(3 * index..3 * index + 3)
+                    .ok_or(PngError::PaletteIndexOutOfRange(index as u8))?;
+                let alpha = self.transparency.get(index).map_or(0xff, |&alpha| alpha as u16);
+                [color[0] as u16, color[1] as u16, color[2] as u16, alpha]
+            },
+            ColorType::GrayscaleAlpha => {
+                let gray = at(0);
+                [gray, gray, gray, at(1)]
+            },
+            ColorType::Rgba => [at(0), at(1), at(2), at(3)],
+        })
+    }
+}
+
+mod tests {
+    #[cfg(test)]
+    fn png_error(e: std::io::Error) -> super::PngError {
+        *e.get_ref().unwrap().downcast_ref::<super::PngError>().unwrap()
+    }
+
+    #[cfg(test)]
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        use crate::algorithms::Crc32;
+
+        let mut crc = Crc32::new();
+        crc.update(kind);
+        crc.update(data);
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&crc.value().to_be_bytes());
+        chunk
+    }
+
+    #[cfg(test)]
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Vec<u8> {
+        let mut data = width.to_be_bytes().to_vec();
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        chunk(b"IHDR", &data)
+    }
+
+    /// A PNG file made of `chunks` with the image data, `rows` with their
+    /// filter types, compressed and split into IDAT chunks before IEND.
+    #[cfg(test)]
+    fn png(chunks: &[Vec<u8>], rows: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        use crate::io::ZlibEncoder;
+
+        let mut encoder = ZlibEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(rows).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        for chunk in chunks {
+            png.extend_from_slice(chunk);
+        }
+        for piece in compressed.chunks(50) {
+            png.extend(chunk(b"IDAT", piece));
+        }
+        png.extend(chunk(b"IEND", &[]));
+        png
+    }
+
+    /// Filters each of `rows` with filter type `y % 5` for row `y`.
+    #[cfg(test)]
+    fn filter_rows(rows: &[Vec<u8>], distance: usize) -> Vec<u8> {
+        use crate::io::png::paeth;
+
+        let mut filtered = Vec::new();
+        let mut above = vec![0; rows.first().map_or(0, |row| row.len())];
+        for (y, row) in rows.iter().enumerate() {
+            let filter = (y % 5) as u8;
+            filtered.push(filter);
+            for i in 0..row.len() {
+                let left = if i >= distance { row[i - distance] } else { 0 };
+                let above_left = if i >= distance { above[i - distance] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => above[i],
+                    3 => ((left as u16 + above[i] as u16) / 2) as u8,
+                    _ => paeth(left, above[i], above_left),
+                };
+                filtered.push(row[i].wrapping_sub(predicted));
+            }
+            above = row.clone();
+        }
+        filtered
+    }
+
+    #[test]
+    fn test_grayscale_bit_depths() {
+        use crate::io::{Pixels, PngDecoder};
+        use crate::random::MT19937_32;
+
+        let mut rng = MT19937_32::from_seed(21);
+        let (width, height) = (11, 3);
+        for &bit_depth in &[1u8, 2, 4, 8, 16] {
+            let max = (1u32 << bit_depth) - 1;
+            let samples: Vec<u32> = (0..width * height).map(|_| rng.generate() & max).collect();
+
+            let mut rows = Vec::new();
+            for row in samples.chunks(width) {
+                let mut bits = 0u64;
+                let mut count = 0;
+                rows.push(0);
+                for &sample in row {
+                    bits = bits << bit_depth | sample as u64;
+                    count += bit_depth as usize;
+                    while count >= 8 {
+                        count -= 8;
+                        rows.push((bits >> count) as u8);
+                    }
+                }
+                if count > 0 {
+                    rows.push((bits << (8 - count)) as u8);
+                }
+            }
+
+            let file = png(&[ihdr(width as u32, height as u32, bit_depth, 0, 0)], &rows);
+            let image = PngDecoder::new(&file[..]).unwrap().decode().unwrap();
+            assert_eq!((image.width, image.height), (width as u32, height as u32));
+            let expected: Vec<u32> = samples.iter()
+                .flat_map(|&sample| match bit_depth {
+                    16 => vec![sample, sample, sample, 0xffff],
+                    _ => {
+                        let value = sample * 0xff / max;
+                        vec![value, value, value, 0xff]
+                    },
+                })
+                .collect();
+            match image.pixels {
+                Pixels::Rgba8(pixels) => {
+                    assert!(bit_depth <= 8);
+                    assert_eq!(pixels.iter().map(|&v| v as u32).collect::<Vec<_>>(), expected);
+                },
+                Pixels::Rgba16(pixels) => {
+                    assert_eq!(bit_depth, 16);
+                    assert_eq!(pixels.iter().map(|&v| v as u32).collect::<Vec<_>>(), expected);
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        use crate::io::{Pixels, PngDecoder};
+        use crate::random::MT19937_32;
+
+        let mut rng = MT19937_32::from_seed(22);
+        let (width, height) = (17, 12);
+        // smooth enough for every filter to have something to predict
+        let rows: Vec<Vec<u8>> = (0..height)
+            .map(|y| (0..width * 3).map(|i| (i * 5 + y * 7 + rng.generate() as usize % 9) as u8).collect())
+            .collect();
+        let file = png(&[ihdr(width as u32, height as u32, 8, 2, 0)], &filter_rows(&rows, 3));
+
+        let image = PngDecoder::new(&file[..]).unwrap().decode().unwrap();
+        let expected: Vec<u8> = rows.iter()
+            .flat_map(|row| row.chunks(3).flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 0xff]))
+            .collect();
+        assert_eq!(image.pixels, Pixels::Rgba8(expected));
+
+        // 16-bit RGBA, 8 bytes to a pixel
+        let rows: Vec<Vec<u8>> = (0..height)
+            .map(|_| (0..width * 8).map(|_| rng.generate() as u8).collect())
+            .collect();
+        let file = png(&[ihdr(width as u32, height as u32, 16, 6, 0)], &filter_rows(&rows, 8));
+
+        let image = PngDecoder::new(&file[..]).unwrap().decode().unwrap();
+        let expected: Vec<u16> = rows.iter()
+            .flat_map(|row| row.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])))
+            .collect();
+        assert_eq!(image.pixels, Pixels::Rgba16(expected));
+    }
+
+    #[test]
+    fn test_palette_and_transparency() {
+        use crate::io::{Pixels, PngDecoder, PngError};
+
+        // 2 bits per index; the fourth entry has no alpha in tRNS
+        let palette = chunk(b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255, 9, 9, 9]);
+        let transparency = chunk(b"tRNS", &[0, 128, 255]);
+        let text = chunk(b"tEXt", b"Comment\0skipped");
+        let rows = [0, 0b00_01_10_11, 0, 0b11_10_01_00];
+        let file = png(&[ihdr(4, 2, 2, 3, 0), text.clone(), palette, transparency], &rows);
+
+        let image = PngDecoder::new(&file[..]).unwrap().decode().unwrap();
+        assert_eq!(image.pixels, Pixels::Rgba8(vec![
+            255, 0, 0, 0,  0, 255, 0, 128,  0, 0, 255, 255,  9, 9, 9, 255,
+            9, 9, 9, 255,  0, 0, 255, 255,  0, 255, 0, 128,  255, 0, 0, 0,
+        ]));
+
+        let palette = chunk(b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        let file = png(&[ihdr(4, 2, 2, 3, 0), palette], &rows);
+        let error = PngDecoder::new(&file[..]).unwrap().decode().unwrap_err();
+        assert_eq!(png_error(error), PngError::PaletteIndexOutOfRange(3));
+
+        let file = png(&[ihdr(4, 2, 2, 3, 0), text], &rows);
+        let error = PngDecoder::new(&file[..]).err().unwrap();
+        assert_eq!(png_error(error), PngError::MissingPalette);
+
+        // a gray level and an RGB color which are transparent
+        let file = png(&[ihdr(2, 1, 8, 0, 0), chunk(b"tRNS", &[0, 7])], &[0, 7, 8]);
+        let image = PngDecoder::new(&file[..]).unwrap().decode().unwrap();
+        assert_eq!(image.pixels, Pixels::Rgba8(vec![7, 7, 7, 0, 8, 8, 8, 255]));
+
+        let file = png(&[ihdr(2, 1, 16, 2, 0), chunk(b"tRNS", &[1, 2, 3, 4, 5, 6])],
+            &[0, 1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 7]);
+        let image = PngDecoder::new(&file[..]).unwrap().decode().unwrap();
+        assert_eq!(image.pixels, Pixels::Rgba16(vec![
+            0x0102, 0x0304, 0x0506, 0, 0x0102, 0x0304, 0x0507, 0xffff,
+        ]));
+    }
+
+    #[test]
+    fn test_interlaced() {
+        use crate::io::PngDecoder;
+        use crate::io::png::ADAM7;
+        use crate::random::MT19937_32;
+
+        let mut rng = MT19937_32::from_seed(23);
+        for &(width, height) in &[(1, 1), (3, 2), (13, 11), (40, 33)] {
+            let pixels: Vec<Vec<u8>> = (0..height)
+                .map(|_| (0..width * 2).map(|_| rng.generate() as u8 & 0xf0).collect())
+                .collect();
+            let plain = png(&[ihdr(width as u32, height as u32, 8, 4, 0)], &filter_rows(&pixels, 2));
+
+            let mut rows = Vec::new();
+            for &(x0, y0, dx, dy) in &ADAM7 {
+                let pass: Vec<Vec<u8>> = (y0..height).step_by(dy)
+                    .map(|y| (x0..width).step_by(dx).flat_map(|x| pixels[y][2 * x..2 * x + 2].to_vec()).collect())
+                    .filter(|row: &Vec<u8>| !row.is_empty())
+                    .collect();
+                rows.extend(filter_rows(&pass, 2));
+            }
+            let interlaced = png(&[ihdr(width as u32, height as u32, 8, 4, 1)], &rows);
+
+            let decoder = PngDecoder::new(&interlaced[..]).unwrap();
+            assert!(decoder.header().interlaced);
+            let image = decoder.decode().unwrap();
+            assert_eq!(image, PngDecoder::new(&plain[..]).unwrap().decode().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        use crate::io::{PngDecoder, PngError};
+
+        let rows = [0, 1, 2, 3, 4];
+        let valid = png(&[ihdr(4, 1, 8, 0, 0)], &rows);
+        PngDecoder::new(&valid[..]).unwrap().decode().unwrap();
+
+        let mut corrupt = valid.clone();
+        corrupt[0] = b'p';
+        let error = PngDecoder::new(&corrupt[..]).err().unwrap();
+        assert_eq!(png_error(error), PngError::BadSignature);
+
+        // the last byte of the IDAT chunk's CRC-32, just before IEND
+        let mut corrupt = valid.clone();
+        let at = corrupt.len() - 12 - 1;
+        corrupt[at] ^= 1;
+        let error = PngDecoder::new(&corrupt[..]).unwrap().decode().unwrap_err();
+        match png_error(error) {
+            PngError::ChecksumMismatch { chunk, .. } => assert_eq!(&chunk, b"IDAT"),
+            e => panic!("{:?}", e),
+        }
+
+        let file = png(&[ihdr(4, 1, 3, 0, 0)], &rows);
+        let error = PngDecoder::new(&file[..]).err().unwrap();
+        assert_eq!(png_error(error), PngError::InvalidHeader);
+
+        let file = png(&[ihdr(4, 1, 8, 0, 0), chunk(b"SKIP", &[])], &rows);
+        let error = PngDecoder::new(&file[..]).err().unwrap();
+        assert_eq!(png_error(error), PngError::UnknownCriticalChunk(*b"SKIP"));
+
+        let file = png(&[ihdr(4, 1, 8, 0, 0)], &[5, 1, 2, 3, 4]);
+        let error = PngDecoder::new(&file[..]).unwrap().decode().unwrap_err();
+        assert_eq!(png_error(error), PngError::InvalidFilter(5));
+
+        let file = png(&[ihdr(4, 2, 8, 0, 0)], &rows);
+        let error = PngDecoder::new(&file[..]).unwrap().decode().unwrap_err();
+        assert_eq!(png_error(error), PngError::MissingImageData);
+    }
+
+    #[test]
+    fn test_limits() {
+        use crate::io::{DeflateDecompressorError, InflateErrorKind, Limit, PngDecoder, PngError, PngOptions};
+
+        // nothing is allocated for the pixels until the image data decodes
+        let file = png(&[ihdr(200000, 200000, 16, 6, 0)], &[0; 9]);
+        let error = PngDecoder::new(&file[..]).unwrap().decode().unwrap_err();
+        assert_eq!(png_error(error), PngError::MissingImageData);
+
+        let options = PngOptions { max_pixels: Some(1 << 30), ..PngOptions::default() };
+        let error = PngDecoder::with_options(&file[..], options).err().unwrap();
+        assert_eq!(png_error(error), PngError::LimitExceeded);
+        let options = PngOptions { max_bytes: Some(1 << 30), ..PngOptions::default() };
+        let error = PngDecoder::with_options(&file[..], options).err().unwrap();
+        assert_eq!(png_error(error), PngError::LimitExceeded);
+
+        let file = png(&[ihdr(4, 1, 8, 0, 0)], &[0, 1, 2, 3, 4]);
+        let options = PngOptions { max_pixels: Some(4), max_bytes: Some(16) };
+        PngDecoder::with_options(&file[..], options).unwrap().decode().unwrap();
+
+        // the image data can't expand past the size of the filtered rows
+        let file = png(&[ihdr(4, 1, 8, 0, 0)], &[0, 1, 2, 3, 4, 5]);
+        let error = PngDecoder::new(&file[..]).unwrap().decode().unwrap_err();
+        let error = *error.get_ref().unwrap().downcast_ref::<DeflateDecompressorError>().unwrap();
+        assert_eq!(error.kind, InflateErrorKind::LimitExceeded(Limit::Output));
+    }
+}
@@ -0,0 +1,351 @@
+use std::io::{self, Write};
+use crate::algorithms::Crc32;
+use crate::io::{write_u32_be, DeflateOptions, ZlibEncoder};
+use super::{paeth, ColorType, PngHeader};
+use super::{SIGNATURE, IHDR, PLTE, IDAT, IEND};
+
+/// The most compressed image data put in one IDAT chunk.
+const IDAT_SIZE: usize = 32768;
+
+fn write_chunk<W: Write>(writer: &mut W, kind: [u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = Crc32::new();
+    crc.update(&kind);
+    crc.update(data);
+    write_u32_be(writer, data.len() as u32)?;
+    writer.write_all(&kind)?;
+    writer.write_all(data)?;
+    write_u32_be(writer, crc.value())
+}
+
+/// Writes the compressed image data as IDAT chunks of `IDAT_SIZE` bytes,
+/// and a shorter one at the end.
+struct IdatWriter<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> IdatWriter<W> {
+    fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() {
+            write_chunk(&mut self.writer, IDAT, &self.buffer)?;
+        }
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() >= IDAT_SIZE {
+            write_chunk(&mut self.writer, IDAT, &self.buffer[..IDAT_SIZE])?;
+            self.buffer.drain(..IDAT_SIZE);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Filters `row` with filter type `filter`, given the row above it and how
+/// many bytes back the pixel before is. `filtered` gets the filter type
+/// followed by the filtered bytes.
+fn filter(filter: u8, row: &[u8], above: &[u8], distance: usize, filtered: &mut [u8]) {
+    filtered[0] = filter;
+    for i in 0..row.len() {
+        let left = if i >= distance { row[i - distance] } else { 0 };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => above[i],
+            3 => ((left as u16 + above[i] as u16) / 2) as u8,
+            _ => {
+                let above_left = if i >= distance { above[i - distance] } else { 0 };
+                paeth(left, above[i], above_left)
+            },
+        };
+        filtered[i + 1] = row[i].wrapping_sub(predicted);
+    }
+}
+
+/// The sum of the filtered bytes of a row taken as signed, ignoring sign,
+/// which is smallest for the rows that tend to compress best.
+fn sum_of_differences(filtered: &[u8]) -> usize {
+    filtered[1..].iter().map(|&byte| (byte as i8).unsigned_abs() as usize).sum()
+}
+
+/// Copies pixels `x, x + step, ...` of `row`, `width` of them with
+/// `pixel_bits` bits each, into `pass`.
+fn pass_row(row: &[u8], x: usize, step: usize, width: usize, pixel_bits: usize, pass: &mut [u8]) {
+    if pixel_bits >= 8 {
+        let bytes = pixel_bits / 8;
+        for (i, pixel) in pass.chunks_mut(bytes).take(width).enumerate() {
+            let from = (x + i * step) * bytes;
+            pixel.copy_from_slice(&row[from..from + bytes]);
+        }
+        return;
+    }
+
+    let mask = (1 << pixel_bits) - 1;
+    for byte in pass.iter_mut() {
+        *byte = 0;
+    }
+    for i in 0..width {
+        let from = (x + i * step) * pixel_bits;
+        let value = (row[from / 8] >> (8 - pixel_bits - from % 8)) & mask;
+        let to = i * pixel_bits;
+        pass[to / 8] |= value << (8 - pixel_bits - to % 8);
+    }
+}
+
+/// Encodes an image as a PNG file (ISO 15948).
+///
+/// The image is given in the format its header describes: rows from the
+/// top, with each row's samples packed into whole bytes and 16-bit samples
+/// big-endian. Indexed images need a palette, which RGB and RGBA images may
+/// have too, as a suggestion for displays with few colors.
+///
+/// Each row is filtered with whichever filter type makes the sum of its
+/// bytes, taken as signed, smallest. As the PNG specification recommends,
+/// indexed images and those with fewer than 8 bits to a sample aren't
+/// filtered. The filtered rows are compressed with `ZlibEncoder`.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{ColorType, Pixels, PngDecoder, PngEncoder, PngHeader};
+/// let header = PngHeader {
+///     width: 3,
+///     height: 2,
+///     bit_depth: 8,
+///     color_type: ColorType::Rgb,
+///     interlaced: false,
+/// };
+/// let rgb = [
+///     255, 0, 0,  0, 255, 0,  0, 0, 255,
+///     0, 0, 0,  128, 128, 128,  255, 255, 255,
+/// ];
+/// let png = PngEncoder::new(Vec::new(), header).encode(&rgb).unwrap();
+///
+/// let image = PngDecoder::new(&png[..]).unwrap().decode().unwrap();
+/// assert_eq!(image.pixels, Pixels::Rgba8(vec![
+///     255, 0, 0, 255,  0, 255, 0, 255,  0, 0, 255, 255,
+///     0, 0, 0, 255,  128, 128, 128, 255,  255, 255, 255, 255,
+/// ]));
+/// ```
+pub struct PngEncoder<W: Write> {
+    writer: W,
+    header: PngHeader,
+    options: DeflateOptions,
+    // RGB triples
+    palette: Vec<u8>,
+}
+
+impl<W: Write> PngEncoder<W> {
+    pub fn new(writer: W, header: PngHeader) -> PngEncoder<W> {
+        PngEncoder::with_options(writer, header, DeflateOptions::default())
+    }
+
+    /// Panics if PNG doesn't allow `header`, or the compression level is
+    /// more than 9.
+    pub fn with_options(writer: W, header: PngHeader, options: DeflateOptions) -> PngEncoder<W> {
+        assert!(header.is_valid(), "invalid PNG header {:?}", header);
+        assert!(options.level <= 9, "compression level must be 0-9");
+        PngEncoder { writer, header, options, palette: Vec::new() }
+    }
+
+    /// Sets the palette, as red, green and blue bytes for each of 1 to 256
+    /// entries. Panics if the image is grayscale.
+    pub fn set_palette(&mut self, palette: &[u8]) {
+        assert!(!palette.is_empty() && palette.len() <= 3 * 256 && palette.len().is_multiple_of(3),
+            "a palette must have 1-256 RGB entries");
+        let grayscale = matches!(self.header.color_type,
+            ColorType::Grayscale | ColorType::GrayscaleAlpha);
+        assert!(!grayscale, "grayscale PNG images can't have a palette");
+        self.palette = palette.to_vec();
+    }
+
+    /// Writes the whole file, returning the writer after it.
+    ///
+    /// Panics if `data` isn't the size the header says, or the image is
+    /// indexed and has no palette.
+    pub fn encode(mut self, data: &[u8]) -> io::Result<W> {
+        let header = self.header;
+        let (width, height) = (header.width as usize, header.height as usize);
+        let row_len = header.row_bytes(width);
+        assert_eq!(data.len(), row_len * height, "image data is the wrong size");
+        assert!(header.color_type != ColorType::Indexed || !self.palette.is_empty(),
+            "indexed PNG images need a palette");
+
+        self.writer.write_all(&SIGNATURE)?;
+        let mut ihdr = header.width.to_be_bytes().to_vec();
+        ihdr.extend_from_slice(&header.height.to_be_bytes());
+        ihdr.extend_from_slice(&[header.bit_depth, header.color_type.to_u8(), 0, 0, header.interlaced as u8]);
+        write_chunk(&mut self.writer, IHDR, &ihdr)?;
+        if !self.palette.is_empty() {
+            write_chunk(&mut self.writer, PLTE, &self.palette)?;
+        }
+
+        let idat = IdatWriter { writer: self.writer, buffer: Vec::new() };
+        let mut zlib = ZlibEncoder::with_options(idat, self.options)?;
+        let adaptive = header.bit_depth >= 8 && header.color_type != ColorType::Indexed;
+        let pixel_bits = header.color_type.channels() * header.bit_depth as usize;
+        let distance = header.filter_distance();
+        for (x0, y0, dx, dy, pass_width, _) in header.passes() {
+            let pass_len = header.row_bytes(pass_width);
+            let mut pass = vec![0; pass_len];
+            let mut above = vec![0; pass_len];
+            let mut filtered = vec![0; pass_len + 1];
+            let mut candidate = vec![0; pass_len + 1];
+            for y in (y0..height).step_by(dy) {
+                let mut row = &data[y * row_len..(y + 1) * row_len];
+                if dx != 1 {
+                    pass_row(row, x0, dx, pass_width, pixel_bits, &mut pass);
+                    row = &pass;
+                }
+
+                filter(0, row, &above, distance, &mut filtered);
+                if adaptive {
+                    let mut best = sum_of_differences(&filtered);
+                    for filter_type in 1..5 {
+                        filter(filter_type, row, &above, distance, &mut candidate);
+                        let sum = sum_of_differences(&candidate);
+                        if sum < best {
+                            best = sum;
+                            std::mem::swap(&mut filtered, &mut candidate);
+                        }
+                    }
+                }
+
+                zlib.write_all(&filtered)?;
+                above.copy_from_slice(row);
+            }
+        }
+
+        let mut writer = zlib.finish()?.finish()?;
+        write_chunk(&mut writer, IEND, &[])?;
+        Ok(writer)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_round_trip() {
+        use crate::io::{ColorType, Pixels, PngDecoder, PngEncoder, PngHeader};
+        use crate::random::MT19937_32;
+
+        let mut rng = MT19937_32::from_seed(22);
+        let color_types = [
+            ColorType::Grayscale, ColorType::Rgb, ColorType::Indexed,
+            ColorType::GrayscaleAlpha, ColorType::Rgba,
+        ];
+        for &color_type in &color_types {
+            for &bit_depth in &[1, 2, 4, 8, 16] {
+                if !color_type.allows_bit_depth(bit_depth) {
+                    continue;
+                }
+
+                let (width, height) = (19, 13);
+                let row_len = (width * color_type.channels() * bit_depth as usize).div_ceil(8);
+                // smooth, so the filters have something to predict
+                let data: Vec<u8> = (0..row_len * height)
+                    .map(|i| (i % row_len + i / row_len * 3 + rng.generate() as usize % 4) as u8)
+                    .collect();
+                let palette: Vec<u8> = (0..3 * 256).map(|_| rng.generate() as u8).collect();
+
+                let mut images = Vec::new();
+                for &interlaced in &[false, true] {
+                    let header = PngHeader {
+                        width: width as u32,
+                        height: height as u32,
+                        bit_depth,
+                        color_type,
+                        interlaced,
+                    };
+                    let mut encoder = PngEncoder::new(Vec::new(), header);
+                    if color_type == ColorType::Indexed {
+                        encoder.set_palette(&palette);
+                    }
+                    let png = encoder.encode(&data).unwrap();
+
+                    let decoder = PngDecoder::new(&png[..]).unwrap();
+                    assert_eq!(*decoder.header(), header);
+                    images.push(decoder.decode().unwrap());
+                }
+                assert_eq!(images[0], images[1]);
+
+                match (color_type, &images[0].pixels) {
+                    (ColorType::Rgba, Pixels::Rgba8(pixels)) => assert_eq!(*pixels, data),
+                    (ColorType::Rgba, Pixels::Rgba16(pixels)) => {
+                        let samples: Vec<u16> = data.chunks(2)
+                            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                            .collect();
+                        assert_eq!(*pixels, samples);
+                    },
+                    (ColorType::Indexed, Pixels::Rgba8(pixels)) if bit_depth == 8 => {
+                        for (pixel, &index) in pixels.chunks(4).zip(data.iter()) {
+                            let index = index as usize;
+                            assert_eq!(pixel[..3], palette[3 * index..3 * index + 3]);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunks_and_filters() {
+        use std::io::Read;
+        use crate::algorithms::crc32;
+        use crate::io::{read_u32_be, ColorType, PngEncoder, PngHeader, ZlibDecoder};
+        use crate::random::MT19937_32;
+
+        let mut rng = MT19937_32::from_seed(122);
+        let (width, height) = (200, 150);
+        let header = PngHeader {
+            width: width as u32,
+            height: height as u32,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        // noise on the left, to need more than one IDAT chunk, and a
+        // gradient on the right
+        let data: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width * 3).map(move |x| (x, y)))
+            .map(|(x, y)| if x < 2 * width { rng.generate() as u8 } else { (x + 2 * y) as u8 })
+            .collect();
+        let png = PngEncoder::new(Vec::new(), header).encode(&data).unwrap();
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']);
+
+        let mut reader = &png[8..];
+        let mut kinds = Vec::new();
+        let mut compressed = Vec::new();
+        while !reader.is_empty() {
+            let length = read_u32_be(&mut reader).unwrap() as usize;
+            let (chunk, rest) = reader.split_at(4 + length);
+            reader = rest;
+            assert_eq!(read_u32_be(&mut reader).unwrap(), crc32(chunk));
+
+            kinds.push(chunk[..4].to_vec());
+            if &chunk[..4] == b"IDAT" {
+                compressed.extend_from_slice(&chunk[4..]);
+            }
+        }
+        let idat_count = kinds.len() - 2;
+        assert!(idat_count > 1);
+        assert_eq!(kinds[0], b"IHDR");
+        assert!(kinds[1..=idat_count].iter().all(|kind| kind == b"IDAT"));
+        assert_eq!(kinds[idat_count + 1], b"IEND");
+
+        let mut filtered = Vec::new();
+        ZlibDecoder::new(&compressed[..]).unwrap().read_to_end(&mut filtered).unwrap();
+        assert_eq!(filtered.len(), height * (width * 3 + 1));
+        let filters: Vec<u8> = filtered.chunks(width * 3 + 1).map(|row| row[0]).collect();
+        // the gradient always predicts better than nothing, and which filter
+        // predicts best varies with the noise
+        assert!(filters.iter().all(|&filter| filter != 0 && filter < 5));
+        assert!((1..5).all(|filter| filters.contains(&filter)));
+    }
+}
@@ -0,0 +1,134 @@
+mod reader;
+pub use self::reader::{ZipArchive, ZipEntry, ZipEntryReader};
+mod writer;
+pub use self::writer::{ZipWriter, FileOptions};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const FLAG_ENCRYPTED: u16 = 0x0001;
+
+/// How the data of a zip entry is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionMethod {
+    Stored,
+    Deflated,
+    /// Deflate64, which Windows uses for large files. It can only be read.
+    Deflate64,
+    Other(u16),
+}
+
+impl CompressionMethod {
+    fn from_u16(method: u16) -> CompressionMethod {
+        match method {
+            0 => CompressionMethod::Stored,
+            8 => CompressionMethod::Deflated,
+            9 => CompressionMethod::Deflate64,
+            method => CompressionMethod::Other(method),
+        }
+    }
+
+    fn to_u16(self) -> u16 {
+        match self {
+            CompressionMethod::Stored => 0,
+            CompressionMethod::Deflated => 8,
+            CompressionMethod::Deflate64 => 9,
+            CompressionMethod::Other(method) => method,
+        }
+    }
+}
+
+/// A timestamp in the MS-DOS format used by zip files, which has a
+/// resolution of two seconds and covers the years 1980 to 2107.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DosDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DosDateTime {
+    pub fn from_dos(date: u16, time: u16) -> DosDateTime {
+        DosDateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0xf) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8,
+        }
+    }
+
+    /// Returns the `(date, time)` pair as stored in a zip file.
+    pub fn to_dos(&self) -> (u16, u16) {
+        let date = (self.year.saturating_sub(1980) << 9)
+            | ((self.month as u16) << 5)
+            | self.day as u16;
+        let time = ((self.hour as u16) << 11)
+            | ((self.minute as u16) << 5)
+            | ((self.second as u16) / 2);
+        (date, time)
+    }
+}
+
+impl Default for DosDateTime {
+    /// The earliest representable time, midnight on 1980-01-01.
+    fn default() -> DosDateTime {
+        DosDateTime { year: 1980, month: 1, day: 1, hour: 0, minute: 0, second: 0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZipError {
+    EndOfCentralDirectoryNotFound,
+    BadSignature(u32),
+    MultipleDisks,
+    Encrypted,
+    UnsupportedMethod(u16),
+    ChecksumMismatch { expected: u32, computed: u32 },
+    SizeMismatch { expected: u64, computed: u64 },
+}
+
+impl std::fmt::Display for ZipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::ZipError::*;
+        match self {
+            EndOfCentralDirectoryNotFound => write!(f, "Not a zip file (no end of central directory record)"),
+            BadSignature(signature) => write!(f, "Bad zip record signature {:08x}", signature),
+            MultipleDisks => write!(f, "Zip files spanning multiple disks are not supported"),
+            Encrypted => write!(f, "Encrypted zip entries are not supported"),
+            UnsupportedMethod(method) => write!(f, "Unsupported zip compression method {}", method),
+            ChecksumMismatch { expected, computed } => write!(f,
+                "zip entry CRC-32 mismatch: archive says {:08x}, data is {:08x}", expected, computed),
+            SizeMismatch { expected, computed } => write!(f,
+                "zip entry size mismatch: archive says {}, data is {}", expected, computed),
+        }
+    }
+}
+
+impl std::error::Error for ZipError {}
+
+impl From<ZipError> for std::io::Error {
+    fn from(e: ZipError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_dos_date_time() {
+        use super::DosDateTime;
+        let time = DosDateTime { year: 2019, month: 3, day: 14, hour: 15, minute: 9, second: 26 };
+        let (date, dos_time) = time.to_dos();
+        assert_eq!((date, dos_time), (0x4e6e, 0x792d));
+        assert_eq!(DosDateTime::from_dos(date, dos_time), time);
+    }
+}
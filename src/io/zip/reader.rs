@@ -0,0 +1,596 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+use crate::algorithms::Crc32;
+use crate::io::DeflateDecompressor;
+use crate::io::{read_u16_le, read_u32_le, read_u64_le};
+use super::*;
+
+const END_OF_CENTRAL_DIRECTORY_SIZE: u64 = 22;
+const ZIP64_LOCATOR_SIZE: u64 = 20;
+const MAX_COMMENT_LENGTH: u64 = 0xffff;
+
+/// An entry from the central directory of a zip archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// The entry's path within the archive. Names which aren't valid UTF-8
+    /// are converted lossily.
+    pub name: String,
+    pub comment: Vec<u8>,
+    pub method: CompressionMethod,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub modified: DosDateTime,
+    flags: u16,
+    header_offset: u64,
+}
+
+impl ZipEntry {
+    /// Directories are stored as empty entries whose names end in '/'.
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+}
+
+/// Reads a zip archive, including ZIP64 archives.
+///
+/// The central directory is read when the archive is opened, so listing
+/// entries doesn't touch the underlying reader. Entries are opened one at a
+/// time with `open`.
+///
+/// The reader is buffered internally, as deflated entries are decoded a
+/// byte at a time, so a `File` can be passed as it is.
+///
+/// # Example
+/// ```no_run
+/// # use stdex::io::ZipArchive;
+/// # use std::io::Read;
+/// let file = std::fs::File::open("archive.zip").unwrap();
+/// let mut archive = ZipArchive::new(file).unwrap();
+/// for entry in archive.entries() {
+///     println!("{} ({} bytes)", entry.name, entry.uncompressed_size);
+/// }
+///
+/// let index = archive.index_of("README.md").unwrap();
+/// let mut readme = String::new();
+/// archive.open(index).unwrap().read_to_string(&mut readme).unwrap();
+/// ```
+pub struct ZipArchive<R: Read + Seek> {
+    reader: BufReader<R>,
+    entries: Vec<ZipEntry>,
+    comment: Vec<u8>,
+}
+
+struct CentralDirectory {
+    entries: u64,
+    size: u64,
+    offset: u64,
+    comment: Vec<u8>,
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    pub fn new(reader: R) -> io::Result<ZipArchive<R>> {
+        let mut reader = BufReader::new(reader);
+        let directory = read_central_directory_location(&mut reader)?;
+
+        reader.seek(SeekFrom::Start(directory.offset))?;
+        let mut buffer = Vec::new();
+        (&mut reader).take(directory.size).read_to_end(&mut buffer)?;
+        if (buffer.len() as u64) < directory.size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let mut remaining = &buffer[..];
+        let mut entries = Vec::new();
+        for _ in 0..directory.entries {
+            entries.push(read_central_header(&mut remaining)?);
+        }
+
+        Ok(ZipArchive {
+            reader,
+            entries,
+            comment: directory.comment,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries in central directory order.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Returns the index of the first entry named `name`.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    /// The archive comment.
+    pub fn comment(&self) -> &[u8] {
+        &self.comment
+    }
+
+    /// Opens the entry at `index` for reading.
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn open(&mut self, index: usize) -> io::Result<ZipEntryReader<'_, R>> {
+        let entry = &self.entries[index];
+        if entry.is_encrypted() {
+            return Err(ZipError::Encrypted.into());
+        }
+
+        self.reader.seek(SeekFrom::Start(entry.header_offset))?;
+        let signature = read_u32_le(&mut self.reader)?;
+        if signature != LOCAL_HEADER_SIGNATURE {
+            return Err(ZipError::BadSignature(signature).into());
+        }
+
+        // the sizes in the local header may be zero when a data descriptor
+        // follows the data, so only the name and extra lengths are used
+        self.reader.seek_relative(22)?;
+        let name_length = read_u16_le(&mut self.reader)?;
+        let extra_length = read_u16_le(&mut self.reader)?;
+        self.reader.seek_relative(name_length as i64 + extra_length as i64)?;
+
+        let data = (&mut self.reader).take(entry.compressed_size);
+        let source = match entry.method {
+            CompressionMethod::Stored => EntrySource::Stored(data),
+            CompressionMethod::Deflated => {
+                EntrySource::Deflated(Box::new(DeflateDecompressor::new(data)?))
+            },
+            CompressionMethod::Deflate64 => {
+                EntrySource::Deflated(Box::new(DeflateDecompressor::new_deflate64(data)?))
+            },
+            CompressionMethod::Other(method) => {
+                return Err(ZipError::UnsupportedMethod(method).into());
+            },
+        };
+
+        Ok(ZipEntryReader {
+            source,
+            crc: Crc32::new(),
+            size: 0,
+            expected_crc: entry.crc32,
+            expected_size: entry.uncompressed_size,
+            finished: false,
+        })
+    }
+
+    /// Returns the underlying `Read` object, wherever the internal buffer
+    /// left it.
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+/// Finds the end of central directory record, following it to the ZIP64
+/// record if there is one.
+fn read_central_directory_location<R: Read + Seek>(reader: &mut R)
+-> io::Result<CentralDirectory> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    if file_length < END_OF_CENTRAL_DIRECTORY_SIZE {
+        return Err(ZipError::EndOfCentralDirectoryNotFound.into());
+    }
+
+    // the record is at the very end, unless the archive has a comment
+    let search_length = std::cmp::min(file_length, END_OF_CENTRAL_DIRECTORY_SIZE + MAX_COMMENT_LENGTH);
+    let search_start = file_length - search_length;
+    reader.seek(SeekFrom::Start(search_start))?;
+    let mut tail = vec![0; search_length as usize];
+    reader.read_exact(&mut tail)?;
+
+    let record_start = (0..=tail.len() - END_OF_CENTRAL_DIRECTORY_SIZE as usize).rev()
+        .find(|&i| {
+            let mut record = &tail[i..];
+            let signature = read_u32_le(&mut record).unwrap();
+            let comment_length = tail[i + 20] as usize | (tail[i + 21] as usize) << 8;
+            signature == END_OF_CENTRAL_DIRECTORY_SIGNATURE
+                && i + END_OF_CENTRAL_DIRECTORY_SIZE as usize + comment_length <= tail.len()
+        })
+        .ok_or(ZipError::EndOfCentralDirectoryNotFound)?;
+
+    let mut record = &tail[record_start + 4..];
+    let disk = read_u16_le(&mut record)?;
+    let directory_disk = read_u16_le(&mut record)?;
+    let _disk_entries = read_u16_le(&mut record)?;
+    let entries = read_u16_le(&mut record)?;
+    let size = read_u32_le(&mut record)?;
+    let offset = read_u32_le(&mut record)?;
+    let comment_length = read_u16_le(&mut record)? as usize;
+    let comment = record[..comment_length].to_vec();
+
+    if disk != 0 || directory_disk != 0 {
+        return Err(ZipError::MultipleDisks.into());
+    }
+
+    let mut directory = CentralDirectory {
+        entries: entries as u64,
+        size: size as u64,
+        offset: offset as u64,
+        comment,
+    };
+
+    let record_position = search_start + record_start as u64;
+    if record_position >= ZIP64_LOCATOR_SIZE {
+        reader.seek(SeekFrom::Start(record_position - ZIP64_LOCATOR_SIZE))?;
+        if read_u32_le(reader)? == ZIP64_LOCATOR_SIGNATURE {
+            read_zip64_record(reader, &mut directory)?;
+        }
+    }
+
+    Ok(directory)
+}
+
+/// Reads the rest of a ZIP64 end of central directory locator and the record
+/// it points to.
+fn read_zip64_record<R: Read + Seek>(reader: &mut R, directory: &mut CentralDirectory)
+-> io::Result<()> {
+    let directory_disk = read_u32_le(reader)?;
+    let record_offset = read_u64_le(reader)?;
+    let disks = read_u32_le(reader)?;
+    if directory_disk != 0 || disks > 1 {
+        return Err(ZipError::MultipleDisks.into());
+    }
+
+    reader.seek(SeekFrom::Start(record_offset))?;
+    let signature = read_u32_le(reader)?;
+    if signature != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+        return Err(ZipError::BadSignature(signature).into());
+    }
+
+    let _record_size = read_u64_le(reader)?;
+    let _version_made_by = read_u16_le(reader)?;
+    let _version_needed = read_u16_le(reader)?;
+    let disk = read_u32_le(reader)?;
+    let directory_disk = read_u32_le(reader)?;
+    if disk != 0 || directory_disk != 0 {
+        return Err(ZipError::MultipleDisks.into());
+    }
+
+    let _disk_entries = read_u64_le(reader)?;
+    directory.entries = read_u64_le(reader)?;
+    directory.size = read_u64_le(reader)?;
+    directory.offset = read_u64_le(reader)?;
+    Ok(())
+}
+
+fn read_central_header(reader: &mut &[u8]) -> io::Result<ZipEntry> {
+    let signature = read_u32_le(reader)?;
+    if signature != CENTRAL_HEADER_SIGNATURE {
+        return Err(ZipError::BadSignature(signature).into());
+    }
+
+    let _version_made_by = read_u16_le(reader)?;
+    let _version_needed = read_u16_le(reader)?;
+    let flags = read_u16_le(reader)?;
+    let method = CompressionMethod::from_u16(read_u16_le(reader)?);
+    let time = read_u16_le(reader)?;
+    let date = read_u16_le(reader)?;
+    let crc32 = read_u32_le(reader)?;
+    let mut compressed_size = read_u32_le(reader)? as u64;
+    let mut uncompressed_size = read_u32_le(reader)? as u64;
+    let name_length = read_u16_le(reader)? as usize;
+    let extra_length = read_u16_le(reader)? as usize;
+    let comment_length = read_u16_le(reader)? as usize;
+    let _disk = read_u16_le(reader)?;
+    let _internal_attributes = read_u16_le(reader)?;
+    let _external_attributes = read_u32_le(reader)?;
+    let mut header_offset = read_u32_le(reader)? as u64;
+
+    let name = take_bytes(reader, name_length)?;
+    let mut extra = take_bytes(reader, extra_length)?;
+    let comment = take_bytes(reader, comment_length)?;
+
+    // the ZIP64 extra field holds only those values which didn't fit, in
+    // this order
+    while extra.len() >= 4 {
+        let id = read_u16_le(&mut extra)?;
+        let length = read_u16_le(&mut extra)? as usize;
+        let mut field = take_bytes(&mut extra, length)?;
+        if id != ZIP64_EXTRA_ID {
+            continue;
+        }
+
+        for value in [&mut uncompressed_size, &mut compressed_size, &mut header_offset] {
+            if *value == 0xffffffff {
+                *value = read_u64_le(&mut field)?;
+            }
+        }
+    }
+
+    Ok(ZipEntry {
+        name: String::from_utf8_lossy(name).into_owned(),
+        comment: comment.to_vec(),
+        method,
+        crc32,
+        compressed_size,
+        uncompressed_size,
+        modified: DosDateTime::from_dos(date, time),
+        flags,
+        header_offset,
+    })
+}
+
+fn take_bytes<'a>(reader: &mut &'a [u8], length: usize) -> io::Result<&'a [u8]> {
+    if reader.len() < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (bytes, rest) = reader.split_at(length);
+    *reader = rest;
+    Ok(bytes)
+}
+
+enum EntrySource<'a, R: Read> {
+    Stored(Take<&'a mut BufReader<R>>),
+    // boxed, as the decompressor holds its whole window
+    Deflated(Box<DeflateDecompressor<Take<&'a mut BufReader<R>>>>),
+}
+
+/// Reads the decompressed data of one zip entry, returned by
+/// `ZipArchive::open`.
+///
+/// The CRC-32 and size from the central directory are checked once the end
+/// of the entry is read, and a mismatch is reported as an error from `read`.
+pub struct ZipEntryReader<'a, R: Read> {
+    source: EntrySource<'a, R>,
+    crc: Crc32,
+    size: u64,
+    expected_crc: u32,
+    expected_size: u64,
+    finished: bool,
+}
+
+impl<'a, R: Read> ZipEntryReader<'a, R> {
+    fn check(&self) -> io::Result<()> {
+        if self.size != self.expected_size {
+            return Err(ZipError::SizeMismatch {
+                expected: self.expected_size,
+                computed: self.size,
+            }.into());
+        }
+
+        let computed = self.crc.value();
+        if computed != self.expected_crc {
+            return Err(ZipError::ChecksumMismatch {
+                expected: self.expected_crc,
+                computed,
+            }.into());
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, R: Read> Read for ZipEntryReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let n = match self.source {
+            EntrySource::Stored(ref mut reader) => reader.read(buf)?,
+            EntrySource::Deflated(ref mut decompressor) => decompressor.read(buf)?,
+        };
+
+        self.crc.update(&buf[..n]);
+        self.size += n as u64;
+        if n == 0 {
+            self.check()?;
+            self.finished = true;
+        }
+
+        Ok(n)
+    }
+}
+
+mod tests {
+    // made by Python's zipfile module
+    #[cfg(test)]
+    const SAMPLE: [u8; 319] = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2d, 0x79,
+        0x6e, 0x4e, 0x2d, 0x3b, 0x08, 0xaf, 0x0c, 0x00, 0x00, 0x00, 0x0c, 0x00,
+        0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2e,
+        0x74, 0x78, 0x74, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72,
+        0x6c, 0x64, 0x0a, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0x83, 0x18, 0x22, 0x50, 0xf4, 0xf7, 0x12, 0x1f, 0x4f, 0x00, 0x00,
+        0x00, 0x0e, 0x01, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x64, 0x69, 0x72,
+        0x2f, 0x70, 0x6f, 0x65, 0x6d, 0x2e, 0x74, 0x78, 0x74, 0xd5, 0xcc, 0xd1,
+        0x0d, 0x80, 0x20, 0x0c, 0x45, 0xd1, 0x55, 0xde, 0x04, 0xee, 0x54, 0xe0,
+        0xc5, 0x36, 0x2a, 0x90, 0x16, 0x42, 0xe2, 0xf4, 0xea, 0x18, 0xfe, 0xde,
+        0x9c, 0xdc, 0x68, 0xb8, 0x66, 0x56, 0x14, 0x76, 0xd6, 0x12, 0x98, 0xbd,
+        0x55, 0x08, 0x9c, 0x05, 0x4b, 0xc9, 0x13, 0x49, 0xdc, 0xdb, 0xc2, 0x7e,
+        0xca, 0xfd, 0x35, 0x1b, 0x0a, 0x17, 0xab, 0x58, 0x32, 0xe8, 0x48, 0x0c,
+        0x2b, 0xc4, 0x50, 0xbe, 0xdc, 0x06, 0x91, 0xd5, 0xf2, 0xc1, 0x1a, 0x1b,
+        0xe2, 0x87, 0xe7, 0x07, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x2d, 0x79, 0x6e, 0x4e, 0x2d, 0x3b, 0x08, 0xaf,
+        0x0c, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x50,
+        0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x83,
+        0x18, 0x22, 0x50, 0xf4, 0xf7, 0x12, 0x1f, 0x4f, 0x00, 0x00, 0x00, 0x0e,
+        0x01, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x01, 0x33, 0x00, 0x00, 0x00, 0x64, 0x69, 0x72,
+        0x2f, 0x70, 0x6f, 0x65, 0x6d, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x05,
+        0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x71, 0x00, 0x00,
+        0x00, 0xac, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x74, 0x65, 0x73, 0x74, 0x20,
+        0x61, 0x72, 0x63, 0x68, 0x69, 0x76, 0x65,
+    ];
+
+    #[cfg(test)]
+    const POEM: &[u8] = b"so much depends upon a red wheel barrow \
+        glazed with rain water beside the white chickens. ";
+
+    #[cfg(test)]
+    fn zip_error(e: std::io::Error) -> super::ZipError {
+        *e.get_ref().unwrap().downcast_ref::<super::ZipError>().unwrap()
+    }
+
+    #[test]
+    fn test_read_sample() {
+        use std::io::{Cursor, Read};
+        use crate::io::{ZipArchive, CompressionMethod, DosDateTime};
+
+        let mut archive = ZipArchive::new(Cursor::new(&SAMPLE[..])).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.comment(), b"test archive");
+
+        let hello = &archive.entries()[0];
+        assert_eq!(hello.name, "hello.txt");
+        assert_eq!(hello.method, CompressionMethod::Stored);
+        assert_eq!(hello.crc32, 0xaf083b2d);
+        assert_eq!(hello.uncompressed_size, 12);
+        assert_eq!(hello.modified, DosDateTime {
+            year: 2019, month: 3, day: 14, hour: 15, minute: 9, second: 26,
+        });
+
+        let poem = &archive.entries()[1];
+        assert_eq!(poem.name, "dir/poem.txt");
+        assert_eq!(poem.method, CompressionMethod::Deflated);
+        assert_eq!(poem.compressed_size, 0x4f);
+        assert_eq!(poem.uncompressed_size, 0x10e);
+        assert!(!poem.is_dir());
+
+        let mut output = Vec::new();
+        archive.open(1).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, POEM.repeat(3));
+
+        output.clear();
+        let index = archive.index_of("hello.txt").unwrap();
+        archive.open(index).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"hello world\n");
+        assert_eq!(archive.index_of("missing.txt"), None);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        use std::io::{Cursor, Read};
+        use crate::io::{ZipArchive, ZipError};
+
+        let mut corrupted = SAMPLE;
+        corrupted[0x27] = b'j';
+        let mut archive = ZipArchive::new(Cursor::new(&corrupted[..])).unwrap();
+        let mut output = Vec::new();
+        let e = archive.open(0).unwrap().read_to_end(&mut output).unwrap_err();
+        assert_eq!(zip_error(e), ZipError::ChecksumMismatch {
+            expected: 0xaf083b2d,
+            computed: crate::algorithms::crc32(b"jello world\n"),
+        });
+
+        let e = ZipArchive::new(Cursor::new(&SAMPLE[..200])).err().unwrap();
+        assert_eq!(zip_error(e), ZipError::EndOfCentralDirectoryNotFound);
+    }
+
+    #[test]
+    fn test_zip64_records() {
+        use std::io::{Cursor, Read};
+        use crate::io::{ZipArchive, write_u16_le, write_u32_le, write_u64_le};
+
+        // a stored entry whose sizes and offset are all in a ZIP64 extra
+        // field, found through ZIP64 end of central directory records
+        let data = b"hello world\n";
+        let mut zip = Vec::new();
+        write_u32_le(&mut zip, 0x04034b50).unwrap();
+        zip.extend_from_slice(&[45, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        write_u32_le(&mut zip, 0xaf083b2d).unwrap();
+        write_u32_le(&mut zip, 0xffffffff).unwrap();
+        write_u32_le(&mut zip, 0xffffffff).unwrap();
+        write_u16_le(&mut zip, 1).unwrap();
+        write_u16_le(&mut zip, 20).unwrap();
+        zip.push(b'a');
+        write_u16_le(&mut zip, 1).unwrap();
+        write_u16_le(&mut zip, 16).unwrap();
+        write_u64_le(&mut zip, data.len() as u64).unwrap();
+        write_u64_le(&mut zip, data.len() as u64).unwrap();
+        zip.extend_from_slice(data);
+
+        let directory_offset = zip.len() as u64;
+        write_u32_le(&mut zip, 0x02014b50).unwrap();
+        zip.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        write_u32_le(&mut zip, 0xaf083b2d).unwrap();
+        write_u32_le(&mut zip, 0xffffffff).unwrap();
+        write_u32_le(&mut zip, 0xffffffff).unwrap();
+        write_u16_le(&mut zip, 1).unwrap();
+        write_u16_le(&mut zip, 28).unwrap();
+        zip.extend_from_slice(&[0; 10]);
+        write_u32_le(&mut zip, 0xffffffff).unwrap();
+        zip.push(b'a');
+        write_u16_le(&mut zip, 1).unwrap();
+        write_u16_le(&mut zip, 24).unwrap();
+        write_u64_le(&mut zip, data.len() as u64).unwrap();
+        write_u64_le(&mut zip, data.len() as u64).unwrap();
+        write_u64_le(&mut zip, 0).unwrap();
+        let directory_size = zip.len() as u64 - directory_offset;
+
+        let record_offset = zip.len() as u64;
+        write_u32_le(&mut zip, 0x06064b50).unwrap();
+        write_u64_le(&mut zip, 44).unwrap();
+        zip.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        write_u64_le(&mut zip, 1).unwrap();
+        write_u64_le(&mut zip, 1).unwrap();
+        write_u64_le(&mut zip, directory_size).unwrap();
+        write_u64_le(&mut zip, directory_offset).unwrap();
+
+        write_u32_le(&mut zip, 0x07064b50).unwrap();
+        write_u32_le(&mut zip, 0).unwrap();
+        write_u64_le(&mut zip, record_offset).unwrap();
+        write_u32_le(&mut zip, 1).unwrap();
+
+        write_u32_le(&mut zip, 0x06054b50).unwrap();
+        zip.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        zip.extend_from_slice(&[0xff; 8]);
+        write_u16_le(&mut zip, 0).unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive.entries()[0].name, "a");
+        assert_eq!(archive.entries()[0].uncompressed_size, 12);
+
+        let mut output = Vec::new();
+        archive.open(0).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[cfg(test)]
+    struct CountingReader<R> {
+        reader: R,
+        reads: usize,
+    }
+
+    #[cfg(test)]
+    impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reads += 1;
+            self.reader.read(buf)
+        }
+    }
+
+    #[cfg(test)]
+    impl<R: std::io::Seek> std::io::Seek for CountingReader<R> {
+        fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.reader.seek(position)
+        }
+    }
+
+    #[test]
+    fn test_buffered() {
+        use std::io::{Cursor, Read};
+        use crate::io::ZipArchive;
+
+        // the deflated entry's 79 bytes shouldn't take a read each
+        let reader = CountingReader { reader: Cursor::new(&SAMPLE[..]), reads: 0 };
+        let mut archive = ZipArchive::new(reader).unwrap();
+        let mut output = Vec::new();
+        archive.open(1).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output.len(), 0x10e);
+        assert!(archive.into_inner().reads < 10);
+    }
+}
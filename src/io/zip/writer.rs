@@ -0,0 +1,439 @@
+use std::io::{self, Write, Seek, SeekFrom};
+use crate::algorithms::Crc32;
+use crate::io::DeflateCompressor;
+use crate::io::{write_u16_le, write_u32_le, write_u64_le};
+use super::*;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const FLAG_UTF8: u16 = 0x0800;
+
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+
+const DOS_DIRECTORY_ATTRIBUTE: u32 = 0x10;
+
+// local headers always reserve room for a ZIP64 extra field, since an
+// entry's size isn't known until its data has been written
+const LOCAL_ZIP64_EXTRA_SIZE: u16 = 20;
+
+// offset of the compressed size in a local header
+const LOCAL_SIZES_OFFSET: u64 = 18;
+
+/// Options for an entry added to a `ZipWriter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOptions {
+    /// `Stored` or `Deflated`. Defaults to `Deflated`.
+    pub method: CompressionMethod,
+    pub modified: DosDateTime,
+}
+
+impl Default for FileOptions {
+    fn default() -> FileOptions {
+        FileOptions {
+            method: CompressionMethod::Deflated,
+            modified: DosDateTime::default(),
+        }
+    }
+}
+
+struct EntryRecord {
+    name: Vec<u8>,
+    flags: u16,
+    method: CompressionMethod,
+    modified: DosDateTime,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    header_offset: u64,
+    external_attributes: u32,
+}
+
+impl EntryRecord {
+    fn needs_zip64(&self) -> bool {
+        self.compressed_size >= 0xffffffff
+            || self.uncompressed_size >= 0xffffffff
+            || self.header_offset >= 0xffffffff
+    }
+}
+
+enum Sink<W: Write> {
+    Idle(W),
+    Stored(W),
+    Deflated(DeflateCompressor<W>),
+    Closed,
+}
+
+/// Writes a zip archive, switching to ZIP64 records when an entry or the
+/// archive grows past 4 GiB, or there are more than 65535 entries.
+///
+/// Entries are streamed: each is started with `start_file`, and its data is
+/// written through the `Write` implementation. The CRC-32 and sizes follow
+/// the data in a data descriptor. `finish()` must be called to write the
+/// central directory.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{ZipArchive, ZipWriter, FileOptions};
+/// # use std::io::{Cursor, Read, Write};
+/// let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+/// writer.start_file("hello.txt", FileOptions::default()).unwrap();
+/// writer.write_all(b"hello world\n").unwrap();
+/// let zip = writer.finish().unwrap();
+///
+/// let mut archive = ZipArchive::new(zip).unwrap();
+/// let mut output = String::new();
+/// archive.open(0).unwrap().read_to_string(&mut output).unwrap();
+/// assert_eq!(output, "hello world\n");
+/// ```
+pub struct ZipWriter<W: Write + Seek> {
+    sink: Sink<W>,
+    entries: Vec<EntryRecord>,
+    crc: Crc32,
+    comment: Vec<u8>,
+}
+
+impl<W: Write + Seek> ZipWriter<W> {
+    /// Entry offsets are taken from the writer's position, so the archive
+    /// may be appended to other data.
+    pub fn new(writer: W) -> ZipWriter<W> {
+        ZipWriter {
+            sink: Sink::Idle(writer),
+            entries: Vec::new(),
+            crc: Crc32::new(),
+            comment: Vec::new(),
+        }
+    }
+
+    /// Sets the archive comment, which is truncated to 65535 bytes.
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        let length = std::cmp::min(comment.len(), 0xffff);
+        self.comment = comment[..length].to_vec();
+    }
+
+    /// Finishes the current entry, if any, and starts a new one named
+    /// `name`. Data for the entry is written through `write`.
+    pub fn start_file(&mut self, name: &str, options: FileOptions) -> io::Result<()> {
+        match options.method {
+            CompressionMethod::Stored | CompressionMethod::Deflated => {},
+            method => return Err(ZipError::UnsupportedMethod(method.to_u16()).into()),
+        }
+
+        self.start_entry(name, options, 0)?;
+        let writer = self.take_writer()?;
+        self.sink = match options.method {
+            CompressionMethod::Deflated => Sink::Deflated(DeflateCompressor::new(writer)),
+            _ => Sink::Stored(writer),
+        };
+        Ok(())
+    }
+
+    /// Adds an empty directory entry. A '/' is appended to `name` if it
+    /// doesn't already end in one.
+    pub fn add_directory(&mut self, name: &str, options: FileOptions) -> io::Result<()> {
+        let mut name = name.to_string();
+        if !name.ends_with('/') {
+            name.push('/');
+        }
+
+        let options = FileOptions { method: CompressionMethod::Stored, ..options };
+        self.start_entry(&name, options, DOS_DIRECTORY_ATTRIBUTE)?;
+        let writer = self.take_writer()?;
+        self.sink = Sink::Stored(writer);
+        self.finish_entry()
+    }
+
+    /// Writes the central directory and returns the underlying `Write`
+    /// object.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.finish_entry()?;
+        let mut writer = self.take_writer()?;
+        write_central_directory(&mut writer, &self.entries, &self.comment)?;
+        Ok(writer)
+    }
+
+    fn take_writer(&mut self) -> io::Result<W> {
+        match std::mem::replace(&mut self.sink, Sink::Closed) {
+            Sink::Idle(writer) => Ok(writer),
+            _ => Err(io::Error::other("zip writer is not idle")),
+        }
+    }
+
+    /// Finishes any current entry and writes the local header for the next.
+    fn start_entry(&mut self, name: &str, options: FileOptions, external_attributes: u32)
+    -> io::Result<()> {
+        self.finish_entry()?;
+        let mut writer = self.take_writer()?;
+
+        let name = name.as_bytes().to_vec();
+        let mut flags = FLAG_DATA_DESCRIPTOR;
+        if !name.is_ascii() {
+            flags |= FLAG_UTF8;
+        }
+
+        let entry = EntryRecord {
+            name,
+            flags,
+            method: options.method,
+            modified: options.modified,
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            header_offset: writer.stream_position()?,
+            external_attributes,
+        };
+
+        let result = write_local_header(&mut writer, &entry);
+        self.sink = Sink::Idle(writer);
+        result?;
+
+        self.entries.push(entry);
+        self.crc = Crc32::new();
+        Ok(())
+    }
+
+    /// Writes the data descriptor for the current entry, if there is one.
+    fn finish_entry(&mut self) -> io::Result<()> {
+        let mut writer = match std::mem::replace(&mut self.sink, Sink::Closed) {
+            Sink::Idle(writer) => {
+                self.sink = Sink::Idle(writer);
+                return Ok(());
+            },
+            Sink::Stored(writer) => writer,
+            Sink::Deflated(compressor) => compressor.finish()?,
+            Sink::Closed => return Err(io::Error::other("zip writer is closed")),
+        };
+
+        let entry = self.entries.last_mut().unwrap();
+        let data_start = entry.header_offset + 30
+            + entry.name.len() as u64 + LOCAL_ZIP64_EXTRA_SIZE as u64;
+        let end = writer.stream_position()?;
+        entry.crc32 = self.crc.value();
+        entry.compressed_size = end - data_start;
+
+        // the local header's sizes must be 0xffffffff when the data
+        // descriptor holds eight byte sizes
+        let zip64 = entry.compressed_size >= 0xffffffff || entry.uncompressed_size >= 0xffffffff;
+        if zip64 {
+            writer.seek(SeekFrom::Start(entry.header_offset + LOCAL_SIZES_OFFSET))?;
+            write_u32_le(&mut writer, 0xffffffff)?;
+            write_u32_le(&mut writer, 0xffffffff)?;
+            writer.seek(SeekFrom::Start(end))?;
+        }
+
+        write_u32_le(&mut writer, DATA_DESCRIPTOR_SIGNATURE)?;
+        write_u32_le(&mut writer, entry.crc32)?;
+        if zip64 {
+            write_u64_le(&mut writer, entry.compressed_size)?;
+            write_u64_le(&mut writer, entry.uncompressed_size)?;
+        } else {
+            write_u32_le(&mut writer, entry.compressed_size as u32)?;
+            write_u32_le(&mut writer, entry.uncompressed_size as u32)?;
+        }
+
+        self.sink = Sink::Idle(writer);
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Write for ZipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match self.sink {
+            Sink::Stored(ref mut writer) => writer.write(buf)?,
+            Sink::Deflated(ref mut compressor) => compressor.write(buf)?,
+            Sink::Idle(_) | Sink::Closed => {
+                return Err(io::Error::other("no zip entry has been started"));
+            },
+        };
+
+        self.crc.update(&buf[..n]);
+        self.entries.last_mut().unwrap().uncompressed_size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sink {
+            Sink::Idle(ref mut writer) | Sink::Stored(ref mut writer) => writer.flush(),
+            Sink::Deflated(ref mut compressor) => compressor.flush(),
+            Sink::Closed => Ok(()),
+        }
+    }
+}
+
+fn write_local_header<W: Write>(writer: &mut W, entry: &EntryRecord) -> io::Result<()> {
+    let (date, time) = entry.modified.to_dos();
+    write_u32_le(writer, LOCAL_HEADER_SIGNATURE)?;
+    write_u16_le(writer, VERSION_ZIP64)?;
+    write_u16_le(writer, entry.flags)?;
+    write_u16_le(writer, entry.method.to_u16())?;
+    write_u16_le(writer, time)?;
+    write_u16_le(writer, date)?;
+
+    // the CRC-32 and sizes are in the data descriptor
+    write_u32_le(writer, 0)?;
+    write_u32_le(writer, 0)?;
+    write_u32_le(writer, 0)?;
+
+    write_u16_le(writer, entry.name.len() as u16)?;
+    write_u16_le(writer, LOCAL_ZIP64_EXTRA_SIZE)?;
+    writer.write_all(&entry.name)?;
+
+    write_u16_le(writer, ZIP64_EXTRA_ID)?;
+    write_u16_le(writer, 16)?;
+    write_u64_le(writer, 0)?;
+    write_u64_le(writer, 0)
+}
+
+fn write_central_header<W: Write>(writer: &mut W, entry: &EntryRecord) -> io::Result<()> {
+    // only the values which don't fit go in the ZIP64 extra field
+    let mut zip64_extra = Vec::new();
+    let mut narrow = |value: u64| {
+        if value >= 0xffffffff {
+            write_u64_le(&mut zip64_extra, value).unwrap();
+            0xffffffff
+        } else {
+            value as u32
+        }
+    };
+    let uncompressed_size = narrow(entry.uncompressed_size);
+    let compressed_size = narrow(entry.compressed_size);
+    let header_offset = narrow(entry.header_offset);
+
+    let version = if entry.needs_zip64() { VERSION_ZIP64 } else { VERSION_DEFAULT };
+    let extra_length = if zip64_extra.is_empty() { 0 } else { 4 + zip64_extra.len() };
+
+    let (date, time) = entry.modified.to_dos();
+    write_u32_le(writer, CENTRAL_HEADER_SIGNATURE)?;
+    write_u16_le(writer, version)?;
+    write_u16_le(writer, version)?;
+    write_u16_le(writer, entry.flags)?;
+    write_u16_le(writer, entry.method.to_u16())?;
+    write_u16_le(writer, time)?;
+    write_u16_le(writer, date)?;
+    write_u32_le(writer, entry.crc32)?;
+    write_u32_le(writer, compressed_size)?;
+    write_u32_le(writer, uncompressed_size)?;
+    write_u16_le(writer, entry.name.len() as u16)?;
+    write_u16_le(writer, extra_length as u16)?;
+    write_u16_le(writer, 0)?; // comment length
+    write_u16_le(writer, 0)?; // disk number
+    write_u16_le(writer, 0)?; // internal attributes
+    write_u32_le(writer, entry.external_attributes)?;
+    write_u32_le(writer, header_offset)?;
+    writer.write_all(&entry.name)?;
+
+    if !zip64_extra.is_empty() {
+        write_u16_le(writer, ZIP64_EXTRA_ID)?;
+        write_u16_le(writer, zip64_extra.len() as u16)?;
+        writer.write_all(&zip64_extra)?;
+    }
+    Ok(())
+}
+
+fn write_central_directory<W: Write + Seek>(writer: &mut W, entries: &[EntryRecord], comment: &[u8])
+-> io::Result<()> {
+    let offset = writer.stream_position()?;
+    for entry in entries {
+        write_central_header(writer, entry)?;
+    }
+    let size = writer.stream_position()? - offset;
+    let count = entries.len() as u64;
+
+    let zip64 = count > 0xffff || size >= 0xffffffff || offset >= 0xffffffff;
+    if zip64 {
+        let record_offset = writer.stream_position()?;
+        write_u32_le(writer, ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE)?;
+        write_u64_le(writer, 44)?; // size of the rest of the record
+        write_u16_le(writer, VERSION_ZIP64)?;
+        write_u16_le(writer, VERSION_ZIP64)?;
+        write_u32_le(writer, 0)?; // disk number
+        write_u32_le(writer, 0)?; // disk with the central directory
+        write_u64_le(writer, count)?;
+        write_u64_le(writer, count)?;
+        write_u64_le(writer, size)?;
+        write_u64_le(writer, offset)?;
+
+        write_u32_le(writer, ZIP64_LOCATOR_SIGNATURE)?;
+        write_u32_le(writer, 0)?; // disk with the ZIP64 record
+        write_u64_le(writer, record_offset)?;
+        write_u32_le(writer, 1)?; // total disks
+    }
+
+    let count = std::cmp::min(count, 0xffff) as u16;
+    let size = std::cmp::min(size, 0xffffffff) as u32;
+    let offset = std::cmp::min(offset, 0xffffffff) as u32;
+    write_u32_le(writer, END_OF_CENTRAL_DIRECTORY_SIGNATURE)?;
+    write_u16_le(writer, 0)?; // disk number
+    write_u16_le(writer, 0)?; // disk with the central directory
+    write_u16_le(writer, count)?;
+    write_u16_le(writer, count)?;
+    write_u32_le(writer, size)?;
+    write_u32_le(writer, offset)?;
+    write_u16_le(writer, comment.len() as u16)?;
+    writer.write_all(comment)
+}
+
+mod tests {
+    #[test]
+    fn test_round_trip() {
+        use std::io::{Cursor, Read, Write};
+        use crate::io::{ZipArchive, ZipWriter, FileOptions, CompressionMethod, DosDateTime};
+
+        let modified = DosDateTime { year: 2021, month: 6, day: 30, hour: 23, minute: 59, second: 58 };
+        let text: Vec<u8> = (0..100_000u64).map(|i| b"abcdefgh"[(i * i % 7) as usize]).collect();
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.set_comment(b"artifacts");
+        writer.start_file("stored.bin", FileOptions {
+            method: CompressionMethod::Stored,
+            modified,
+        }).unwrap();
+        writer.write_all(&text[..1000]).unwrap();
+        writer.add_directory("empty", FileOptions::default()).unwrap();
+        writer.start_file("dir/d\u{e9}j\u{e0} vu.txt", FileOptions::default()).unwrap();
+        for piece in text.chunks(4096) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.start_file("empty.txt", FileOptions::default()).unwrap();
+        let zip = writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(zip).unwrap();
+        assert_eq!(archive.comment(), b"artifacts");
+        let names: Vec<&str> = archive.entries().iter().map(|e| &e.name[..]).collect();
+        assert_eq!(names, ["stored.bin", "empty/", "dir/d\u{e9}j\u{e0} vu.txt", "empty.txt"]);
+        assert_eq!(archive.entries()[0].modified, modified);
+        assert_eq!(archive.entries()[0].compressed_size, 1000);
+        assert!(archive.entries()[1].is_dir());
+        assert!(archive.entries()[2].compressed_size < text.len() as u64 / 2);
+
+        let expected: [&[u8]; 4] = [&text[..1000], b"", &text, b""];
+        for (index, expected) in expected.iter().enumerate() {
+            let mut output = Vec::new();
+            archive.open(index).unwrap().read_to_end(&mut output).unwrap();
+            assert_eq!(&output[..], *expected);
+        }
+    }
+
+    #[test]
+    fn test_many_entries() {
+        use std::io::{Cursor, Read, Write};
+        use crate::io::{ZipArchive, ZipWriter, FileOptions, CompressionMethod};
+
+        // past 65535 entries, the count is only in the ZIP64 record
+        let options = FileOptions { method: CompressionMethod::Stored, ..FileOptions::default() };
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for i in 0..70_000u32 {
+            writer.start_file(&format!("{}", i), options).unwrap();
+            writer.write_all(&i.to_le_bytes()).unwrap();
+        }
+        let zip = writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(zip).unwrap();
+        assert_eq!(archive.len(), 70_000);
+        let index = archive.index_of("69999").unwrap();
+        let mut output = Vec::new();
+        archive.open(index).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, 69_999u32.to_le_bytes());
+    }
+}
@@ -0,0 +1,390 @@
+use std::io::{self, Read, Write};
+use crate::algorithms::Adler32;
+use crate::io::{DeflateCompressor, DeflateDecompressor, DeflateOptions, InflateOptions, Strategy};
+use crate::io::{read_u8, read_u32_be, write_u8, write_u32_be};
+
+const CM_DEFLATE: u8 = 8;
+const MAX_CINFO: u8 = 7;
+const FDICT: u8 = 0x20;
+
+/// Decompresses a zlib stream (RFC 1950): a deflate stream wrapped in a two
+/// byte header and followed by an Adler-32 checksum of the decompressed data.
+///
+/// The checksum is verified once the end of the stream is read, and a
+/// mismatch is reported as an error from `read`.
+///
+/// # Example
+/// ```
+/// # use stdex::io::ZlibDecoder;
+/// # use std::io::Read;
+/// let compressed = [
+///     0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07,
+///     0x00, 0x06, 0x2c, 0x02, 0x15,
+/// ];
+/// let mut decoder = ZlibDecoder::new(&compressed[..]).unwrap();
+/// let mut output = String::new();
+/// decoder.read_to_string(&mut output).unwrap();
+/// assert_eq!(output, "hello");
+/// ```
+pub struct ZlibDecoder<R: Read> {
+    decompressor: DeflateDecompressor<R>,
+    adler: Adler32,
+    finished: bool,
+}
+
+impl<R: Read> ZlibDecoder<R> {
+    /// Reads the zlib header, then the start of the deflate stream.
+    ///
+    /// Streams which need a preset dictionary fail with
+    /// `ZlibError::DictionaryRequired`.
+    pub fn new(reader: R) -> io::Result<ZlibDecoder<R>> {
+        ZlibDecoder::with_options(reader, InflateOptions::default())
+    }
+
+    /// Like `new`, but fails with `InflateErrorKind::LimitExceeded` if the
+    /// data expands past the limits in `options`.
+    pub fn with_options(mut reader: R, options: InflateOptions) -> io::Result<ZlibDecoder<R>> {
+        if let Some(dictionary_id) = read_header(&mut reader)? {
+            return Err(ZlibError::DictionaryRequired(dictionary_id).into());
+        }
+
+        ZlibDecoder::from_decompressor(DeflateDecompressor::with_options(reader, options)?)
+    }
+
+    /// Like `new`, but streams which need a preset dictionary are
+    /// decompressed with `dictionary`. Its Adler-32 checksum must match the
+    /// one in the header, or this fails with `ZlibError::WrongDictionary`.
+    ///
+    /// Streams which don't need a dictionary are decompressed as usual.
+    pub fn with_dictionary(mut reader: R, dictionary: &[u8]) -> io::Result<ZlibDecoder<R>> {
+        let decompressor = match read_header(&mut reader)? {
+            Some(expected) => {
+                let mut adler = Adler32::new();
+                adler.update(dictionary);
+                let computed = adler.value();
+                if expected != computed {
+                    return Err(ZlibError::WrongDictionary { expected, computed }.into());
+                }
+                DeflateDecompressor::with_dictionary(reader, dictionary)?
+            },
+            None => DeflateDecompressor::new(reader)?,
+        };
+
+        ZlibDecoder::from_decompressor(decompressor)
+    }
+
+    fn from_decompressor(decompressor: DeflateDecompressor<R>) -> io::Result<ZlibDecoder<R>> {
+        Ok(ZlibDecoder {
+            decompressor,
+            adler: Adler32::new(),
+            finished: false,
+        })
+    }
+
+    fn check_trailer(&mut self) -> io::Result<()> {
+        let expected = read_u32_be(self.decompressor.get_mut())?;
+        let computed = self.adler.value();
+        if expected != computed {
+            return Err(ZlibError::ChecksumMismatch { expected, computed }.into());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ZlibDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let n = self.decompressor.read(buf)?;
+        self.adler.update(&buf[..n]);
+        if n == 0 {
+            self.check_trailer()?;
+            self.finished = true;
+        }
+
+        Ok(n)
+    }
+}
+
+/// Reads a zlib header, returning the dictionary id if the FDICT flag is set.
+fn read_header<R: Read>(reader: &mut R) -> io::Result<Option<u32>> {
+    let cmf = read_u8(reader)?;
+    let flg = read_u8(reader)?;
+
+    if cmf & 0x0f != CM_DEFLATE {
+        return Err(ZlibError::UnsupportedMethod(cmf & 0x0f).into());
+    }
+
+    if cmf >> 4 > MAX_CINFO {
+        return Err(ZlibError::InvalidWindowSize(cmf >> 4).into());
+    }
+
+    if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err(ZlibError::HeaderCheckFailed.into());
+    }
+
+    if flg & FDICT != 0 {
+        Ok(Some(read_u32_be(reader)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns a zlib header for a 32 KiB window, with FCHECK filled in.
+fn header(flevel: u8, dictionary_id: Option<u32>) -> (u8, u8) {
+    let cmf = MAX_CINFO << 4 | CM_DEFLATE;
+    let mut flg = flevel << 6;
+    if dictionary_id.is_some() {
+        flg |= FDICT;
+    }
+    flg += (31 - (cmf as u16 * 256 + flg as u16) % 31) as u8 % 31;
+    (cmf, flg)
+}
+
+/// The FLEVEL header field for `options`, as zlib sets it.
+fn flevel(options: DeflateOptions) -> u8 {
+    match (options.strategy, options.level) {
+        (Strategy::HuffmanOnly, _) | (Strategy::Rle, _) | (Strategy::Fixed, _) => 0,
+        (_, 0..=1) => 0,
+        (_, 2..=5) => 1,
+        (_, 6) => 2,
+        _ => 3,
+    }
+}
+
+/// Compresses data into a zlib stream (RFC 1950).
+///
+/// The header is written when the encoder is created. `finish()` must be
+/// called to write the final deflate block and the Adler-32 trailer, and to
+/// get back the underlying writer.
+///
+/// # Example
+/// ```
+/// # use stdex::io::{ZlibDecoder, ZlibEncoder};
+/// # use std::io::{Read, Write};
+/// let mut encoder = ZlibEncoder::new(Vec::new()).unwrap();
+/// encoder.write_all(b"hello").unwrap();
+/// let compressed = encoder.finish().unwrap();
+/// assert_eq!(&compressed[..2], &[0x78, 0x9c]);
+///
+/// let mut decoder = ZlibDecoder::new(&compressed[..]).unwrap();
+/// let mut output = String::new();
+/// decoder.read_to_string(&mut output).unwrap();
+/// assert_eq!(output, "hello");
+/// ```
+pub struct ZlibEncoder<W: Write> {
+    compressor: DeflateCompressor<W>,
+    adler: Adler32,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(writer: W) -> io::Result<ZlibEncoder<W>> {
+        ZlibEncoder::with_options(writer, DeflateOptions::default())
+    }
+
+    /// Creates an encoder which compresses with the given level and
+    /// strategy, which are also recorded in the header's FLEVEL field.
+    pub fn with_options(mut writer: W, options: DeflateOptions) -> io::Result<ZlibEncoder<W>> {
+        let (cmf, flg) = header(flevel(options), None);
+        write_u8(&mut writer, cmf)?;
+        write_u8(&mut writer, flg)?;
+
+        Ok(ZlibEncoder {
+            compressor: DeflateCompressor::with_options(writer, options),
+            adler: Adler32::new(),
+        })
+    }
+
+    /// Creates an encoder which compresses with a preset dictionary. The
+    /// header records the dictionary's Adler-32 checksum, and the same
+    /// dictionary must be given to `ZlibDecoder::with_dictionary`.
+    pub fn with_dictionary(mut writer: W, dictionary: &[u8]) -> io::Result<ZlibEncoder<W>> {
+        let mut adler = Adler32::new();
+        adler.update(dictionary);
+        let dictionary_id = adler.value();
+
+        let (cmf, flg) = header(2, Some(dictionary_id));
+        write_u8(&mut writer, cmf)?;
+        write_u8(&mut writer, flg)?;
+        write_u32_be(&mut writer, dictionary_id)?;
+
+        Ok(ZlibEncoder {
+            compressor: DeflateCompressor::with_dictionary(writer, dictionary),
+            adler: Adler32::new(),
+        })
+    }
+
+    /// Finishes the deflate stream, writes the checksum, and returns the
+    /// underlying `Write` object.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self.compressor.finish()?;
+        write_u32_be(&mut writer, self.adler.value())?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.compressor.write(buf)?;
+        self.adler.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.compressor.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZlibError {
+    UnsupportedMethod(u8),
+    InvalidWindowSize(u8),
+    HeaderCheckFailed,
+    DictionaryRequired(u32),
+    WrongDictionary { expected: u32, computed: u32 },
+    ChecksumMismatch { expected: u32, computed: u32 },
+}
+
+impl std::fmt::Display for ZlibError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::ZlibError::*;
+        match self {
+            UnsupportedMethod(method) => write!(f, "Unsupported zlib compression method {}", method),
+            InvalidWindowSize(cinfo) => write!(f, "Invalid zlib window size (CINFO = {})", cinfo),
+            HeaderCheckFailed => write!(f, "zlib header check (FCHECK) failed"),
+            DictionaryRequired(id) => write!(f, "zlib stream requires preset dictionary {:08x}", id),
+            WrongDictionary { expected, computed } => write!(f,
+                "zlib stream requires preset dictionary {:08x}, but was given {:08x}", expected, computed),
+            ChecksumMismatch { expected, computed } => write!(f,
+                "zlib Adler-32 mismatch: stream says {:08x}, data is {:08x}", expected, computed),
+        }
+    }
+}
+
+impl std::error::Error for ZlibError {}
+
+impl From<ZlibError> for io::Error {
+    fn from(e: ZlibError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+mod tests {
+    #[cfg(test)]
+    fn zlib_error(e: std::io::Error) -> super::ZlibError {
+        *e.get_ref().unwrap().downcast_ref::<super::ZlibError>().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        use std::io::{Read, Write};
+        use crate::io::{ZlibDecoder, ZlibEncoder};
+
+        let input: Vec<u8> = (0..100_000u64).map(|i| (i * i % 251) as u8).collect();
+        let mut encoder = ZlibEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(&input).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder = ZlibDecoder::new(&compressed[..]).unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        use std::io::Read;
+        use crate::io::{ZlibDecoder, ZlibError};
+
+        let compressed = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07,
+            0x00, 0x06, 0x2c, 0x02, 0x16,
+        ];
+        let mut decoder = ZlibDecoder::new(&compressed[..]).unwrap();
+        let mut output = Vec::new();
+        let e = decoder.read_to_end(&mut output).unwrap_err();
+        assert_eq!(zlib_error(e), ZlibError::ChecksumMismatch {
+            expected: 0x062c0216,
+            computed: 0x062c0215,
+        });
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        use crate::io::{ZlibDecoder, ZlibError};
+
+        let e = ZlibDecoder::new(&[0x78, 0x9d, 0x03, 0x00][..]).err().unwrap();
+        assert_eq!(zlib_error(e), ZlibError::HeaderCheckFailed);
+
+        let e = ZlibDecoder::new(&[0x79, 0x9c, 0x03, 0x00][..]).err().unwrap();
+        assert_eq!(zlib_error(e), ZlibError::UnsupportedMethod(9));
+
+        // compressed by zlib with the dictionary "hello world"
+        let with_dictionary = [
+            0x78, 0xbb, 0x1a, 0x0b, 0x04, 0x5d, 0xcb, 0x40, 0x30, 0x15,
+            0xc0, 0x6c, 0x00, 0x3b, 0x20, 0x06, 0x91,
+        ];
+        let e = ZlibDecoder::new(&with_dictionary[..]).err().unwrap();
+        assert_eq!(zlib_error(e), ZlibError::DictionaryRequired(0x1a0b045d));
+    }
+
+    #[test]
+    fn test_dictionary() {
+        use std::io::{Read, Write};
+        use crate::io::{ZlibDecoder, ZlibEncoder, ZlibError};
+
+        // compressed by zlib with the dictionary "hello world"
+        let with_dictionary = [
+            0x78, 0xbb, 0x1a, 0x0b, 0x04, 0x5d, 0xcb, 0x40, 0x30, 0x15,
+            0xc0, 0x6c, 0x00, 0x3b, 0x20, 0x06, 0x91,
+        ];
+        let mut decoder = ZlibDecoder::with_dictionary(&with_dictionary[..], b"hello world").unwrap();
+        let mut output = String::new();
+        decoder.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "hello world hello");
+
+        let e = ZlibDecoder::with_dictionary(&with_dictionary[..], b"hello").err().unwrap();
+        assert_eq!(zlib_error(e), ZlibError::WrongDictionary {
+            expected: 0x1a0b045d,
+            computed: 0x062c0215,
+        });
+
+        let mut gen = crate::random::MT19937_32::from_seed(5);
+        let dictionary: Vec<u8> = (0..40_000).map(|_| gen.generate() as u8).collect();
+        let input = &dictionary[10_000..12_000];
+        let mut encoder = ZlibEncoder::with_dictionary(Vec::new(), &dictionary).unwrap();
+        encoder.write_all(input).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < 50);
+
+        let e = ZlibDecoder::new(&compressed[..]).err().unwrap();
+        assert!(matches!(zlib_error(e), ZlibError::DictionaryRequired(_)));
+
+        let mut decoder = ZlibDecoder::with_dictionary(&compressed[..], &dictionary).unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_levels() {
+        use std::io::{Read, Write};
+        use crate::io::{DeflateOptions, Strategy, ZlibDecoder, ZlibEncoder};
+
+        let levels = [(0, 0x01), (1, 0x01), (5, 0x5e), (6, 0x9c), (9, 0xda)];
+        for &(level, flg) in levels.iter() {
+            let options = DeflateOptions { level, strategy: Strategy::Default };
+            let mut encoder = ZlibEncoder::with_options(Vec::new(), options).unwrap();
+            encoder.write_all(b"hello hello hello").unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(&compressed[..2], &[0x78, flg]);
+
+            let mut decoder = ZlibDecoder::new(&compressed[..]).unwrap();
+            let mut output = String::new();
+            decoder.read_to_string(&mut output).unwrap();
+            assert_eq!(output, "hello hello hello");
+        }
+    }
+}